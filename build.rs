@@ -0,0 +1,74 @@
+use std::env;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout)
+        .ok()
+        .map(|s| s.trim().to_owned())
+}
+
+/// Bytes of `BuildInfo.git_hash` left after its NUL terminator.
+const GIT_HASH_LEN: usize = 31;
+
+/// Bytes of `BuildInfo.rustc_version` left after its NUL terminator.
+const RUSTC_VERSION_LEN: usize = 63;
+
+/// `git describe`, or when a long tag would overflow `BuildInfo.git_hash`, the bare abbreviated
+/// commit, keeping the `-dirty` suffix either way.
+fn git_hash() -> String {
+    const DIRTY: &str = "-dirty";
+    let Some(describe) = command_output("git", &["describe", "--always", "--dirty", "--abbrev=12"])
+    else {
+        return "unknown".to_owned();
+    };
+    if describe.len() <= GIT_HASH_LEN {
+        return describe;
+    }
+    let suffix = if describe.ends_with(DIRTY) { DIRTY } else { "" };
+    let commit = command_output("git", &["rev-parse", "--short=12", "HEAD"])
+        .unwrap_or_else(|| "unknown".to_owned());
+    format!("{commit}{suffix}")
+}
+
+/// `rustc --version`, cut on a char boundary to fit `BuildInfo.rustc_version`, since distro
+/// builds can append long suffixes.
+fn rustc_version() -> String {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    let mut version =
+        command_output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_owned());
+    let mut len = version.len().min(RUSTC_VERSION_LEN);
+    while !version.is_char_boundary(len) {
+        len -= 1;
+    }
+    version.truncate(len);
+    version
+}
+
+fn main() {
+    let git_hash = git_hash();
+
+    let rustc_version = rustc_version();
+
+    // Honour SOURCE_DATE_EPOCH so reproducible builds stay reproducible.
+    let timestamp = env::var("SOURCE_DATE_EPOCH").unwrap_or_else(|_| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+            .to_string()
+    });
+
+    println!("cargo:rustc-env=SOUL_BUILD_GIT_HASH={git_hash}");
+    println!("cargo:rustc-env=SOUL_BUILD_RUSTC={rustc_version}");
+    println!("cargo:rustc-env=SOUL_BUILD_TIMESTAMP={timestamp}");
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
@@ -0,0 +1,146 @@
+//! Self-test results as postcard bytes, enabled by the `postcard` feature.
+//!
+//! The fixed [`SelfTestResult`] always reserves every value slot and the whole message; a
+//! [`Report`] carries only the values the test recorded and the message up to its NUL, so the
+//! layout can grow without hosts misreading old offsets. The algorithm encodes each result into
+//! the `SelfTestEncoded` mailbox next to `SelfTestMailbox`.
+
+use serde::Serialize;
+
+use crate::{SelfTestResult, MAX_VALUES, MESSAGE_LEN};
+
+/// Worst-case encoding of a [`Report`]: five varints of up to 5 bytes each (the three words and
+/// both lengths), every value slot at 5 bytes and a full message, rounded up to words.
+pub const ENCODED_CAPACITY: usize = (5 * 5 + MAX_VALUES * 5 + MESSAGE_LEN).next_multiple_of(4);
+
+/// The fields of a [`SelfTestResult`] that are valid, in the order postcard encodes them.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Report<'a> {
+    pub id: u32,
+    pub status: u32,
+    pub duration_us: u32,
+    pub values: &'a [u32],
+    pub message: &'a str,
+}
+
+/// [`Report`] decoded on the host, owning what the borrowed form points into.
+#[cfg(feature = "std")]
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OwnedReport {
+    pub id: u32,
+    pub status: u32,
+    pub duration_us: u32,
+    pub values: Vec<u32>,
+    pub message: String,
+}
+
+impl SelfTestResult {
+    /// The valid part of the block, borrowed for encoding.
+    pub fn report(&self) -> Report<'_> {
+        Report {
+            id: self.id,
+            status: self.status,
+            duration_us: self.duration_us,
+            values: self.values(),
+            message: self.message_str(),
+        }
+    }
+}
+
+/// The `SelfTestEncoded` mailbox: a [`Report`] of the last result as postcard bytes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EncodedResult {
+    /// Number of valid bytes in `data`, zero if encoding failed.
+    pub len: u32,
+    pub data: [u8; ENCODED_CAPACITY],
+}
+
+impl EncodedResult {
+    pub const EMPTY: Self = Self {
+        len: 0,
+        data: [0; ENCODED_CAPACITY],
+    };
+
+    /// Replaces the contents with the encoding of `result`.
+    pub fn encode(&mut self, result: &SelfTestResult) {
+        self.len = match postcard::to_slice(&result.report(), &mut self.data) {
+            Ok(bytes) => bytes.len() as u32,
+            Err(_) => 0,
+        };
+    }
+}
+
+/// Decodes a `SelfTestEncoded` mailbox read back as raw bytes, `None` if it is empty or
+/// malformed.
+#[cfg(feature = "std")]
+pub fn decode(mailbox: &[u8]) -> Option<OwnedReport> {
+    let len = crate::word(mailbox, 0)? as usize;
+    postcard::from_bytes(mailbox.get(4..4 + len)?).ok()
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    fn mailbox_bytes(encoded: &EncodedResult) -> Vec<u8> {
+        let mut bytes = encoded.len.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&encoded.data);
+        bytes
+    }
+
+    #[test]
+    fn report_round_trips_through_the_mailbox() {
+        let mut result = SelfTestResult::EMPTY;
+        result.start(0x0403);
+        result.value(868_000_000);
+        result.value(-92i32 as u32);
+        result.message(format_args!("RSSI avg {} dBm", -92));
+        result.finish(0, 2_000_000);
+
+        let mut encoded = EncodedResult::EMPTY;
+        encoded.encode(&result);
+        assert_ne!(encoded.len, 0);
+        let report = decode(&mailbox_bytes(&encoded)).unwrap();
+        assert_eq!(
+            report,
+            OwnedReport {
+                id: 0x0403,
+                status: 0,
+                duration_us: 2_000_000,
+                values: vec![868_000_000, -92i32 as u32],
+                message: "RSSI avg -92 dBm".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn worst_case_result_fits_capacity() {
+        let mut result = SelfTestResult::EMPTY;
+        result.start(u32::MAX);
+        for _ in 0..MAX_VALUES {
+            result.value(u32::MAX);
+        }
+        result.message(format_args!("{:#<1$}", "", MESSAGE_LEN));
+        result.finish(u32::MAX, u32::MAX);
+
+        let mut encoded = EncodedResult::EMPTY;
+        encoded.encode(&result);
+        assert_ne!(encoded.len, 0);
+        assert_eq!(
+            decode(&mailbox_bytes(&encoded)).unwrap().values.len(),
+            MAX_VALUES
+        );
+    }
+
+    #[test]
+    fn decode_rejects_empty_and_truncated_mailboxes() {
+        assert_eq!(decode(&mailbox_bytes(&EncodedResult::EMPTY)), None);
+        assert_eq!(decode(&[0, 0]), None);
+
+        let mut encoded = EncodedResult::EMPTY;
+        encoded.encode(&SelfTestResult::EMPTY);
+        let bytes = mailbox_bytes(&encoded);
+        assert_eq!(decode(&bytes[..4 + encoded.len as usize - 1]), None);
+    }
+}
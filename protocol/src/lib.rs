@@ -0,0 +1,522 @@
+//! Self-test layouts shared by the flash algorithm and the host tools that drive it.
+//!
+//! The algorithm exports these blocks by symbol name: `SelfTestTable` in its own link section,
+//! `SelfTestParams`, `SelfTestMailbox`, `SelfTestStatuses` and the [`TestMask`]s `SelfTestSkip`
+//! and `SelfTestPassed` as RAM mailboxes. Every field is a little-endian `u32` or a byte array,
+//! so a host decodes them with the `from_bytes` constructors here rather than its own copy of the
+//! offsets. The `std` feature adds helpers that collect whole tables; `postcard` adds the
+//! [`encoded`] form of results.
+//!
+//! The image also exports its [`ProtocolVersion`] as `SelfTestVersion`. A host reads it first and
+//! stops at an unknown major version; within a major, layouts only grow in ways older hosts can
+//! ignore.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use core::fmt::{self, Write};
+use core::mem::{offset_of, size_of};
+
+#[cfg(feature = "postcard")]
+pub mod encoded;
+
+/// Major version of these layouts. Bumped when a block changes shape or a field changes meaning;
+/// a host must refuse an image whose major it doesn't know rather than guess at its offsets.
+pub const VERSION_MAJOR: u32 = 1;
+/// Minor version of these layouts. Bumped for additions an older host can ignore: new
+/// [`flags`] bits, [`group`] values or trailing fields. Hosts accept any minor.
+pub const VERSION_MINOR: u32 = 2;
+
+/// What `SelfTestStatuses` holds for a test the last `SelfTestAll` skipped, whether by its skip
+/// mask or its [`flags`]; the algorithm's `TEST_SKIPPED` error code.
+pub const STATUS_SKIPPED: u32 = 0x5e1f_0004;
+
+/// Tests `SelfTestAll` can run, one bit each in a [`TestMask`].
+pub const MAX_TESTS: usize = 64;
+/// Words of a [`TestMask`].
+pub const MASK_WORDS: usize = MAX_TESTS.div_ceil(32);
+
+/// One bit per `SelfTestAll` test, bit `n % 32` of word `n / 32` standing for test `n`.
+///
+/// `SelfTestSkip` takes the tests to skip, which the next `SelfTestAll` clears after reading; its
+/// `skip_mask` argument adds to word 0. `SelfTestPassed` holds the tests that passed, of which
+/// `SelfTestAll` also returns word 0.
+pub type TestMask = [u32; MASK_WORDS];
+
+/// Whether `mask` has the bit of test `bit` set; false past [`MAX_TESTS`].
+pub fn mask_has(mask: &TestMask, bit: usize) -> bool {
+    mask.get(bit / 32)
+        .is_some_and(|word| word & 1 << (bit % 32) != 0)
+}
+
+/// Sets the bit of test `bit` in `mask`, returning false past [`MAX_TESTS`].
+pub fn mask_set(mask: &mut TestMask, bit: usize) -> bool {
+    match mask.get_mut(bit / 32) {
+        Some(word) => {
+            *word |= 1 << (bit % 32);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Measurement slots available to one test.
+pub const MAX_VALUES: usize = 8;
+/// Bytes of [`SelfTestParameters::data`].
+pub const PARAMS_CAPACITY: usize = 64;
+/// Bytes of [`SelfTestResult::message`], NUL terminator included.
+pub const MESSAGE_LEN: usize = 64;
+
+/// Bits of [`TestInfo::flags`].
+pub mod flags {
+    /// Wears or wipes something (e.g. flash endurance) or leaves a watchdog running, so
+    /// `SelfTestAll` skips it; run it alone with `SelfTest`, outside the factory not at all.
+    pub const DESTRUCTIVE: u32 = 1 << 0;
+    /// Needs external wiring such as loopback jumpers or an RF load to pass.
+    pub const REQUIRES_FIXTURE: u32 = 1 << 1;
+    /// Fails without host parameters, such as the pins or frequency it works on, so `SelfTestAll`
+    /// skips it; run it alone with `SelfTest`.
+    pub const REQUIRES_PARAMS: u32 = 1 << 2;
+
+    /// Every flag with the name host tools print for it.
+    pub const NAMES: [(u32, &str); 3] = [
+        (DESTRUCTIVE, "destructive"),
+        (REQUIRES_FIXTURE, "requires-fixture"),
+        (REQUIRES_PARAMS, "requires-params"),
+    ];
+}
+
+/// Values of [`TestInfo::group`], in the order the groups run.
+pub mod group {
+    /// Sanity checks of the algorithm itself.
+    pub const GENERAL: u32 = 0;
+    pub const POWER: u32 = 1;
+    pub const CLOCKS: u32 = 2;
+    pub const MEMORY: u32 = 3;
+    pub const RADIO: u32 = 4;
+    pub const PERIPHERALS: u32 = 5;
+
+    /// Indexed by group value.
+    pub const NAMES: [&str; 6] = [
+        "general",
+        "power",
+        "clocks",
+        "memory",
+        "radio",
+        "peripherals",
+    ];
+}
+
+/// The `SelfTestVersion` block, exported next to the library's `SelfTestDescription` whose own
+/// magic identifies the blob but carries no layout version.
+///
+/// An image without the block predates versioning and uses the 1.0 layouts.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProtocolVersion {
+    /// [`ProtocolVersion::MAGIC`], the same word `SelfTestDescription` starts with.
+    pub magic: u32,
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl ProtocolVersion {
+    pub const SIZE: usize = size_of::<Self>();
+    /// "Soul" read as a big-endian word.
+    pub const MAGIC: u32 = 0x536f_756c;
+    /// The version these layouts implement.
+    pub const CURRENT: Self = Self {
+        magic: Self::MAGIC,
+        major: VERSION_MAJOR,
+        minor: VERSION_MINOR,
+    };
+    /// What an image without a `SelfTestVersion` block speaks.
+    pub const UNVERSIONED: Self = Self {
+        magic: Self::MAGIC,
+        major: 1,
+        minor: 0,
+    };
+
+    /// Decodes the block at the start of `bytes`, `None` if they are too short.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(Self {
+            magic: word_at(bytes, offset_of!(Self, magic))?,
+            major: word_at(bytes, offset_of!(Self, major))?,
+            minor: word_at(bytes, offset_of!(Self, minor))?,
+        })
+    }
+
+    /// Whether a host built against these layouts can read an image speaking `self`: the magic
+    /// must match and the major must be [`VERSION_MAJOR`]; the minor is not compared.
+    pub fn is_supported(&self) -> bool {
+        self.magic == Self::MAGIC && self.major == VERSION_MAJOR
+    }
+}
+
+/// One `SelfTestTable` entry: per-test metadata the library's `SelfTestItem` has no room for.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TestInfo {
+    pub id: u32,
+    pub flags: u32,
+    /// One of [`group`]; the registry is sorted by it, so a group's tests run together.
+    pub group: u32,
+    /// Typical run time on a good board, for the host to derive its call timeout from.
+    pub expected_ms: u32,
+}
+
+impl TestInfo {
+    pub const SIZE: usize = size_of::<Self>();
+    /// The entry that ends the table.
+    pub const TERMINATOR: Self = Self {
+        id: 0,
+        flags: 0,
+        group: 0,
+        expected_ms: 0,
+    };
+
+    /// Decodes the entry at the start of `bytes`, `None` if they are too short.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(Self {
+            id: word_at(bytes, offset_of!(Self, id))?,
+            flags: word_at(bytes, offset_of!(Self, flags))?,
+            group: word_at(bytes, offset_of!(Self, group))?,
+            expected_ms: word_at(bytes, offset_of!(Self, expected_ms))?,
+        })
+    }
+}
+
+/// Test-specific bytes the host writes into `SelfTestParams` before calling `SelfTest(id)`;
+/// their layout is defined by each test, almost always as little-endian words.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SelfTestParameters {
+    /// Number of valid bytes in `data`; cleared after every run, so parameters never carry over.
+    pub len: u32,
+    pub data: [u8; PARAMS_CAPACITY],
+}
+
+impl SelfTestParameters {
+    pub const SIZE: usize = size_of::<Self>();
+    pub const EMPTY: Self = Self {
+        len: 0,
+        data: [0; PARAMS_CAPACITY],
+    };
+
+    /// The block holding `words` in order, `None` if they don't fit.
+    pub fn from_words(words: &[u32]) -> Option<Self> {
+        let mut params = Self::EMPTY;
+        let bytes = params.data.get_mut(..words.len() * 4)?;
+        for (chunk, word) in bytes.chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        params.len = (words.len() * 4) as u32;
+        Some(params)
+    }
+
+    /// The block as the host writes it to the `SelfTestParams` symbol.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut out = [0; Self::SIZE];
+        out[offset_of!(Self, len)..][..4].copy_from_slice(&self.len.to_le_bytes());
+        out[offset_of!(Self, data)..][..PARAMS_CAPACITY].copy_from_slice(&self.data);
+        out
+    }
+}
+
+/// The `index`-th little-endian word of `params`, or `None` if the host didn't supply it.
+pub fn word(params: &[u8], index: usize) -> Option<u32> {
+    word_at(params, index * 4)
+}
+
+/// Detailed outcome of the last self test, left in `SelfTestMailbox`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SelfTestResult {
+    /// ID of the test that filled this block, zero before the first `SelfTest` call.
+    pub id: u32,
+    /// Zero on pass, otherwise the code the test returned.
+    pub status: u32,
+    /// Zero when the algorithm was built with `timeout-spin`, which leaves DWT to the probe.
+    pub duration_us: u32,
+    /// Number of valid entries in `values`.
+    pub value_count: u32,
+    /// Test-specific measurements, e.g. a failing address and the pattern read back.
+    pub values: [u32; MAX_VALUES],
+    /// NUL-terminated, truncated to fit.
+    pub message: [u8; MESSAGE_LEN],
+}
+
+impl SelfTestResult {
+    pub const SIZE: usize = size_of::<Self>();
+    pub const EMPTY: Self = Self {
+        id: 0,
+        status: 0,
+        duration_us: 0,
+        value_count: 0,
+        values: [0; MAX_VALUES],
+        message: [0; MESSAGE_LEN],
+    };
+
+    /// Decodes the block at the start of `bytes`, `None` if they are too short.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut result = Self {
+            id: word_at(bytes, offset_of!(Self, id))?,
+            status: word_at(bytes, offset_of!(Self, status))?,
+            duration_us: word_at(bytes, offset_of!(Self, duration_us))?,
+            value_count: word_at(bytes, offset_of!(Self, value_count))?,
+            ..Self::EMPTY
+        };
+        for (i, value) in result.values.iter_mut().enumerate() {
+            *value = word_at(bytes, offset_of!(Self, values) + i * 4)?;
+        }
+        let message = offset_of!(Self, message);
+        result
+            .message
+            .copy_from_slice(bytes.get(message..message + MESSAGE_LEN)?);
+        Some(result)
+    }
+
+    /// The measurements the test recorded.
+    pub fn values(&self) -> &[u32] {
+        let count = (self.value_count as usize).min(MAX_VALUES);
+        &self.values[..count]
+    }
+
+    /// The message up to its NUL, or up to the first byte that isn't valid UTF-8.
+    pub fn message_str(&self) -> &str {
+        let len = self
+            .message
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(MESSAGE_LEN);
+        match core::str::from_utf8(&self.message[..len]) {
+            Ok(text) => text,
+            Err(e) => core::str::from_utf8(&self.message[..e.valid_up_to()]).unwrap_or_default(),
+        }
+    }
+
+    /// Appends a measurement, dropping it once all [`MAX_VALUES`] slots are used.
+    pub fn value(&mut self, value: u32) {
+        if let Some(slot) = self.values.get_mut(self.value_count as usize) {
+            *slot = value;
+            self.value_count += 1;
+        }
+    }
+
+    /// Replaces the message, truncating it to fit.
+    pub fn message(&mut self, args: fmt::Arguments) {
+        self.message = [0; MESSAGE_LEN];
+        let _ = Truncating {
+            buf: &mut self.message,
+            len: 0,
+        }
+        .write_fmt(args);
+    }
+
+    /// Clears the block for the test `id` about to run.
+    pub fn start(&mut self, id: u32) {
+        *self = Self { id, ..Self::EMPTY };
+    }
+
+    pub fn finish(&mut self, status: u32, duration_us: u32) {
+        self.status = status;
+        self.duration_us = duration_us;
+    }
+}
+
+/// Decodes a `SelfTestTable`, stopping at its zero-ID terminator or the end of `data`.
+#[cfg(feature = "std")]
+pub fn parse_table(data: &[u8]) -> Vec<TestInfo> {
+    data.chunks_exact(TestInfo::SIZE)
+        .filter_map(TestInfo::from_bytes)
+        .take_while(|test| test.id != 0)
+        .collect()
+}
+
+/// Decodes `SelfTestStatuses`, one status per `SelfTestAll` bit.
+#[cfg(feature = "std")]
+pub fn parse_statuses(data: &[u8]) -> Vec<u32> {
+    (0..MAX_TESTS).map_while(|bit| word(data, bit)).collect()
+}
+
+fn word_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Formats into a fixed buffer, silently dropping whatever doesn't fit before the final NUL.
+struct Truncating<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for Truncating<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = self.buf.len().saturating_sub(self.len + 1);
+        let n = s.len().min(room);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn version_decodes_and_checks_major() {
+        let mut bytes = [0u8; ProtocolVersion::SIZE];
+        put(&mut bytes, 0, ProtocolVersion::MAGIC);
+        put(&mut bytes, 4, VERSION_MAJOR);
+        put(&mut bytes, 8, VERSION_MINOR + 7);
+        let version = ProtocolVersion::from_bytes(&bytes).unwrap();
+        assert_eq!(version.minor, VERSION_MINOR + 7);
+        assert!(version.is_supported());
+
+        assert!(ProtocolVersion::UNVERSIONED.is_supported());
+        let next_major = ProtocolVersion {
+            major: VERSION_MAJOR + 1,
+            ..ProtocolVersion::CURRENT
+        };
+        assert!(!next_major.is_supported());
+        let foreign = ProtocolVersion {
+            magic: 0,
+            ..ProtocolVersion::CURRENT
+        };
+        assert!(!foreign.is_supported());
+        assert_eq!(ProtocolVersion::from_bytes(&bytes[..8]), None);
+    }
+
+    #[test]
+    fn test_info_decodes_fields_in_order() {
+        let mut bytes = [0u8; TestInfo::SIZE];
+        for (i, value) in [0x0401, flags::REQUIRES_PARAMS, group::RADIO, 20]
+            .into_iter()
+            .enumerate()
+        {
+            put(&mut bytes, i * 4, value);
+        }
+        let info = TestInfo::from_bytes(&bytes).unwrap();
+        assert_eq!(info.id, 0x0401);
+        assert_eq!(info.flags, flags::REQUIRES_PARAMS);
+        assert_eq!(info.group, group::RADIO);
+        assert_eq!(info.expected_ms, 20);
+        assert_eq!(TestInfo::from_bytes(&bytes[..12]), None);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn parse_table_stops_at_terminator() {
+        let mut data = vec![0u8; TestInfo::SIZE * 4];
+        put(&mut data, 0, 1);
+        put(&mut data, TestInfo::SIZE, 2);
+        // Past the terminator; must not be returned.
+        put(&mut data, TestInfo::SIZE * 3, 3);
+        let ids: Vec<u32> = parse_table(&data).iter().map(|test| test.id).collect();
+        assert_eq!(ids, [1, 2]);
+        assert_eq!(parse_table(&data[..TestInfo::SIZE + 3]).len(), 1);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn parse_statuses_is_capped_at_max_tests() {
+        let data: Vec<u8> = (0..MAX_TESTS as u32 + 4)
+            .flat_map(u32::to_le_bytes)
+            .collect();
+        let statuses = parse_statuses(&data);
+        assert_eq!(statuses.len(), MAX_TESTS);
+        assert_eq!(statuses[MAX_TESTS - 1], MAX_TESTS as u32 - 1);
+        assert_eq!(parse_statuses(&data[..6]), [0]);
+    }
+
+    #[test]
+    fn params_from_words_lays_out_little_endian() {
+        let params = SelfTestParameters::from_words(&[0x1122_3344, 5]).unwrap();
+        assert_eq!(params.len, 8);
+        assert_eq!(params.data[..8], [0x44, 0x33, 0x22, 0x11, 5, 0, 0, 0]);
+        assert_eq!(word(&params.data[..params.len as usize], 1), Some(5));
+        assert_eq!(word(&params.data[..params.len as usize], 2), None);
+
+        let bytes = params.to_bytes();
+        assert_eq!(bytes[..4], 8u32.to_le_bytes());
+        assert_eq!(bytes[4..8], 0x1122_3344u32.to_le_bytes());
+
+        let full = [0u32; PARAMS_CAPACITY / 4];
+        assert!(SelfTestParameters::from_words(&full).is_some());
+        assert_eq!(
+            SelfTestParameters::from_words(&[0; PARAMS_CAPACITY / 4 + 1]),
+            None
+        );
+    }
+
+    #[test]
+    fn result_from_bytes_round_trips_the_block() {
+        let mut bytes = [0u8; SelfTestResult::SIZE];
+        put(&mut bytes, offset_of!(SelfTestResult, id), 0x0301);
+        put(&mut bytes, offset_of!(SelfTestResult, status), 0x5e1f_0010);
+        put(&mut bytes, offset_of!(SelfTestResult, duration_us), 1234);
+        put(&mut bytes, offset_of!(SelfTestResult, value_count), 2);
+        put(&mut bytes, offset_of!(SelfTestResult, values), 0x2000_0000);
+        put(
+            &mut bytes,
+            offset_of!(SelfTestResult, values) + 4,
+            0xAAAA_5555,
+        );
+        let message = offset_of!(SelfTestResult, message);
+        bytes[message..message + 4].copy_from_slice(b"bad\0");
+
+        let result = SelfTestResult::from_bytes(&bytes).unwrap();
+        assert_eq!(result.id, 0x0301);
+        assert_eq!(result.status, 0x5e1f_0010);
+        assert_eq!(result.duration_us, 1234);
+        assert_eq!(result.values(), [0x2000_0000, 0xAAAA_5555]);
+        assert_eq!(result.message_str(), "bad");
+        assert_eq!(
+            SelfTestResult::from_bytes(&bytes[..SelfTestResult::SIZE - 1]),
+            None
+        );
+    }
+
+    #[test]
+    fn result_caps_values_and_truncates_message() {
+        let mut result = SelfTestResult::EMPTY;
+        for value in 0..MAX_VALUES as u32 + 2 {
+            result.value(value);
+        }
+        assert_eq!(result.values().len(), MAX_VALUES);
+        assert_eq!(result.values()[MAX_VALUES - 1], MAX_VALUES as u32 - 1);
+
+        result.message(format_args!("{:x<100}", "x"));
+        assert_eq!(result.message_str().len(), MESSAGE_LEN - 1);
+        assert_eq!(result.message[MESSAGE_LEN - 1], 0);
+
+        result.message[..4].copy_from_slice(&[b'o', b'k', 0xFF, 0]);
+        assert_eq!(result.message_str(), "ok");
+        result.value_count = u32::MAX;
+        assert_eq!(result.values().len(), MAX_VALUES);
+
+        result.start(7);
+        assert_eq!(
+            result,
+            SelfTestResult {
+                id: 7,
+                ..SelfTestResult::EMPTY
+            }
+        );
+    }
+
+    #[test]
+    fn masks_index_words_then_bits() {
+        let mut mask = [0; MASK_WORDS];
+        assert!(mask_set(&mut mask, 0));
+        assert!(mask_set(&mut mask, 33));
+        assert!(!mask_set(&mut mask, MAX_TESTS));
+        assert_eq!(mask[..2], [1, 2]);
+        assert!(mask_has(&mask, 33));
+        assert!(!mask_has(&mask, 32));
+        assert!(!mask_has(&mask, MAX_TESTS));
+    }
+}
@@ -0,0 +1,22 @@
+//! Internal invariant checks for development builds, enabled by the `asserts` feature.
+//!
+//! Unlike `debug_assert!`, a failed check doesn't panic into the `udf` handler: it logs the
+//! condition and returns one of the `ASSERT_*` codes, so the host sees which invariant broke.
+//! Without the feature the condition isn't evaluated and the check compiles to nothing.
+
+/// Returns `code` from the enclosing function if `cond` is false and `asserts` is enabled.
+macro_rules! ensure {
+    ($cond:expr, $code:expr) => {
+        if cfg!(feature = "asserts") && !$cond {
+            $crate::log::error!(
+                "Assertion failed: {} at {}:{}",
+                stringify!($cond),
+                file!(),
+                line!()
+            );
+            return Err($code);
+        }
+    };
+}
+
+pub(crate) use ensure;
@@ -0,0 +1,166 @@
+//! Bench console on an RTT down channel, enabled by the `console` feature.
+//!
+//! The `Console` entry point reads one command per line from down channel 0 and answers on the
+//! print channel, so a board can be poked at with any RTT terminal and no dedicated runner:
+//!
+//! - `test <id> [word...]`: runs a self test with the words as its parameters, needs `self-test`;
+//! - `stats`: prints the session's `OpStats`;
+//! - `read <addr> [words]`: dumps up to 64 words from a word-aligned address;
+//! - `exit`: returns from `Console`.
+//!
+//! Numbers are decimal or `0x`-prefixed hex. `read` touches whatever it is given, so an unmapped
+//! address faults like any other bad access.
+
+use core::fmt::{self, Write};
+
+use flash_algorithm::ErrorCode;
+use rtt_target::{rprint, rprintln, DownChannel};
+
+use crate::error::{self, codes};
+use crate::mailbox::{abort_requested, Mailbox};
+use crate::stats;
+
+/// Longest command line; the rest of a longer one is discarded with it.
+const LINE_LEN: usize = 64;
+const MAX_READ_WORDS: u32 = 64;
+/// Also the most parameter words `test` takes, which fill `SelfTestParams`.
+const MAX_ARGS: usize = 16;
+
+/// Registered by `init_rtt`, which owns the channel's buffer.
+static CHANNEL: Mailbox<Option<DownChannel>> = Mailbox::new(None);
+
+/// The print channel as a [`Write`] sink, for formatters shared with the log.
+struct Output;
+
+impl Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        rprint!("{}", s);
+        Ok(())
+    }
+}
+
+pub fn attach(channel: DownChannel) {
+    CHANNEL.write(Some(channel));
+}
+
+/// Serves commands until `exit` or a host abort, which it returns as [`codes::ABORTED`].
+pub fn run() -> Result<(), ErrorCode> {
+    let mut line = [0u8; LINE_LEN];
+    let mut len = 0;
+    let mut overflow = false;
+    rprintln!("console ready, commands: test, stats, read, exit");
+    loop {
+        if abort_requested() {
+            return Err(codes::ABORTED);
+        }
+        let mut byte = [0u8];
+        let mut got = 0;
+        CHANNEL.update(|channel| {
+            if let Some(channel) = channel {
+                got = channel.read(&mut byte);
+            }
+        });
+        if got == 0 {
+            continue;
+        }
+        match byte[0] {
+            b'\r' | b'\n' => {
+                let command = core::mem::take(&mut len);
+                if core::mem::take(&mut overflow) {
+                    rprintln!("line too long, at most {} bytes", LINE_LEN);
+                } else if let Ok(command) = core::str::from_utf8(&line[..command]) {
+                    if command.trim() == "exit" {
+                        return Ok(());
+                    }
+                    execute(command);
+                }
+            }
+            byte if len < LINE_LEN => {
+                line[len] = byte;
+                len += 1;
+            }
+            _ => overflow = true,
+        }
+    }
+}
+
+fn execute(command: &str) {
+    let mut words = command.split_ascii_whitespace();
+    let Some(name) = words.next() else {
+        return;
+    };
+    let mut args = [0u32; MAX_ARGS];
+    let mut count = 0;
+    for word in words {
+        let (Some(slot), Some(value)) = (args.get_mut(count), number(word)) else {
+            rprintln!(
+                "bad argument {:?}, expected at most {} numbers",
+                word,
+                MAX_ARGS
+            );
+            return;
+        };
+        *slot = value;
+        count += 1;
+    }
+    let args = &args[..count];
+
+    let outcome = match (name, args) {
+        #[cfg(feature = "self-test")]
+        ("test", [id, params @ ..]) => test(*id, params),
+        ("stats", []) => {
+            let _ = stats::write_summary(&mut Output);
+            Ok(())
+        }
+        ("read", [addr]) => read(*addr, 4),
+        ("read", [addr, words]) => read(*addr, *words),
+        _ => {
+            rprintln!("unknown command {:?}", command.trim());
+            return;
+        }
+    };
+    if let Err(e) = outcome {
+        rprintln!("error {:#010x}: {}", e.get(), error::describe(e.get()));
+    }
+}
+
+#[cfg(feature = "self-test")]
+fn test(id: u32, params: &[u32]) -> Result<(), ErrorCode> {
+    crate::self_test::run_with(id, params, |result| {
+        rprint!(
+            "test {:#x}: status {:#x}, {} us",
+            id,
+            result.status,
+            result.duration_us
+        );
+        for value in result.values() {
+            rprint!(" {:#010x}", value);
+        }
+        rprintln!(" {}", result.message_str());
+    })
+}
+
+fn read(addr: u32, words: u32) -> Result<(), ErrorCode> {
+    if !addr.is_multiple_of(4) {
+        return Err(codes::ALIGNMENT);
+    }
+    if !(1..=MAX_READ_WORDS).contains(&words) || addr.checked_add(words * 4).is_none() {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+    for row in (0..words).step_by(4) {
+        rprint!("{:#010x}:", addr + row * 4);
+        for i in row..(row + 4).min(words) {
+            let value = unsafe { ((addr + i * 4) as *const u32).read_volatile() };
+            rprint!(" {:08x}", value);
+        }
+        rprintln!();
+    }
+    Ok(())
+}
+
+fn number(word: &str) -> Option<u32> {
+    match word.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => word.parse().ok(),
+    }
+}
@@ -0,0 +1,21 @@
+//! Device identity read from the factory-programmed engineering bytes (RM0461, section 41).
+
+const UID96: usize = 0x1FFF_7590;
+const FLASH_SIZE: usize = 0x1FFF_75E0;
+
+/// The 96-bit unique device ID as three words, lowest address first.
+pub fn uid() -> [u32; 3] {
+    let base = UID96 as *const u32;
+    unsafe {
+        [
+            base.read_volatile(),
+            base.add(1).read_volatile(),
+            base.add(2).read_volatile(),
+        ]
+    }
+}
+
+/// Main flash size in KiB, as reported by the FLASH_SIZE register.
+pub fn flash_size_kib() -> u16 {
+    unsafe { (FLASH_SIZE as *const u16).read_volatile() }
+}
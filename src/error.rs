@@ -0,0 +1,288 @@
+//! Error codes returned to the host and the string table that decodes them.
+//!
+//! Every code the algorithm returns is a named constant in [`codes`], built by [`code`] from a
+//! [`subsystem`] in the upper half-word and a detail in the lower one; call sites never construct
+//! an `ErrorCode` themselves. The numbers are part of the host ABI: a code keeps its value once
+//! released and retired ones aren't reused, and new codes take the next free detail of their
+//! subsystem and an entry in [`ErrorStrings`].
+//!
+//! The flash driver returns the finer [`FlashError`] instead, which converts into its code where
+//! the caller hands it on to the host.
+
+use crate::info::fixed_str;
+
+/// Builds an error code from a subsystem in the upper half-word and a detail in the lower one.
+///
+/// Evaluated at compile time for the constants in [`codes`], so a zero code fails the build.
+pub const fn code(subsystem: u16, detail: u16) -> flash_algorithm::ErrorCode {
+    match flash_algorithm::ErrorCode::new((subsystem as u32) << 16 | detail as u32) {
+        Some(code) => code,
+        None => panic!("error codes must be non-zero"),
+    }
+}
+
+/// Upper half-words of [`code`], grouping codes by the part of the algorithm raising them.
+pub mod subsystem {
+    pub const GENERAL: u16 = 0x0000;
+    pub const FLASH: u16 = 0x0001;
+    pub const SELF_TEST: u16 = 0x5e1f;
+    /// Internal invariants checked with the `asserts` feature.
+    pub const ASSERT: u16 = 0xa55e;
+}
+
+/// Well-known error codes, so call sites never spell out raw numbers.
+pub mod codes {
+    use super::code;
+    use super::subsystem::{ASSERT, FLASH, GENERAL, SELF_TEST};
+    use flash_algorithm::ErrorCode;
+
+    /// The host cancelled the operation through `AbortRequest`.
+    pub const ABORTED: ErrorCode = code(GENERAL, 0x0001);
+    /// The stack canary planted by `Init` got overwritten.
+    pub const STACK_OVERFLOW: ErrorCode = code(GENERAL, 0x0002);
+    /// A pointer or length passed by the host is unusable.
+    pub const INVALID_ARGUMENT: ErrorCode = code(GENERAL, 0x0003);
+    /// An entry point was called before `Init` or after `UnInit`.
+    pub const NOT_INITIALIZED: ErrorCode = code(GENERAL, 0x0004);
+    /// FLASH_SR.BSY didn't clear in time.
+    pub const TIMEOUT: ErrorCode = code(FLASH, 0x0001);
+    /// FLASH_CR stayed locked after writing the unlock keys.
+    pub const LOCKED: ErrorCode = code(FLASH, 0x0002);
+    /// The requested range lies outside main flash.
+    pub const OUT_OF_RANGE: ErrorCode = code(FLASH, 0x0003);
+    /// Programming must start on a 64-bit boundary.
+    pub const ALIGNMENT: ErrorCode = code(FLASH, 0x0004);
+    /// Flash contents differ from the data the host expected.
+    pub const VERIFY_MISMATCH: ErrorCode = code(FLASH, 0x0005);
+    /// Flash matched the expected data only after ECC corrected a bit on the way out.
+    pub const ECC_CORRECTED: ErrorCode = code(FLASH, 0x0006);
+    pub const OPERATION: ErrorCode = code(FLASH, 0x0010);
+    pub const PROGRAMMING: ErrorCode = code(FLASH, 0x0011);
+    pub const WRITE_PROTECTED: ErrorCode = code(FLASH, 0x0012);
+    pub const PROGRAM_ALIGNMENT: ErrorCode = code(FLASH, 0x0013);
+    pub const PROGRAM_SIZE: ErrorCode = code(FLASH, 0x0014);
+    pub const PROGRAM_SEQUENCE: ErrorCode = code(FLASH, 0x0015);
+    pub const FAST_PROGRAM: ErrorCode = code(FLASH, 0x0016);
+    /// A driver address wasn't aligned as its caller promised, e.g. a page erase mid-page.
+    pub const ASSERT_ALIGNMENT: ErrorCode = code(ASSERT, 0x0001);
+    /// A page number or range computed by the driver falls outside main flash.
+    pub const ASSERT_PAGE_BOUNDS: ErrorCode = code(ASSERT, 0x0002);
+    /// FLASH_CR was locked or still had an operation bit set when the driver started one.
+    pub const ASSERT_LOCK_STATE: ErrorCode = code(ASSERT, 0x0003);
+    /// A buffer is larger than the one the host is declared to pass.
+    pub const ASSERT_BUFFER_SIZE: ErrorCode = code(ASSERT, 0x0004);
+    /// The host asked for a self-test ID that isn't implemented by this build.
+    pub const UNKNOWN_TEST: ErrorCode = code(SELF_TEST, 0x0001);
+    /// The running self test overran its declared duration plus margin.
+    pub const TEST_TIMEOUT: ErrorCode = code(SELF_TEST, 0x0002);
+    /// The core computed a wrong arithmetic or branch result in the CPU sanity test.
+    pub const CORE_FAULT: ErrorCode = code(SELF_TEST, 0x0003);
+    /// Left in `SelfTestStatuses` for a test `SelfTestAll` didn't run; never returned.
+    pub const TEST_SKIPPED: ErrorCode = code(SELF_TEST, 0x0004);
+    /// A RAM word read back differently from what the memory test wrote.
+    pub const RAM_FAULT: ErrorCode = code(SELF_TEST, 0x0010);
+    /// SYSCFG flagged an SRAM2 parity error, or the flag wouldn't clear.
+    pub const PARITY_ERROR: ErrorCode = code(SELF_TEST, 0x0011);
+    /// The host required SRAM2 parity but the `SRAM2_PE` option bit leaves it disabled.
+    pub const PARITY_DISABLED: ErrorCode = code(SELF_TEST, 0x0012);
+    /// A DMA transfer flagged an error, never completed or copied the wrong data.
+    pub const DMA_FAULT: ErrorCode = code(SELF_TEST, 0x0013);
+    /// The CRC of a flash range differs from the one the host expected.
+    pub const CRC_MISMATCH: ErrorCode = code(SELF_TEST, 0x0020);
+    /// Scanning flash caused more single-bit ECC corrections than the host allowed.
+    pub const FLASH_ECC: ErrorCode = code(SELF_TEST, 0x0021);
+    /// The radio kept BUSY asserted, stayed in reset or didn't clock SPI bytes in time.
+    pub const RADIO_TIMEOUT: ErrorCode = code(SELF_TEST, 0x0030);
+    /// The radio answered, but with a status or register contents it shouldn't have.
+    pub const RADIO_FAULT: ErrorCode = code(SELF_TEST, 0x0031);
+    /// A pin didn't read back the level it was driven or pulled to.
+    pub const PIN_FAULT: ErrorCode = code(SELF_TEST, 0x0040);
+    /// An oscillator's ready flag never rose, or SYSCLK wouldn't switch to it.
+    pub const CLOCK_TIMEOUT: ErrorCode = code(SELF_TEST, 0x0050);
+    /// A measured clock frequency is further from nominal than the host allowed.
+    pub const CLOCK_INACCURATE: ErrorCode = code(SELF_TEST, 0x0051);
+    /// An oscillator started, but later than the host allowed.
+    pub const CLOCK_SLOW: ErrorCode = code(SELF_TEST, 0x0052);
+    /// A watchdog didn't accept its configuration, or didn't fire or reset when it should have.
+    pub const WATCHDOG_FAULT: ErrorCode = code(SELF_TEST, 0x0060);
+    /// The RNG flagged a seed or clock error, repeated a word or failed a statistical check.
+    pub const RNG_FAULT: ErrorCode = code(SELF_TEST, 0x0070);
+    /// A crypto accelerator or the CRC unit produced the wrong output for a known-answer vector.
+    pub const KAT_MISMATCH: ErrorCode = code(SELF_TEST, 0x0071);
+    /// An analog reading, or the calibration data behind it, is outside its plausible range.
+    pub const ANALOG_RANGE: ErrorCode = code(SELF_TEST, 0x0080);
+    /// The main regulator didn't report ready on, or running from, the LDO or SMPS it was given.
+    pub const REGULATOR_FAULT: ErrorCode = code(SELF_TEST, 0x0090);
+    /// Backup registers didn't hold the pattern written before the power cycle.
+    pub const RETENTION_LOST: ErrorCode = code(SELF_TEST, 0x0091);
+    /// The core didn't enter the low-power mode, or a wakeup source fired early, late or not at all.
+    pub const LOW_POWER_FAULT: ErrorCode = code(SELF_TEST, 0x0092);
+    /// A bus peripheral never got a byte out or in, or never acknowledged being enabled.
+    pub const BUS_TIMEOUT: ErrorCode = code(SELF_TEST, 0x00A0);
+    /// A U(S)ART received a byte with a framing error, usually a baud rate mismatch.
+    pub const UART_FRAMING: ErrorCode = code(SELF_TEST, 0x00A1);
+    /// A U(S)ART received a byte before the previous one was read out.
+    pub const UART_OVERRUN: ErrorCode = code(SELF_TEST, 0x00A2);
+    /// Data came back over a loopback, but not what was sent.
+    pub const DATA_MISMATCH: ErrorCode = code(SELF_TEST, 0x00A3);
+    /// A bus line stayed low with only the pull-ups on it, or the controller saw a bus error or
+    /// lost arbitration to some other driver.
+    pub const BUS_STUCK: ErrorCode = code(SELF_TEST, 0x00A4);
+    /// A device the host listed didn't answer on its bus, or left its data line floating.
+    pub const DEVICE_MISSING: ErrorCode = code(SELF_TEST, 0x00A5);
+    /// A device answered on its bus, but with an ID other than the one the host expected.
+    pub const DEVICE_ID_MISMATCH: ErrorCode = code(SELF_TEST, 0x00A6);
+    /// A timer's output, measured back, was missing or off in frequency or duty cycle.
+    pub const TIMER_FAULT: ErrorCode = code(SELF_TEST, 0x00B0);
+}
+
+/// Failures of the flash driver, turned into their [`codes`] where they leave it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlashError {
+    /// The host raised `AbortRequest` between two hardware operations.
+    Aborted,
+    /// The host passed less data than the range it asked for.
+    #[cfg_attr(not(feature = "verify"), allow(dead_code))]
+    InvalidArgument,
+    Timeout,
+    Locked,
+    OutOfRange,
+    Alignment,
+    #[cfg_attr(not(feature = "verify"), allow(dead_code))]
+    VerifyMismatch,
+    #[cfg_attr(not(feature = "verify"), allow(dead_code))]
+    EccCorrected,
+    /// FLASH_SR flags, most specific first.
+    WriteProtected,
+    ProgramAlignment,
+    ProgramSize,
+    ProgramSequence,
+    Programming,
+    FastProgram,
+    Operation,
+    /// An `asserts` check failed, with its `ASSERT_*` code.
+    Assert(flash_algorithm::ErrorCode),
+}
+
+impl From<FlashError> for flash_algorithm::ErrorCode {
+    fn from(error: FlashError) -> Self {
+        match error {
+            FlashError::Aborted => codes::ABORTED,
+            FlashError::InvalidArgument => codes::INVALID_ARGUMENT,
+            FlashError::Timeout => codes::TIMEOUT,
+            FlashError::Locked => codes::LOCKED,
+            FlashError::OutOfRange => codes::OUT_OF_RANGE,
+            FlashError::Alignment => codes::ALIGNMENT,
+            FlashError::VerifyMismatch => codes::VERIFY_MISMATCH,
+            FlashError::EccCorrected => codes::ECC_CORRECTED,
+            FlashError::WriteProtected => codes::WRITE_PROTECTED,
+            FlashError::ProgramAlignment => codes::PROGRAM_ALIGNMENT,
+            FlashError::ProgramSize => codes::PROGRAM_SIZE,
+            FlashError::ProgramSequence => codes::PROGRAM_SEQUENCE,
+            FlashError::Programming => codes::PROGRAMMING,
+            FlashError::FastProgram => codes::FAST_PROGRAM,
+            FlashError::Operation => codes::OPERATION,
+            FlashError::Assert(code) => code,
+        }
+    }
+}
+
+/// One entry of the [`ErrorStrings`] table.
+#[repr(C)]
+pub struct ErrorString {
+    pub code: u32,
+    pub text: [u8; 28],
+}
+
+const fn entry(code: flash_algorithm::ErrorCode, text: &str) -> ErrorString {
+    ErrorString {
+        code: code.get(),
+        text: fixed_str(text),
+    }
+}
+
+/// The zero-code entry that ends the table, which no [`code`] can produce.
+const fn terminator() -> ErrorString {
+    ErrorString {
+        code: 0,
+        text: [0; 28],
+    }
+}
+
+/// Code-to-text table for host-side decoding, terminated by a zero code.
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+#[link_section = "ErrorStrings"]
+pub static ErrorStrings: [ErrorString; 53] = [
+    entry(codes::ABORTED, "aborted by host"),
+    entry(codes::STACK_OVERFLOW, "stack canary overwritten"),
+    entry(codes::INVALID_ARGUMENT, "invalid argument"),
+    entry(codes::NOT_INITIALIZED, "not initialised"),
+    entry(codes::TIMEOUT, "flash: busy timeout"),
+    entry(codes::LOCKED, "flash: unlock failed"),
+    entry(codes::OUT_OF_RANGE, "flash: address out of range"),
+    entry(codes::ALIGNMENT, "flash: address misaligned"),
+    entry(codes::VERIFY_MISMATCH, "flash: verify mismatch"),
+    entry(codes::ECC_CORRECTED, "flash: ECC corrected a bit"),
+    entry(codes::OPERATION, "OPERR: operation error"),
+    entry(codes::PROGRAMMING, "PROGERR: programming error"),
+    entry(codes::WRITE_PROTECTED, "WRPERR: write protected"),
+    entry(codes::PROGRAM_ALIGNMENT, "PGAERR: misaligned program"),
+    entry(codes::PROGRAM_SIZE, "SIZERR: bad program size"),
+    entry(codes::PROGRAM_SEQUENCE, "PGSERR: program sequence"),
+    entry(codes::FAST_PROGRAM, "FASTERR: fast program error"),
+    entry(codes::ASSERT_ALIGNMENT, "assert: misaligned address"),
+    entry(codes::ASSERT_PAGE_BOUNDS, "assert: page out of bounds"),
+    entry(codes::ASSERT_LOCK_STATE, "assert: bad FLASH_CR state"),
+    entry(codes::ASSERT_BUFFER_SIZE, "assert: buffer too large"),
+    entry(codes::UNKNOWN_TEST, "unknown self-test id"),
+    entry(codes::TEST_TIMEOUT, "self-test timed out"),
+    entry(codes::CORE_FAULT, "CPU core check failed"),
+    entry(codes::TEST_SKIPPED, "self-test skipped"),
+    entry(codes::RAM_FAULT, "RAM pattern mismatch"),
+    entry(codes::PARITY_ERROR, "SRAM2 parity error"),
+    entry(codes::PARITY_DISABLED, "SRAM2 parity disabled"),
+    entry(codes::DMA_FAULT, "DMA transfer failed"),
+    entry(codes::CRC_MISMATCH, "flash CRC mismatch"),
+    entry(codes::FLASH_ECC, "flash ECC corrections"),
+    entry(codes::RADIO_TIMEOUT, "radio not responding"),
+    entry(codes::RADIO_FAULT, "radio bad response"),
+    entry(codes::PIN_FAULT, "pin level mismatch"),
+    entry(codes::CLOCK_TIMEOUT, "oscillator not ready"),
+    entry(codes::CLOCK_INACCURATE, "clock out of tolerance"),
+    entry(codes::CLOCK_SLOW, "oscillator slow to start"),
+    entry(codes::WATCHDOG_FAULT, "watchdog misbehaved"),
+    entry(codes::RNG_FAULT, "RNG health check failed"),
+    entry(codes::KAT_MISMATCH, "known-answer mismatch"),
+    entry(codes::ANALOG_RANGE, "analog reading out of range"),
+    entry(codes::REGULATOR_FAULT, "regulator switch failed"),
+    entry(codes::RETENTION_LOST, "backup registers lost data"),
+    entry(codes::LOW_POWER_FAULT, "Stop mode wakeup failed"),
+    entry(codes::BUS_TIMEOUT, "bus transfer timed out"),
+    entry(codes::UART_FRAMING, "UART framing error"),
+    entry(codes::UART_OVERRUN, "UART overrun"),
+    entry(codes::DATA_MISMATCH, "loopback data mismatch"),
+    entry(codes::BUS_STUCK, "bus stuck or contended"),
+    entry(codes::DEVICE_MISSING, "expected device missing"),
+    entry(codes::DEVICE_ID_MISMATCH, "device ID mismatch"),
+    entry(codes::TIMER_FAULT, "timer output mismatch"),
+    terminator(),
+];
+
+/// The [`ErrorStrings`] text for `code`, empty for one the table lacks; for on-target output.
+#[cfg(feature = "console")]
+pub fn describe(code: u32) -> &'static str {
+    let Some(entry) = ErrorStrings
+        .iter()
+        .find(|entry| entry.code == code && code != 0)
+    else {
+        return "";
+    };
+    let len = entry
+        .text
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(entry.text.len());
+    core::str::from_utf8(&entry.text[..len]).unwrap_or_default()
+}
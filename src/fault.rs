@@ -0,0 +1,157 @@
+//! HardFault capture, enabled by the `fault-capture` feature.
+//!
+//! While the algorithm runs, VTOR still points at whatever firmware ran before it, so `Init`
+//! swaps in a small RAM vector table routing the fault exceptions here and `UnInit` puts the
+//! original one back. The stacked registers and fault status land in the exported
+//! `FaultMailbox` before the core stops on a breakpoint for the host to collect them.
+
+use core::cell::UnsafeCell;
+
+use crate::mailbox::Mailbox;
+use crate::regs::Reg;
+
+const ICSR: Reg = Reg::at(0xE000_ED00, 0x04);
+const VTOR: Reg = Reg::at(0xE000_ED00, 0x08);
+const CFSR: Reg = Reg::at(0xE000_ED00, 0x28);
+const HFSR: Reg = Reg::at(0xE000_ED00, 0x2C);
+const MMFAR: Reg = Reg::at(0xE000_ED00, 0x34);
+const BFAR: Reg = Reg::at(0xE000_ED00, 0x38);
+const NVIC_ICER: usize = 0xE000_E180;
+
+/// 16 core exceptions followed by the WLE5's 62 interrupt lines.
+const VECTOR_COUNT: usize = 16 + 62;
+
+/// Set in [`FaultReport::magic`] once a fault got captured.
+pub const FAULT_MAGIC: u32 = 0xFA17_C0DE;
+
+#[repr(C)]
+pub struct FaultReport {
+    pub magic: u32,
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+}
+
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+pub static FaultMailbox: Mailbox<FaultReport> = Mailbox::new(FaultReport {
+    magic: 0,
+    r0: 0,
+    r1: 0,
+    r2: 0,
+    r3: 0,
+    r12: 0,
+    lr: 0,
+    pc: 0,
+    xpsr: 0,
+    cfsr: 0,
+    hfsr: 0,
+    mmfar: 0,
+    bfar: 0,
+});
+
+/// VTOR needs the table aligned to the next power of two above its size.
+#[repr(C, align(512))]
+struct VectorTable(UnsafeCell<[u32; VECTOR_COUNT]>);
+
+// Only touched from `install`/`uninstall`, which run from the single-threaded entry points.
+unsafe impl Sync for VectorTable {}
+
+static VECTORS: VectorTable = VectorTable(UnsafeCell::new([0; VECTOR_COUNT]));
+static PREVIOUS_VTOR: Mailbox<u32> = Mailbox::new(0);
+
+// Picks whichever stack the faulting context used and passes the exception frame on.
+core::arch::global_asm!(
+    ".section .text.SoulHardFault, \"ax\", %progbits",
+    ".global SoulHardFault",
+    ".type SoulHardFault, %function",
+    ".thumb_func",
+    "SoulHardFault:",
+    "    tst lr, #4",
+    "    ite eq",
+    "    mrseq r0, msp",
+    "    mrsne r0, psp",
+    "    b {record}",
+    record = sym record_fault,
+);
+
+extern "C" {
+    fn SoulHardFault();
+}
+
+extern "C" fn record_fault(frame: *const u32) -> ! {
+    let stacked = |i| unsafe { frame.add(i).read_volatile() };
+    FaultMailbox.write(FaultReport {
+        magic: FAULT_MAGIC,
+        r0: stacked(0),
+        r1: stacked(1),
+        r2: stacked(2),
+        r3: stacked(3),
+        r12: stacked(4),
+        lr: stacked(5),
+        pc: stacked(6),
+        xpsr: stacked(7),
+        cfsr: CFSR.read(),
+        hfsr: HFSR.read(),
+        mmfar: MMFAR.read(),
+        bfar: BFAR.read(),
+    });
+
+    loop {
+        cortex_m::asm::bkpt();
+    }
+}
+
+/// SVCall, PendSV and SysTick left running by the previous firmware are simply ignored.
+extern "C" fn ignore_exception() {}
+
+/// Interrupts the previous firmware left enabled get disabled in the NVIC on first arrival.
+extern "C" fn mask_interrupt() {
+    let active = ICSR.read() & 0x1ff;
+    if let Some(irq) = active.checked_sub(16) {
+        let icer = Reg::at(NVIC_ICER, (irq as usize / 32) * 4);
+        icer.write(1 << (irq % 32));
+    }
+}
+
+/// Points VTOR at a RAM table routing NMI, HardFault and the configurable faults to the capture.
+pub fn install() {
+    let fault = SoulHardFault as unsafe extern "C" fn() as usize as u32;
+    let ignore = ignore_exception as extern "C" fn() as usize as u32;
+    let mask = mask_interrupt as extern "C" fn() as usize as u32;
+
+    let vectors = VECTORS.0.get();
+    unsafe {
+        let table = &mut *vectors;
+        // Initial SP and reset are never fetched through VTOR while the algorithm runs.
+        table[0] = 0;
+        table[1] = 0;
+        // NMI, HardFault, MemManage, BusFault and UsageFault.
+        table[2..7].fill(fault);
+        table[7..16].fill(ignore);
+        table[16..].fill(mask);
+    }
+
+    PREVIOUS_VTOR.write(VTOR.read());
+    cortex_m::asm::dsb();
+    VTOR.write(vectors as usize as u32);
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+}
+
+/// Restores the vector table that was active before [`install`].
+pub fn uninstall() {
+    VTOR.write(PREVIOUS_VTOR.read());
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+}
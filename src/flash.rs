@@ -0,0 +1,359 @@
+//! Register-level driver for the STM32WLE5 main flash (RM0461, section 3).
+
+use flash_algorithm::ErrorCode;
+
+use crate::asserts::ensure;
+use crate::error::{codes, FlashError};
+use crate::log;
+#[cfg(feature = "verify")]
+use crate::mailbox::Mismatches;
+use crate::mailbox::{abort_requested, report_progress, Operation};
+use crate::regs::Reg;
+use crate::stats;
+use crate::timeout::{self, CycleCounter};
+
+pub const BASE: u32 = 0x0800_0000;
+pub const SIZE: u32 = 0x4_0000;
+pub const PAGE_SIZE: u32 = 0x800;
+/// The `page_size` declared in `algorithm!`: the most `ProgramPage` is handed at once.
+const PROGRAM_PAGE_SIZE: usize = 0x400;
+
+const FLASH: usize = 0x5800_4000;
+const KEYR: Reg = Reg::at(FLASH, 0x08);
+pub const SR: Reg = Reg::at(FLASH, 0x10);
+const CR: Reg = Reg::at(FLASH, 0x14);
+#[cfg(feature = "verify")]
+const ECCR: Reg = Reg::at(FLASH, 0x18);
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xCDEF_89AB;
+
+const SR_EOP: u32 = 1 << 0;
+const SR_OPERR: u32 = 1 << 1;
+const SR_PROGERR: u32 = 1 << 3;
+const SR_WRPERR: u32 = 1 << 4;
+const SR_PGAERR: u32 = 1 << 5;
+const SR_SIZERR: u32 = 1 << 6;
+const SR_PGSERR: u32 = 1 << 7;
+const SR_MISSERR: u32 = 1 << 8;
+const SR_FASTERR: u32 = 1 << 9;
+const SR_RDERR: u32 = 1 << 14;
+const SR_OPTVERR: u32 = 1 << 15;
+const SR_BSY: u32 = 1 << 16;
+const SR_ERRORS: u32 = SR_OPERR
+    | SR_PROGERR
+    | SR_WRPERR
+    | SR_PGAERR
+    | SR_SIZERR
+    | SR_PGSERR
+    | SR_MISSERR
+    | SR_FASTERR
+    | SR_RDERR
+    | SR_OPTVERR;
+
+const CR_PG: u32 = 1 << 0;
+const CR_PER: u32 = 1 << 1;
+const CR_MER: u32 = 1 << 2;
+const CR_PNB_SHIFT: u32 = 3;
+const CR_PNB_MASK: u32 = 0x7f << CR_PNB_SHIFT;
+const CR_STRT: u32 = 1 << 16;
+const CR_LOCK: u32 = 1 << 31;
+
+/// Double word offset into main flash of the last ECC correction.
+#[cfg(feature = "verify")]
+const ECCR_ADDR_MASK: u32 = 0x1_FFFF;
+#[cfg(feature = "verify")]
+const ECCR_SYSF: u32 = 1 << 20;
+#[cfg(feature = "verify")]
+const ECCR_ECCC: u32 = 1 << 30;
+#[cfg(feature = "verify")]
+const ECCR_ECCD: u32 = 1 << 31;
+
+/// Page and mass erase take up to ~25 ms (DS13105, tERASE/tME), doubled for margin.
+const ERASE_TIMEOUT_US: u32 = 50_000;
+/// A double word takes ~90 us to program.
+const PROGRAM_TIMEOUT_US: u32 = 1_000;
+
+/// Runs a controller sequence with interrupts masked and PRIMASK restored afterwards, so an
+/// interrupt the previous firmware left enabled can't preempt it halfway.
+fn masked<R>(sequence: impl FnOnce() -> R) -> R {
+    cortex_m::interrupt::free(|_| sequence())
+}
+
+pub fn unlock() -> Result<(), FlashError> {
+    if CR.read() & CR_LOCK != 0 {
+        masked(|| {
+            KEYR.write(KEY1);
+            KEYR.write(KEY2);
+        });
+    }
+    if CR.read() & CR_LOCK != 0 {
+        return Err(FlashError::Locked);
+    }
+    Ok(())
+}
+
+pub fn lock() {
+    CR.set_bits(CR_LOCK);
+    if cfg!(feature = "asserts") && CR.read() & CR_LOCK == 0 {
+        log::error!("Assertion failed: FLASH_CR still unlocked after lock()");
+    }
+}
+
+fn idle() -> bool {
+    SR.read() & SR_BSY == 0
+}
+
+fn wait_idle(timeout_us: u32) -> Result<(), FlashError> {
+    if !timeout::wait_us(timeout_us, idle) {
+        return Err(FlashError::Timeout);
+    }
+    check_errors()
+}
+
+/// Maps the sticky SR error flags to an error code, leaving them set for the error report.
+fn check_errors() -> Result<(), FlashError> {
+    let sr = SR.read();
+    let error = if sr & SR_WRPERR != 0 {
+        FlashError::WriteProtected
+    } else if sr & SR_PGAERR != 0 {
+        FlashError::ProgramAlignment
+    } else if sr & SR_SIZERR != 0 {
+        FlashError::ProgramSize
+    } else if sr & SR_PGSERR != 0 {
+        FlashError::ProgramSequence
+    } else if sr & SR_PROGERR != 0 {
+        FlashError::Programming
+    } else if sr & (SR_MISSERR | SR_FASTERR) != 0 {
+        FlashError::FastProgram
+    } else if sr & SR_ERRORS != 0 {
+        FlashError::Operation
+    } else {
+        return Ok(());
+    };
+    Err(error)
+}
+
+/// Waits for any previous operation and clears stale flags before starting a new one.
+fn prepare() -> Result<(), FlashError> {
+    // A locked controller silently ignores PER/PG/STRT, which would look like success.
+    if CR.read() & CR_LOCK != 0 {
+        return Err(FlashError::Locked);
+    }
+    if !timeout::wait_us(ERASE_TIMEOUT_US, idle) {
+        return Err(FlashError::Timeout);
+    }
+    // Every sequence clears its operation bit, even when it fails.
+    ensure!(
+        CR.read() & (CR_PG | CR_PER | CR_MER) == 0,
+        FlashError::Assert(codes::ASSERT_LOCK_STATE)
+    );
+    SR.write(SR_ERRORS | SR_EOP);
+    Ok(())
+}
+
+/// Called between hardware operations, where stopping leaves the controller idle.
+fn check_abort() -> Result<(), FlashError> {
+    if abort_requested() {
+        return Err(FlashError::Aborted);
+    }
+    Ok(())
+}
+
+/// Runs a controller sequence, also returning how long it took in microseconds; always 0 with
+/// `timeout-spin`, whose probes keep DWT for themselves.
+fn timed<R>(sequence: impl FnOnce() -> R) -> (R, u32) {
+    if cfg!(feature = "timeout-spin") {
+        return (sequence(), 0);
+    }
+    CycleCounter::enable();
+    let start = CycleCounter::now();
+    let result = sequence();
+    let cycles = CycleCounter::now().wrapping_sub(start);
+    (result, timeout::us_for_cycles(cycles))
+}
+
+fn check_range(addr: u32, len: u32) -> Result<(), FlashError> {
+    if addr < BASE || addr - BASE > SIZE || len > SIZE - (addr - BASE) {
+        return Err(FlashError::OutOfRange);
+    }
+    Ok(())
+}
+
+pub fn erase_all() -> Result<(), FlashError> {
+    check_abort()?;
+    prepare()?;
+    // The controller erases the whole bank in one go, so only start and end can be reported.
+    report_progress(Operation::EraseAll, 0, 1);
+    let (result, us) = timed(|| {
+        masked(|| {
+            CR.set_bits(CR_MER);
+            CR.set_bits(CR_STRT);
+            let result = wait_idle(ERASE_TIMEOUT_US);
+            CR.clear_bits(CR_MER);
+            result
+        })
+    });
+    report_progress(Operation::EraseAll, 1, 1);
+    log::info!("Chip erase took {} us", us);
+    result?;
+    stats::count(|c| c.chip_erases += 1);
+    Ok(())
+}
+
+pub fn erase_page(addr: u32) -> Result<(), FlashError> {
+    check_range(addr, PAGE_SIZE)?;
+    // The controller erases the containing page, which callers must not rely on.
+    ensure!(
+        addr.is_multiple_of(PAGE_SIZE),
+        FlashError::Assert(codes::ASSERT_ALIGNMENT)
+    );
+    prepare()?;
+
+    let page = (addr - BASE) / PAGE_SIZE;
+    ensure!(
+        page < SIZE / PAGE_SIZE,
+        FlashError::Assert(codes::ASSERT_PAGE_BOUNDS)
+    );
+    let (result, us) = timed(|| {
+        masked(|| {
+            CR.modify(|v| (v & !CR_PNB_MASK) | (page << CR_PNB_SHIFT) | CR_PER);
+            CR.set_bits(CR_STRT);
+            let result = wait_idle(ERASE_TIMEOUT_US);
+            CR.clear_bits(CR_PER);
+            result
+        })
+    });
+    log::trace!("Erase page {} at {:#010x} took {} us", page, addr, us);
+    result?;
+    stats::count(|c| {
+        c.sectors_erased += 1;
+        c.slowest_erase_us = c.slowest_erase_us.max(us);
+    });
+    Ok(())
+}
+
+/// Erases `count` consecutive pages from `addr`, passing the failing page to `on_error`.
+#[cfg(feature = "erase-sectors")]
+pub fn erase_pages(
+    addr: u32,
+    count: u32,
+    on_error: impl Fn(u32, FlashError) -> ErrorCode,
+) -> Result<(), ErrorCode> {
+    count
+        .checked_mul(PAGE_SIZE)
+        .ok_or(FlashError::OutOfRange)
+        .and_then(|len| check_range(addr, len))
+        .map_err(|e| on_error(addr, e))?;
+
+    (0..count).try_for_each(|i| {
+        report_progress(Operation::EraseSector, i, count);
+        check_abort().map_err(|e| on_error(addr + i * PAGE_SIZE, e))?;
+        let page = addr + i * PAGE_SIZE;
+        erase_page(page).map_err(|e| on_error(page, e))
+    })?;
+    report_progress(Operation::EraseSector, count, count);
+    Ok(())
+}
+
+/// Programs `data` in 64-bit double words, padding a short tail with the erased value.
+pub fn program(addr: u32, data: &[u8]) -> Result<(), FlashError> {
+    if !addr.is_multiple_of(8) {
+        return Err(FlashError::Alignment);
+    }
+    check_range(addr, data.len() as u32)?;
+    ensure!(
+        data.len() <= PROGRAM_PAGE_SIZE,
+        FlashError::Assert(codes::ASSERT_BUFFER_SIZE)
+    );
+    check_abort()?;
+    prepare()?;
+
+    let (result, us) = timed(|| {
+        masked(|| {
+            CR.set_bits(CR_PG);
+            let result = data.chunks(8).enumerate().try_for_each(|(i, chunk)| {
+                check_abort()?;
+                let mut double_word = [0xffu8; 8];
+                double_word[..chunk.len()].copy_from_slice(chunk);
+
+                let target = (addr as usize + i * 8) as *mut u32;
+                unsafe {
+                    target.write_volatile(u32::from_le_bytes([
+                        double_word[0],
+                        double_word[1],
+                        double_word[2],
+                        double_word[3],
+                    ]));
+                    target.add(1).write_volatile(u32::from_le_bytes([
+                        double_word[4],
+                        double_word[5],
+                        double_word[6],
+                        double_word[7],
+                    ]));
+                }
+                wait_idle(PROGRAM_TIMEOUT_US)
+            });
+            CR.clear_bits(CR_PG);
+            result
+        })
+    });
+    log::trace!(
+        "Program {} bytes at {:#010x} took {} us",
+        data.len(),
+        addr,
+        us
+    );
+    result?;
+    stats::count(|c| {
+        c.pages_programmed += 1;
+        c.slowest_program_us = c.slowest_program_us.max(us);
+    });
+    Ok(())
+}
+
+/// Bytes compared between abort checks.
+#[cfg(feature = "verify")]
+const VERIFY_CHUNK: usize = 0x800;
+
+/// Compares `size` bytes of flash at `addr` with `data`, or with the erased value if the host
+/// passes none, noting every difference in `mismatches`. Data that only matched thanks to an
+/// ECC correction in the range fails too, since the cell behind it is already weak.
+#[cfg(feature = "verify")]
+pub fn verify(
+    addr: u32,
+    size: u32,
+    data: Option<&[u8]>,
+    mismatches: &mut Mismatches,
+) -> Result<(), FlashError> {
+    check_range(addr, size)?;
+    let len = size as usize;
+    let expected = |i: usize| data.map_or(0xff, |data| data[i]);
+    if data.is_some_and(|data| data.len() < len) {
+        return Err(FlashError::InvalidArgument);
+    }
+
+    // Write-one-to-clear, leaving ECCD alone: a double error raises an NMI rather than being
+    // polled for.
+    ECCR.modify(|v| (v & !ECCR_ECCD) | ECCR_ECCC);
+    for start in (0..len).step_by(VERIFY_CHUNK) {
+        check_abort()?;
+        for i in start..(start + VERIFY_CHUNK).min(len) {
+            let address = addr + i as u32;
+            let actual = unsafe { (address as *const u8).read_volatile() };
+            if actual != expected(i) {
+                mismatches.push(address, expected(i), actual);
+            }
+        }
+    }
+    if mismatches.count != 0 {
+        return Err(FlashError::VerifyMismatch);
+    }
+    let eccr = ECCR.read();
+    let corrected = BASE + (eccr & ECCR_ADDR_MASK) * 8;
+    if eccr & (ECCR_ECCC | ECCR_SYSF) == ECCR_ECCC && (addr & !7..addr + size).contains(&corrected)
+    {
+        return Err(FlashError::EccCorrected);
+    }
+    Ok(())
+}
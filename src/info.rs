@@ -0,0 +1,133 @@
+//! Build identification and capability descriptors placed in their own link sections.
+
+/// Copies `src` into a zero-padded, NUL-terminated byte array at compile time.
+pub const fn fixed_str<const N: usize>(src: &str) -> [u8; N] {
+    let bytes = src.as_bytes();
+    assert!(
+        bytes.len() < N,
+        "string does not fit, one byte is kept for the NUL terminator"
+    );
+
+    let mut out = [0u8; N];
+    let mut i = 0;
+    while i < bytes.len() {
+        out[i] = bytes[i];
+        i += 1;
+    }
+    out
+}
+
+/// Human-readable algorithm version, e.g. `soul-flashalgo-stm32wl 0.1.0`.
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+#[link_section = "AlgoVersion"]
+pub static AlgoVersion: [u8; 64] = fixed_str(concat!(
+    env!("CARGO_PKG_NAME"),
+    " ",
+    env!("CARGO_PKG_VERSION")
+));
+
+/// Bits set in [`Capabilities`], one per optional feature compiled into this build.
+///
+/// The numbering is part of the host ABI, so bits for features this build lacks are kept.
+#[allow(dead_code)]
+pub mod caps {
+    pub const ERASE_CHIP: u32 = 1 << 0;
+    pub const VERIFY: u32 = 1 << 1;
+    pub const BLANK_CHECK: u32 = 1 << 2;
+    pub const SELF_TEST: u32 = 1 << 3;
+    pub const OPTION_BYTES: u32 = 1 << 4;
+    pub const FAST_PROGRAM: u32 = 1 << 5;
+    pub const ERASE_SECTORS: u32 = 1 << 6;
+    pub const DEVICE_INFO: u32 = 1 << 7;
+    pub const LOG_RING: u32 = 1 << 8;
+}
+
+const fn capabilities() -> u32 {
+    let mut flags = caps::ERASE_CHIP;
+    if cfg!(feature = "verify") {
+        flags |= caps::VERIFY;
+    }
+    if cfg!(feature = "self-test") {
+        flags |= caps::SELF_TEST;
+    }
+    if cfg!(feature = "erase-sectors") {
+        flags |= caps::ERASE_SECTORS;
+    }
+    if cfg!(feature = "device-info") {
+        flags |= caps::DEVICE_INFO;
+    }
+    if cfg!(feature = "log-ring") {
+        flags |= caps::LOG_RING;
+    }
+    flags
+}
+
+/// Capability bitmask so the host can adapt its flow without probing entry points.
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+#[link_section = "Capabilities"]
+pub static Capabilities: u32 = capabilities();
+
+const fn parse_u64(src: &str) -> u64 {
+    let bytes = src.as_bytes();
+    let mut value = 0u64;
+    let mut i = 0;
+    while i < bytes.len() {
+        assert!(
+            bytes[i].is_ascii_digit(),
+            "timestamp must be decimal seconds"
+        );
+        value = value * 10 + (bytes[i] - b'0') as u64;
+        i += 1;
+    }
+    value
+}
+
+/// Revision and toolchain that produced this binary, filled in by `build.rs`.
+#[repr(C)]
+pub struct BuildInfoDescription {
+    /// `git describe --always --dirty` of the source tree, or just the abbreviated commit and any
+    /// `-dirty` when a long tag makes that too long.
+    pub git_hash: [u8; 32],
+    /// Build time in seconds since the Unix epoch (or `SOURCE_DATE_EPOCH`).
+    pub timestamp: u64,
+    /// `rustc --version` of the compiler used, cut to the first 63 bytes.
+    pub rustc_version: [u8; 64],
+}
+
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+#[link_section = "BuildInfo"]
+pub static BuildInfo: BuildInfoDescription = BuildInfoDescription {
+    git_hash: fixed_str(env!("SOUL_BUILD_GIT_HASH")),
+    timestamp: parse_u64(env!("SOUL_BUILD_TIMESTAMP")),
+    rustc_version: fixed_str(env!("SOUL_BUILD_RUSTC")),
+};
+
+/// CRC-32s over the `DeviceData` and `SelfTestInfo` sections, so host tools can tell a
+/// corrupted or partially loaded image before trusting its memory map and test list.
+///
+/// Both descriptors come out of the `algorithm!` macro, which leaves nothing to hash at compile
+/// time; the block is built zeroed and `cargo xtask seal` patches it into the linked ELF.
+#[repr(C)]
+pub struct DescriptorCrcDescription {
+    /// The bytes `DCRC` once sealed, 0 in an image that never was.
+    pub magic: u32,
+    /// CRC-32/ISO-HDLC of the section contents, 0 for a section the image lacks.
+    pub device_data: u32,
+    pub self_test_info: u32,
+}
+
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+#[link_section = "DescriptorCrc"]
+pub static DescriptorCrc: DescriptorCrcDescription = DescriptorCrcDescription {
+    magic: 0,
+    device_data: 0,
+    self_test_info: 0,
+};
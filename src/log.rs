@@ -0,0 +1,142 @@
+//! Logging filtered at compile time by the `log-error`, `log-info` and `log-trace` features.
+//!
+//! Each feature enables its level and the ones above it. A disabled level's macros compile to a
+//! branch on a constant, so the optimiser drops the call along with its formatting code; with no
+//! level enabled the image carries neither log output nor `core::fmt` for it.
+//!
+//! Lines go to RTT, or with `log-semihosting` to the debugger's semihosting console instead, for
+//! fixtures that drive the algorithm through OpenOCD. Semihosting halts the core on every line,
+//! and without a debugger to service it the call faults, so it is for bring-up only.
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Off,
+    /// Failures the host would otherwise only see as an error code.
+    Error,
+    /// One line per entry point and the session summary.
+    Info,
+    /// Per-page detail of the program and erase paths, for bring-up.
+    Trace,
+}
+
+pub const LEVEL: Level = if cfg!(feature = "log-trace") {
+    Level::Trace
+} else if cfg!(feature = "log-info") {
+    Level::Info
+} else if cfg!(feature = "log-error") {
+    Level::Error
+} else {
+    Level::Off
+};
+
+/// Declares `init_rtt`, which sets up the RTT up channel the log macros, the `panic-record`
+/// handler and the `console` print to, and does nothing when none is compiled in;
+/// `log-semihosting` takes the first two off RTT. With `console` it also sets up the down
+/// channel the console reads, under the same name.
+///
+/// Every field is optional:
+///
+/// - `size`: buffer bytes (default 1024); raise it when trace logs overrun a slow host.
+/// - `mode`: a [`rtt_target::ChannelMode`] (default `NoBlockSkip`, dropping lines that don't
+///   fit); `BlockIfFull` loses nothing but stalls programming until the host drains the buffer.
+/// - `name`: what the host lists the channel as (default `"Terminal"`).
+macro_rules! rtt {
+    ({ $($fields:tt)* }) => {
+        $crate::log::rtt!(@ 1024 NoBlockSkip "Terminal"; $($fields)*);
+    };
+    (@ $size:literal $mode:ident $name:literal; size: $value:literal $(, $($rest:tt)*)?) => {
+        $crate::log::rtt!(@ $value $mode $name; $($($rest)*)?);
+    };
+    (@ $size:literal $mode:ident $name:literal; mode: $value:ident $(, $($rest:tt)*)?) => {
+        $crate::log::rtt!(@ $size $value $name; $($($rest)*)?);
+    };
+    (@ $size:literal $mode:ident $name:literal; name: $value:literal $(, $($rest:tt)*)?) => {
+        $crate::log::rtt!(@ $size $mode $value; $($($rest)*)?);
+    };
+    (@ $size:literal $mode:ident $name:literal;) => {
+        fn init_rtt() {
+            #[cfg(feature = "console")]
+            {
+                let channels = rtt_target::rtt_init! {
+                    up: {
+                        0: {
+                            size: $size
+                            mode: $mode
+                            name: $name
+                        }
+                    }
+                    down: {
+                        0: {
+                            size: 64
+                            name: $name
+                        }
+                    }
+                };
+                rtt_target::set_print_channel(channels.up.0);
+                $crate::console::attach(channels.down.0);
+            }
+            #[cfg(all(
+                not(any(feature = "console", feature = "log-semihosting")),
+                any(feature = "log-error", feature = "panic-record")
+            ))]
+            {
+                let channels = rtt_target::rtt_init! {
+                    up: {
+                        0: {
+                            size: $size
+                            mode: $mode
+                            name: $name
+                        }
+                    }
+                };
+                rtt_target::set_print_channel(channels.up.0);
+            }
+        }
+    };
+}
+
+/// Writes one line to the log backend, whatever the level.
+macro_rules! println {
+    ($($arg:tt)+) => {{
+        #[cfg(not(feature = "log-semihosting"))]
+        rtt_target::rprintln!($($arg)+);
+        #[cfg(feature = "log-semihosting")]
+        cortex_m_semihosting::hprintln!($($arg)+);
+    }};
+}
+
+/// A [`core::fmt::Write`] sink on the log backend, for text formatted by code shared with the
+/// console. It writes whatever it is given, whatever the level.
+pub struct Writer;
+
+impl core::fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        #[cfg(not(feature = "log-semihosting"))]
+        rtt_target::rprint!("{}", s);
+        #[cfg(feature = "log-semihosting")]
+        cortex_m_semihosting::hprint!("{}", s);
+        Ok(())
+    }
+}
+
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::log::LEVEL >= $level {
+            $crate::log::println!($($arg)+);
+        }
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log::log_at!($crate::log::Level::Error, $($arg)+) };
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log::log_at!($crate::log::Level::Info, $($arg)+) };
+}
+
+macro_rules! trace {
+    ($($arg:tt)+) => { $crate::log::log_at!($crate::log::Level::Trace, $($arg)+) };
+}
+
+pub(crate) use {error, info, log_at, println, rtt, trace};
@@ -0,0 +1,118 @@
+//! The last operations kept in a RAM ring, enabled by the `log-ring` feature.
+//!
+//! Each `Init`, erase and program call appends one [`LogRecord`] to the exported `LogRing`, so
+//! after a failure the host can dump what led up to it even with no RTT client attached. The
+//! ring isn't cleared by `Init`, only when the algorithm is loaded. Its geometry is published in
+//! the `LogRingInfo` section; like the mailboxes, the block itself is found by its symbol name,
+//! since the algorithm is position independent and can't store its own address.
+
+use flash_algorithm::ErrorCode;
+
+#[cfg(feature = "log-ring")]
+use crate::mailbox::Mailbox;
+use crate::mailbox::Operation;
+#[cfg(feature = "log-ring")]
+use crate::timeout::CycleCounter;
+
+/// Records the ring holds before the oldest is overwritten.
+#[cfg(feature = "log-ring")]
+pub const CAPACITY: usize = 32;
+
+#[cfg(feature = "log-ring")]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct LogRecord {
+    /// The [`Operation`] called.
+    pub operation: u32,
+    pub address: u32,
+    /// Zero on success, otherwise the returned error code.
+    pub status: u32,
+    /// Core cycles the call took, at the clock `Init` was given; 0 with `timeout-spin`.
+    pub cycles: u32,
+}
+
+#[cfg(feature = "log-ring")]
+#[repr(C)]
+pub struct Ring {
+    /// Records written since load; the newest is at `(head - 1) % CAPACITY`.
+    pub head: u32,
+    pub records: [LogRecord; CAPACITY],
+}
+
+#[cfg(feature = "log-ring")]
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+pub static LogRing: Mailbox<Ring> = Mailbox::new(Ring {
+    head: 0,
+    records: [LogRecord {
+        operation: 0,
+        address: 0,
+        status: 0,
+        cycles: 0,
+    }; CAPACITY],
+});
+
+/// Layout of `LogRing`, for host tools to dump it without their own copy of the constants.
+#[cfg(feature = "log-ring")]
+#[repr(C)]
+pub struct LogRingDescription {
+    /// The bytes `LRNG`.
+    pub magic: u32,
+    /// Bytes per [`LogRecord`], which follow the `head` word.
+    pub record_size: u32,
+    pub capacity: u32,
+}
+
+#[cfg(feature = "log-ring")]
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+#[link_section = "LogRingInfo"]
+pub static LogRingInfo: LogRingDescription = LogRingDescription {
+    magic: u32::from_le_bytes(*b"LRNG"),
+    record_size: core::mem::size_of::<LogRecord>() as u32,
+    capacity: CAPACITY as u32,
+};
+
+/// Runs `op`, appending its outcome and duration to the ring as `operation` on `address`. The
+/// duration is left at 0 with `timeout-spin`, whose probes keep DWT for themselves.
+#[cfg(feature = "log-ring")]
+pub fn measure(
+    operation: Operation,
+    address: u32,
+    op: impl FnOnce() -> Result<(), ErrorCode>,
+) -> Result<(), ErrorCode> {
+    let (result, cycles) = if cfg!(feature = "timeout-spin") {
+        (op(), 0)
+    } else {
+        CycleCounter::enable();
+        let start = CycleCounter::now();
+        let result = op();
+        (result, CycleCounter::now().wrapping_sub(start))
+    };
+
+    LogRing.update(|ring| {
+        ring.records[ring.head as usize % CAPACITY] = LogRecord {
+            operation: operation as u32,
+            address,
+            status: match result {
+                Ok(()) => 0,
+                Err(e) => e.get(),
+            },
+            cycles,
+        };
+        ring.head = ring.head.wrapping_add(1);
+    });
+    result
+}
+
+#[cfg(not(feature = "log-ring"))]
+#[inline(always)]
+pub fn measure(
+    _operation: Operation,
+    _address: u32,
+    op: impl FnOnce() -> Result<(), ErrorCode>,
+) -> Result<(), ErrorCode> {
+    op()
+}
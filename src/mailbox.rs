@@ -0,0 +1,231 @@
+//! Fixed RAM blocks the host reads back after a call returns.
+//!
+//! The algorithm stays position independent, so these aren't referenced from a descriptor;
+//! the host resolves them by their exported symbol names, as it already does for `Init` & co.
+
+use core::cell::UnsafeCell;
+#[cfg(feature = "panic-record")]
+use core::fmt;
+
+use flash_algorithm::ErrorCode;
+
+use crate::flash;
+use crate::log;
+use crate::stats;
+
+#[repr(transparent)]
+pub struct Mailbox<T>(UnsafeCell<T>);
+
+// The algorithm is single threaded and the host only touches mailboxes while the core is halted.
+unsafe impl<T> Sync for Mailbox<T> {}
+
+impl<T> Mailbox<T> {
+    pub const fn new(value: T) -> Self {
+        Self(UnsafeCell::new(value))
+    }
+
+    pub fn write(&self, value: T) {
+        unsafe { self.0.get().write_volatile(value) }
+    }
+
+    /// Modifies the block in place, for ones too large to build on the algorithm's small stack.
+    pub fn update(&self, f: impl FnOnce(&mut T)) {
+        f(unsafe { &mut *self.0.get() });
+        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+impl<T: Copy> Mailbox<T> {
+    pub fn read(&self) -> T {
+        unsafe { self.0.get().read_volatile() }
+    }
+}
+
+/// Formats into a fixed buffer, silently dropping whatever doesn't fit before the final NUL.
+#[cfg(feature = "panic-record")]
+pub struct Truncating<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+#[cfg(feature = "panic-record")]
+impl<'a> Truncating<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+}
+
+#[cfg(feature = "panic-record")]
+impl fmt::Write for Truncating<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = self.buf.len().saturating_sub(self.len + 1);
+        let n = s.len().min(room);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Operation that was running when an error got recorded.
+#[repr(u32)]
+#[derive(Clone, Copy)]
+pub enum Operation {
+    Init = 1,
+    EraseAll = 2,
+    EraseSector = 3,
+    ProgramPage = 4,
+    #[cfg_attr(not(feature = "verify"), allow(dead_code))]
+    Verify = 5,
+}
+
+/// Mismatching bytes kept per failed verify; the rest are only counted.
+pub const MAX_MISMATCHES: usize = 4;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Mismatch {
+    pub address: u32,
+    /// The byte the host expected, zero-extended.
+    pub expected: u32,
+    /// The byte read back from flash, zero-extended.
+    pub actual: u32,
+}
+
+/// The first mismatching bytes a verify found, enough to tell a corrupted image (scattered or
+/// wholesale differences) from a programming bug (bits stuck at the erased value).
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Mismatches {
+    /// Mismatching bytes found in total, including those past [`MAX_MISMATCHES`].
+    pub count: u32,
+    /// Valid up to `count`, in address order.
+    pub first: [Mismatch; MAX_MISMATCHES],
+}
+
+impl Mismatches {
+    pub const NONE: Self = Self {
+        count: 0,
+        first: [Mismatch {
+            address: 0,
+            expected: 0,
+            actual: 0,
+        }; MAX_MISMATCHES],
+    };
+
+    #[cfg(feature = "verify")]
+    pub fn push(&mut self, address: u32, expected: u8, actual: u8) {
+        if let Some(slot) = self.first.get_mut(self.count as usize) {
+            *slot = Mismatch {
+                address,
+                expected: expected as u32,
+                actual: actual as u32,
+            };
+        }
+        self.count = self.count.saturating_add(1);
+    }
+
+    #[cfg(feature = "verify")]
+    pub fn listed(&self) -> &[Mismatch] {
+        &self.first[..(self.count as usize).min(MAX_MISMATCHES)]
+    }
+}
+
+/// Context of the most recent failure; `code` is zero until something fails.
+#[repr(C)]
+pub struct ErrorReport {
+    pub code: u32,
+    pub operation: u32,
+    pub address: u32,
+    pub flash_sr: u32,
+    /// Filled in by a failed verify, cleared by every other failure.
+    pub mismatches: Mismatches,
+}
+
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+pub static ErrorMailbox: Mailbox<ErrorReport> = Mailbox::new(ErrorReport {
+    code: 0,
+    operation: 0,
+    address: 0,
+    flash_sr: 0,
+    mismatches: Mismatches::NONE,
+});
+
+/// Value the host writes into [`AbortRequest`] to cancel the running operation.
+pub const ABORT_MAGIC: u32 = 0xAB0B_7ED0;
+
+/// Written by the host while the core runs; polled at safe points and cleared by `Init`.
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+pub static AbortRequest: Mailbox<u32> = Mailbox::new(0);
+
+pub fn abort_requested() -> bool {
+    AbortRequest.read() == ABORT_MAGIC
+}
+
+/// Progress of the running operation, for the host to poll without halting the core.
+#[repr(C)]
+pub struct ProgressReport {
+    /// [`Operation`] in progress, zero before the first long operation starts.
+    pub operation: u32,
+    pub percent: u32,
+}
+
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+pub static ProgressMailbox: Mailbox<ProgressReport> = Mailbox::new(ProgressReport {
+    operation: 0,
+    percent: 0,
+});
+
+pub fn report_progress(operation: Operation, done: u32, total: u32) {
+    let percent = if total == 0 {
+        100
+    } else {
+        (done as u64 * 100 / total as u64) as u32
+    };
+    ProgressMailbox.write(ProgressReport {
+        operation: operation as u32,
+        percent,
+    });
+}
+
+/// Records `code` along with the current FLASH_SR and hands it back for `map_err` chains.
+pub fn record_error(operation: Operation, address: u32, code: ErrorCode) -> ErrorCode {
+    stats::count(|c| c.errors += 1);
+    let flash_sr = flash::SR.read();
+    log::error!(
+        "Operation {} failed at {:#010x}: code {:#x}, SR {:#010x}",
+        operation as u32,
+        address,
+        code.get(),
+        flash_sr
+    );
+    ErrorMailbox.write(ErrorReport {
+        code: code.get(),
+        operation: operation as u32,
+        address,
+        flash_sr,
+        mismatches: Mismatches::NONE,
+    });
+    code
+}
+
+/// Adds the bytes a failed verify found to the report [`record_error`] just wrote.
+#[cfg(feature = "verify")]
+pub fn record_mismatches(code: ErrorCode, mismatches: &Mismatches) -> ErrorCode {
+    log::error!("Verify found {} mismatching bytes", mismatches.count);
+    for m in mismatches.listed() {
+        log::error!(
+            "  {:#010x}: expected {:#04x}, read {:#04x}",
+            m.address,
+            m.expected,
+            m.actual
+        );
+    }
+    ErrorMailbox.update(|report| report.mismatches = *mismatches);
+    code
+}
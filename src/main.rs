@@ -1,11 +1,46 @@
 #![no_std]
 #![no_main]
 
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use flash_algorithm::*;
-use rtt_target::{rprintln, rtt_init_print};
+use mailbox::{record_error, Operation};
+use perf::Metric;
+
+mod asserts;
+#[cfg(feature = "console")]
+mod console;
+#[cfg(feature = "device-info")]
+mod device;
+mod error;
+#[cfg(feature = "fault-capture")]
+mod fault;
+mod flash;
+mod info;
+mod log;
+mod log_ring;
+mod mailbox;
+#[cfg(feature = "panic-record")]
+mod panic;
+mod perf;
+mod regs;
+#[cfg(feature = "self-test")]
+mod self_test;
+mod stack;
+mod stats;
+mod timeout;
+
+#[cfg(all(feature = "panic-record", feature = "panic-udf"))]
+compile_error!(
+    "`panic-record` replaces the library's panic handler, disable `panic-udf` to use it"
+);
 
 struct Algorithm;
 
+/// Set once `Init` has unlocked flash and cleared by `UnInit`, for the extern entry points that
+/// need a session.
+static SESSION: AtomicBool = AtomicBool::new(false);
+
 algorithm!(Algorithm, {
     target_name: "stm32wle5",
     flash_address: 0x08000000,
@@ -15,7 +50,7 @@ algorithm!(Algorithm, {
     ram_start_addr: 0x20000000,
     ram_end_addr: 0x20010000,
     sectors: [{
-        size: 0x400,
+        size: 0x800,
         address: 0x0,
     }],
     self_tests: [
@@ -27,35 +62,233 @@ algorithm!(Algorithm, {
     ],
 });
 
+// The library's `algorithm!` has no RTT fields, so the channel is configured here.
+log::rtt!({
+    size: 1024,
+    mode: NoBlockSkip,
+    name: "Terminal",
+});
+
 impl FlashAlgorithm for Algorithm {
-    fn new(_address: u32, _clock: u32, _function: Function) -> Result<Self, ErrorCode> {
-        rtt_init_print!();
-        rprintln!("Init");
-        // TODO: Add setup code for the flash algorithm.
+    fn new(address: u32, clock: u32, _function: Function) -> Result<Self, ErrorCode> {
+        init_rtt();
+        stack::paint();
+        #[cfg(feature = "fault-capture")]
+        fault::install();
+        log::info!("Init");
+        mailbox::AbortRequest.write(0);
+        stats::reset();
+        timeout::set_clock(clock);
+        log_ring::measure(Operation::Init, address, || Ok(flash::unlock()?))
+            .map_err(|e| record_error(Operation::Init, address, e))?;
+        SESSION.store(true, Ordering::Relaxed);
         Ok(Self)
     }
 
     fn erase_all(&mut self) -> Result<(), ErrorCode> {
-        rprintln!("Erase All");
-        // TODO: Add code here that erases the entire flash.
-        Err(ErrorCode::new(0x70d0).unwrap())
+        log::info!("Erase All");
+        stack::check()?;
+        log_ring::measure(Operation::EraseAll, flash::BASE, || {
+            Ok(perf::measure(Metric::Erase, flash::SIZE, flash::erase_all)?)
+        })
+        .map_err(|e| record_error(Operation::EraseAll, flash::BASE, e))
     }
 
     fn erase_sector(&mut self, addr: u32) -> Result<(), ErrorCode> {
-        rprintln!("Erase sector addr:{}", addr);
-        // TODO: Add code here that erases a page to flash.
-        Ok(())
+        log::info!("Erase sector addr:{}", addr);
+        stack::check()?;
+        log_ring::measure(Operation::EraseSector, addr, || {
+            Ok(perf::measure(Metric::Erase, flash::PAGE_SIZE, || {
+                flash::erase_page(addr)
+            })?)
+        })
+        .map_err(|e| record_error(Operation::EraseSector, addr, e))
     }
 
     fn program_page(&mut self, addr: u32, data: &[u8]) -> Result<(), ErrorCode> {
-        rprintln!("Program Page addr:{} size:{}", addr, data.len());
-        // TODO: Add code here that writes a page to flash.
+        log::info!("Program Page addr:{} size:{}", addr, data.len());
+        stack::check()?;
+        log_ring::measure(Operation::ProgramPage, addr, || {
+            Ok(perf::measure(Metric::Program, data.len() as u32, || {
+                flash::program(addr, data)
+            })?)
+        })
+        .map_err(|e| record_error(Operation::ProgramPage, addr, e))
+    }
+
+    /// Compares flash with `data`, or blank-checks it if there is none; on a mismatch the
+    /// first differing bytes are logged and left in `ErrorMailbox`.
+    #[cfg(feature = "verify")]
+    fn verify(&mut self, addr: u32, size: u32, data: Option<&[u8]>) -> Result<(), ErrorCode> {
+        log::info!("Verify addr:{} size:{}", addr, size);
+        stack::check()?;
+        let mut mismatches = mailbox::Mismatches::NONE;
+        log_ring::measure(Operation::Verify, addr, || {
+            Ok(perf::measure(Metric::Verify, size, || {
+                flash::verify(addr, size, data, &mut mismatches)
+            })?)
+        })
+        .map_err(|e| {
+            let e = record_error(Operation::Verify, addr, e);
+            mailbox::record_mismatches(e, &mismatches)
+        })
+    }
+}
+
+/// Converts a result into the 0-on-success convention of the extern entry points.
+#[cfg(any(feature = "console", feature = "erase-sectors", feature = "self-test"))]
+fn abi_result(result: Result<(), ErrorCode>) -> u32 {
+    match result {
+        Ok(()) => 0,
+        Err(e) => e.get(),
+    }
+}
+
+/// Fails with `NOT_INITIALIZED` outside an `Init`..`UnInit` session.
+#[cfg(any(
+    feature = "console",
+    feature = "erase-sectors",
+    feature = "self-test-record"
+))]
+fn require_session() -> Result<(), ErrorCode> {
+    if SESSION.load(Ordering::Relaxed) {
         Ok(())
+    } else {
+        Err(error::codes::NOT_INITIALIZED)
+    }
+}
+
+/// Erases `count` consecutive sectors starting at `addr`, returning 0 on success.
+///
+/// Must be called between `Init` and `UnInit`, like `EraseSector`, or fails with
+/// `NOT_INITIALIZED`.
+#[cfg(feature = "erase-sectors")]
+#[no_mangle]
+#[link_section = ".entry"]
+pub extern "C" fn EraseSectors(addr: u32, count: u32) -> u32 {
+    log::info!("Erase sectors addr:{} count:{}", addr, count);
+    if let Err(e) = require_session().and_then(|()| stack::check()) {
+        return e.get();
     }
+    let bytes = count.saturating_mul(flash::PAGE_SIZE);
+    abi_result(log_ring::measure(Operation::EraseSector, addr, || {
+        perf::measure(Metric::Erase, bytes, || {
+            flash::erase_pages(addr, count, |sector, e| {
+                record_error(Operation::EraseSector, sector, e.into())
+            })
+        })
+    }))
+}
+
+/// Copies the 96-bit unique device ID into the three words at `buf`, returning 0 on success.
+///
+/// # Safety
+///
+/// `buf` must be null or point to 12 writable bytes of host-provided RAM.
+#[cfg(feature = "device-info")]
+#[no_mangle]
+#[link_section = ".entry"]
+pub unsafe extern "C" fn ReadUID(buf: *mut u32) -> u32 {
+    if buf.is_null() {
+        return error::codes::INVALID_ARGUMENT.get();
+    }
+    for (i, word) in device::uid().into_iter().enumerate() {
+        unsafe { buf.add(i).write_unaligned(word) };
+    }
+    0
+}
+
+/// Copies the FLASH_SIZE register (main flash size in KiB) into the word at `buf`.
+///
+/// # Safety
+///
+/// `buf` must be null or point to 4 writable bytes of host-provided RAM.
+#[cfg(feature = "device-info")]
+#[no_mangle]
+#[link_section = ".entry"]
+pub unsafe extern "C" fn GetFlashSize(buf: *mut u32) -> u32 {
+    if buf.is_null() {
+        return error::codes::INVALID_ARGUMENT.get();
+    }
+    unsafe { buf.write_unaligned(device::flash_size_kib() as u32) };
+    0
+}
+
+/// Runs the self test advertised in `SelfTestInfo` under `id`, returning 0 on success.
+#[cfg(feature = "self-test")]
+#[no_mangle]
+#[link_section = ".entry"]
+pub extern "C" fn SelfTest(id: u32) -> u32 {
+    log::info!("Self test id:{}", id);
+    abi_result(stack::check().and_then(|()| self_test::run(id)))
+}
+
+/// Runs every registered self test whose bit isn't set in `SelfTestSkip` or, for the first 32,
+/// `skip_mask`, in registry order, except those flagged destructive or as needing a fixture or
+/// parameters, which only run alone through `SelfTest`.
+///
+/// Leaves a bitmap with bit `n` set if test `n` passed in `SelfTestPassed` and returns its first
+/// word; each test's status is left in `SelfTestStatuses` and the last one's details in
+/// `SelfTestMailbox`.
+#[cfg(feature = "self-test")]
+#[no_mangle]
+#[link_section = ".entry"]
+pub extern "C" fn SelfTestAll(skip_mask: u32) -> u32 {
+    log::info!("Self test all skip:{:#x}", skip_mask);
+    if stack::check().is_err() {
+        return 0;
+    }
+    self_test::run_all(skip_mask)
+}
+
+/// Programs the last `SelfTestAll` run, the device UID and `timestamp` into the flash page at
+/// `addr`, erasing it first; 0 picks the last page of main flash. Returns 0 on success.
+///
+/// Must be called between `Init` and `UnInit`, like `EraseSector`, after `SelfTestAll`, or fails
+/// with `NOT_INITIALIZED`.
+#[cfg(feature = "self-test-record")]
+#[no_mangle]
+#[link_section = ".entry"]
+pub extern "C" fn SelfTestRecord(addr: u32, timestamp: u32) -> u32 {
+    log::info!("Self test record addr:{} timestamp:{}", addr, timestamp);
+    abi_result(
+        require_session()
+            .and_then(|()| stack::check())
+            .and_then(|()| self_test::save_record(addr, timestamp)),
+    )
+}
+
+/// Serves the RTT bench console until it reads `exit`, returning 0, or the host aborts it.
+///
+/// Must be called between `Init` and `UnInit`, or fails with `NOT_INITIALIZED`; see `console` for
+/// the commands.
+#[cfg(feature = "console")]
+#[no_mangle]
+#[link_section = ".entry"]
+pub extern "C" fn Console() -> u32 {
+    log::info!("Console");
+    abi_result(
+        require_session()
+            .and_then(|()| stack::check())
+            .and_then(|()| console::run()),
+    )
 }
 
 impl Drop for Algorithm {
     fn drop(&mut self) {
-        // TODO: Add code here to uninitialize the flash algorithm.
+        SESSION.store(false, Ordering::Relaxed);
+        flash::lock();
+        #[cfg(feature = "fault-capture")]
+        fault::uninstall();
+        if stack::check().is_err() {
+            log::error!("Stack overflow detected");
+        }
+        #[cfg(feature = "stack-check")]
+        log::info!(
+            "Stack high-water mark: {} of {} bytes",
+            stack::high_water(),
+            stack::STACK_SIZE
+        );
+        stats::log_summary();
     }
 }
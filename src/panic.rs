@@ -0,0 +1,56 @@
+//! Panic handler recording the location and message, enabled by the `panic-record` feature.
+//!
+//! Replaces the library's `udf` handler so `unwrap()` failures during bring-up leave the
+//! formatted message in the exported `PanicMailbox` (and in the log) before the core halts.
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+
+use crate::log;
+use crate::mailbox::{Mailbox, Truncating};
+
+/// Set in [`PanicReport::magic`] once a panic got recorded.
+pub const PANIC_MAGIC: u32 = 0x9A41_C0DE;
+
+#[repr(C)]
+pub struct PanicReport {
+    pub magic: u32,
+    pub line: u32,
+    pub column: u32,
+    /// NUL-terminated, truncated to fit.
+    pub file: [u8; 64],
+    /// NUL-terminated, truncated to fit.
+    pub message: [u8; 128],
+}
+
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+pub static PanicMailbox: Mailbox<PanicReport> = Mailbox::new(PanicReport {
+    magic: 0,
+    line: 0,
+    column: 0,
+    file: [0; 64],
+    message: [0; 128],
+});
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    PanicMailbox.update(|report| {
+        report.file = [0; 64];
+        report.message = [0; 128];
+        if let Some(location) = info.location() {
+            report.line = location.line();
+            report.column = location.column();
+            let _ = Truncating::new(&mut report.file).write_str(location.file());
+        }
+        let _ = write!(Truncating::new(&mut report.message), "{}", info.message());
+        report.magic = PANIC_MAGIC;
+    });
+
+    log::println!("{}", info);
+
+    loop {
+        cortex_m::asm::bkpt();
+    }
+}
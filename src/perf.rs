@@ -0,0 +1,90 @@
+//! Per-operation cycle and byte counters, enabled by the `perf-metrics` feature.
+//!
+//! The totals accumulate in the exported `PerfStats` block across calls, so the host can read
+//! them after a whole flashing session and derive throughput per operation.
+
+#[cfg(feature = "perf-metrics")]
+use crate::mailbox::Mailbox;
+#[cfg(feature = "perf-metrics")]
+use crate::timeout::CycleCounter;
+
+#[derive(Clone, Copy)]
+pub enum Metric {
+    Erase,
+    Program,
+    #[cfg_attr(not(feature = "verify"), allow(dead_code))]
+    Verify,
+}
+
+#[cfg(feature = "perf-metrics")]
+#[repr(C)]
+pub struct OpStats {
+    pub calls: u32,
+    pub bytes: u32,
+    pub cycles: u64,
+}
+
+#[cfg(feature = "perf-metrics")]
+impl OpStats {
+    const fn new() -> Self {
+        Self {
+            calls: 0,
+            bytes: 0,
+            cycles: 0,
+        }
+    }
+}
+
+#[cfg(feature = "perf-metrics")]
+#[repr(C)]
+pub struct PerfReport {
+    /// Core clock the cycle counts were taken at, in Hz.
+    pub clock_hz: u32,
+    pub erase: OpStats,
+    pub program: OpStats,
+    pub verify: OpStats,
+}
+
+#[cfg(feature = "perf-metrics")]
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+pub static PerfStats: Mailbox<PerfReport> = Mailbox::new(PerfReport {
+    clock_hz: 0,
+    erase: OpStats::new(),
+    program: OpStats::new(),
+    verify: OpStats::new(),
+});
+
+/// Runs `op`, charging its cycles and `bytes` to `metric`. With `timeout-spin`, whose probes keep
+/// DWT for themselves, only calls and bytes are counted.
+#[cfg(feature = "perf-metrics")]
+pub fn measure<R>(metric: Metric, bytes: u32, op: impl FnOnce() -> R) -> R {
+    let (result, cycles) = if cfg!(feature = "timeout-spin") {
+        (op(), 0)
+    } else {
+        CycleCounter::enable();
+        let start = CycleCounter::now();
+        let result = op();
+        (result, CycleCounter::now().wrapping_sub(start))
+    };
+
+    PerfStats.update(|report| {
+        report.clock_hz = crate::timeout::clock_hz();
+        let stats = match metric {
+            Metric::Erase => &mut report.erase,
+            Metric::Program => &mut report.program,
+            Metric::Verify => &mut report.verify,
+        };
+        stats.calls += 1;
+        stats.bytes = stats.bytes.wrapping_add(bytes);
+        stats.cycles += cycles as u64;
+    });
+    result
+}
+
+#[cfg(not(feature = "perf-metrics"))]
+#[inline(always)]
+pub fn measure<R>(_metric: Metric, _bytes: u32, op: impl FnOnce() -> R) -> R {
+    op()
+}
@@ -0,0 +1,34 @@
+//! Minimal volatile register access for the handful of peripherals this algorithm touches.
+
+/// A 32-bit memory-mapped register at a fixed address.
+///
+/// The algorithm is the only code running on the core while the host has it loaded, so the
+/// accessors are safe: every `Reg` is built from the reference manual's fixed address map.
+#[derive(Clone, Copy)]
+pub struct Reg(usize);
+
+impl Reg {
+    pub const fn at(base: usize, offset: usize) -> Self {
+        Self(base + offset)
+    }
+
+    pub fn read(self) -> u32 {
+        unsafe { (self.0 as *const u32).read_volatile() }
+    }
+
+    pub fn write(self, value: u32) {
+        unsafe { (self.0 as *mut u32).write_volatile(value) }
+    }
+
+    pub fn modify(self, f: impl FnOnce(u32) -> u32) {
+        self.write(f(self.read()));
+    }
+
+    pub fn set_bits(self, mask: u32) {
+        self.modify(|v| v | mask);
+    }
+
+    pub fn clear_bits(self, mask: u32) {
+        self.modify(|v| v & !mask);
+    }
+}
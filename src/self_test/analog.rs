@@ -0,0 +1,453 @@
+//! ADC-based tests of the supply, the on-chip sensors, the DAC and the comparators, enabled by
+//! `self-test-analog` (RM0461, chapters 18 to 20).
+//!
+//! The readings are corrected with the factory calibration values in system memory, which ST
+//! takes at VDDA = 3.3 V and 30 °C (and 130 °C for the second temperature point).
+
+use flash_algorithm::ErrorCode;
+
+use super::gpio::Pin;
+use super::params::word;
+use super::snapshot::Snapshot;
+use super::table::{flags, group};
+use super::{check_deadline, rcc, SelfTestResult, Test};
+use crate::error::codes;
+use crate::regs::Reg;
+use crate::timeout;
+
+const ADC: usize = 0x4001_2400;
+const ADC_ISR: Reg = Reg::at(ADC, 0x00);
+const ADC_CR: Reg = Reg::at(ADC, 0x08);
+const ADC_CFGR2: Reg = Reg::at(ADC, 0x10);
+const ADC_SMPR: Reg = Reg::at(ADC, 0x14);
+const ADC_CHSELR: Reg = Reg::at(ADC, 0x28);
+const ADC_DR: Reg = Reg::at(ADC, 0x40);
+const ADC_CCR: Reg = Reg::at(ADC, 0x308);
+const ISR_ADRDY: u32 = 1 << 0;
+const ISR_EOC: u32 = 1 << 2;
+const ISR_CCRDY: u32 = 1 << 13;
+const CR_ADEN: u32 = 1 << 0;
+const CR_ADDIS: u32 = 1 << 1;
+const CR_ADSTART: u32 = 1 << 2;
+const CR_ADVREGEN: u32 = 1 << 28;
+const CR_ADCAL: u32 = 1 << 31;
+/// Synchronous clock PCLK / 4: no kernel clock to set up, and at most 12 MHz.
+const CFGR2_CKMODE_PCLK_DIV4: u32 = 0b10 << 30;
+/// 160.5 cycles, comfortably over the minimum sampling time of VREFINT and the sensor.
+const SMPR_SMP1_MAX: u32 = 0b111;
+const CCR_VREFEN: u32 = 1 << 22;
+const CCR_TSEN: u32 = 1 << 23;
+const APB2ENR_ADCEN: u32 = 1 << 9;
+
+const CHANNEL_TEMPERATURE: u32 = 12;
+const CHANNEL_VREFINT: u32 = 13;
+
+/// Factory VREFINT reading at VDDA = 3.3 V.
+const VREFINT_CAL: usize = 0x1FFF_75AA;
+const CAL_VDDA_MV: u32 = 3_300;
+/// Factory temperature sensor readings at 30 °C and 130 °C, VDDA = 3.3 V.
+const TS_CAL1: usize = 0x1FFF_75A8;
+const TS_CAL2: usize = 0x1FFF_75CA;
+const TS_CAL1_C: i64 = 30;
+const TS_CAL2_C: i64 = 130;
+
+const SAMPLES: u32 = 16;
+/// Full scale of both the ADC and the DAC, which are 12-bit.
+const FULL_SCALE: u32 = 0xFFF;
+
+const DAC: usize = 0x4000_7400;
+const DAC_CR: Reg = Reg::at(DAC, 0x00);
+const DAC_DHR12R1: Reg = Reg::at(DAC, 0x08);
+const DAC_MCR: Reg = Reg::at(DAC, 0x3C);
+const DAC_CR_EN1: u32 = 1 << 0;
+/// Normal mode, buffered, driving the pin.
+const DAC_MODE_PIN: u32 = 0b000;
+/// Normal mode, unbuffered: rail to rail, but only into a high-impedance load.
+const DAC_MODE_PIN_UNBUFFERED: u32 = 0b010;
+const DAC_MCR_MODE1_MASK: u32 = 0b111;
+const APB1ENR1_DACEN: u32 = 1 << 29;
+/// DAC_OUT1's pin, PA10, which is also ADC channel 6.
+const DAC_PIN: u8 = 0x0A;
+const CHANNEL_DAC_PIN: u32 = 6;
+/// Several times the buffered output's settling time to 1 LSB.
+const DAC_SETTLE_US: u32 = 20;
+
+/// The ADC, enabled and calibrated for the duration of [`Adc::with`].
+pub struct Adc(());
+
+impl Adc {
+    /// Powers up and calibrates the ADC with the internal channels in `ccr` (VREFEN, TSEN)
+    /// switched on, runs `f`, then powers it down and restores its clock and CCR.
+    pub fn with<R>(
+        ccr: u32,
+        f: impl FnOnce(&mut Adc) -> Result<R, ErrorCode>,
+    ) -> Result<R, ErrorCode> {
+        rcc::with_clock(rcc::APB2ENR, APB2ENR_ADCEN, || {
+            let _ccr = Snapshot::registers([ADC_CCR]);
+            let mut adc = Adc(());
+            Self::enable(ccr)?;
+            f(&mut adc)
+        })
+    }
+
+    fn enable(ccr: u32) -> Result<(), ErrorCode> {
+        ADC_CFGR2.write(CFGR2_CKMODE_PCLK_DIV4);
+        ADC_CCR.set_bits(ccr);
+        ADC_CR.write(CR_ADVREGEN);
+        // t_ADCVREG_STUP is 20 us; VREFINT and the sensor need about as long to start.
+        timeout::wait_us(20, || false);
+        ADC_CR.set_bits(CR_ADCAL);
+        wait(|| ADC_CR.read() & CR_ADCAL == 0)?;
+        ADC_ISR.write(ISR_ADRDY);
+        ADC_CR.set_bits(CR_ADEN);
+        wait(|| ADC_ISR.read() & ISR_ADRDY != 0)?;
+        ADC_SMPR.write(SMPR_SMP1_MAX);
+        Ok(())
+    }
+
+    /// The average of [`SAMPLES`] conversions of `channel`, as a 12-bit value.
+    pub fn sample(&mut self, channel: u32) -> Result<u32, ErrorCode> {
+        ADC_ISR.write(ISR_CCRDY);
+        ADC_CHSELR.write(1 << channel);
+        wait(|| ADC_ISR.read() & ISR_CCRDY != 0)?;
+        let mut sum = 0;
+        for _ in 0..SAMPLES {
+            ADC_CR.set_bits(CR_ADSTART);
+            wait(|| ADC_ISR.read() & ISR_EOC != 0)?;
+            sum += ADC_DR.read() & 0xFFF;
+        }
+        Ok(sum / SAMPLES)
+    }
+
+    /// VDDA in millivolts, from a VREFINT conversion and its calibration value; needs VREFEN.
+    pub fn vdda_mv(&mut self) -> Result<(u32, u32), ErrorCode> {
+        let raw = self.sample(CHANNEL_VREFINT)?;
+        Ok((CAL_VDDA_MV * calibration(VREFINT_CAL) / raw.max(1), raw))
+    }
+}
+
+impl Drop for Adc {
+    /// Powers the ADC down however the test ended, before its CCR and clock go back.
+    fn drop(&mut self) {
+        if ADC_CR.read() & CR_ADEN != 0 {
+            ADC_CR.set_bits(CR_ADDIS);
+            timeout::wait_us(100, || ADC_CR.read() & CR_ADEN == 0);
+        }
+        ADC_CR.write(0);
+    }
+}
+
+/// A 16-bit factory calibration value from system memory.
+fn calibration(addr: usize) -> u32 {
+    unsafe { (addr as *const u16).read_volatile() as u32 }
+}
+
+/// Enables DAC channel 1 in `mode` (DAC_MCR.MODE1) for the duration of `f`, then restores the
+/// DAC and its clock.
+fn with_dac<R>(mode: u32, f: impl FnOnce() -> R) -> R {
+    rcc::with_clock(rcc::APB1ENR1, APB1ENR1_DACEN, || {
+        // MODE1 only accepts writes while the channel is off, which the snapshot also respects.
+        let _dac = Snapshot::peripheral([DAC_MCR, DAC_DHR12R1, DAC_CR]);
+        DAC_CR.write(0);
+        DAC_MCR.modify(|v| (v & !DAC_MCR_MODE1_MASK) | mode);
+        DAC_CR.write(DAC_CR_EN1);
+        f()
+    })
+}
+
+/// Sets the DAC output, with no trigger enabled, and waits for it to settle.
+fn dac_output(code: u32) {
+    DAC_DHR12R1.write(code);
+    timeout::wait_us(DAC_SETTLE_US, || false);
+}
+
+fn wait(mut done: impl FnMut() -> bool) -> Result<(), ErrorCode> {
+    while !done() {
+        check_deadline()?;
+    }
+    Ok(())
+}
+
+pub const VDDA: Test = Test {
+    id: 0x0101,
+    flags: 0,
+    group: group::POWER,
+    expected_ms: 2,
+    run: vdda,
+};
+
+/// Measures VDDA through VREFINT and checks it against the limits in `[min_mv, max_mv]`,
+/// default 1800 to 3600 mV, the WLE5 operating range. Values: VDDA in mV, VREFINT reading,
+/// VREFINT_CAL.
+fn vdda(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let min_mv = word(params, 0).unwrap_or(1_800);
+    let max_mv = word(params, 1).unwrap_or(3_600);
+
+    let (mv, raw) = Adc::with(CCR_VREFEN, |adc| adc.vdda_mv())?;
+    result.value(mv);
+    result.value(raw);
+    result.value(calibration(VREFINT_CAL));
+    result.message(format_args!("VDDA {} mV", mv));
+    if !(min_mv..=max_mv).contains(&mv) {
+        return Err(codes::ANALOG_RANGE);
+    }
+    Ok(())
+}
+
+pub const TEMPERATURE: Test = Test {
+    id: 0x0521,
+    flags: 0,
+    group: group::PERIPHERALS,
+    expected_ms: 2,
+    run: temperature,
+};
+
+/// Reads the temperature sensor, corrected for the actual VDDA, and interpolates between both
+/// factory calibration points.
+///
+/// Parameters: `[min_c, max_c]` as signed degrees Celsius, default 0 to 60; a reading outside a
+/// factory floor's plausible range points at the sensor or the reference rather than the room.
+/// Calibration points that are blank or out of order fail the same way. Values: temperature in
+/// hundredths of a degree (signed), sensor reading, TS_CAL1, TS_CAL2, VDDA in mV.
+fn temperature(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let min_c = word(params, 0).map_or(0, |v| v as i32);
+    let max_c = word(params, 1).map_or(60, |v| v as i32);
+
+    let (raw, vdda_mv) = Adc::with(CCR_VREFEN | CCR_TSEN, |adc| {
+        let (vdda_mv, _) = adc.vdda_mv()?;
+        Ok((adc.sample(CHANNEL_TEMPERATURE)?, vdda_mv))
+    })?;
+    let (cal1, cal2) = (calibration(TS_CAL1), calibration(TS_CAL2));
+    if cal1 == 0 || cal2 == 0xFFFF || cal2 <= cal1 {
+        result.message(format_args!("bad TS_CAL {} / {}", cal1, cal2));
+        return Err(codes::ANALOG_RANGE);
+    }
+
+    // Scale the reading to what it would have been at VDDA = 3.3 V, then interpolate.
+    let scaled = raw as i64 * vdda_mv as i64 - cal1 as i64 * CAL_VDDA_MV as i64;
+    let span = (cal2 - cal1) as i64 * CAL_VDDA_MV as i64;
+    let centi_c = ((TS_CAL2_C - TS_CAL1_C) * 100 * scaled / span + TS_CAL1_C * 100) as i32;
+    result.value(centi_c as u32);
+    result.value(raw);
+    result.value(cal1);
+    result.value(cal2);
+    result.value(vdda_mv);
+    let sign = if centi_c < 0 { "-" } else { "" };
+    let abs = centi_c.unsigned_abs();
+    result.message(format_args!(
+        "{}{}.{:02} C at VDDA {} mV",
+        sign,
+        abs / 100,
+        abs % 100,
+        vdda_mv
+    ));
+    if !(min_c * 100..=max_c * 100).contains(&centi_c) {
+        return Err(codes::ANALOG_RANGE);
+    }
+    Ok(())
+}
+
+pub const DAC_READBACK: Test = Test {
+    id: 0x0522,
+    flags: 0,
+    group: group::PERIPHERALS,
+    expected_ms: 5,
+    run: dac_readback,
+};
+
+/// Codes the DAC steps through, clear of the output buffer's headroom at either rail.
+const DAC_CODES: [u32; 5] = [0x200, 0x600, 0x800, 0xA00, 0xE00];
+
+/// Steps the DAC through five codes and reads each back with the ADC; both use VDDA as their
+/// reference, so a reading should match its code.
+///
+/// Parameters: `[channel, max_error]`. By default the ADC reads channel 6, the DAC's own pin
+/// PA10, which then needs nothing else on it; a board looping DAC_OUT1 to another ADC input
+/// names that channel, whose pin must be in analog mode, as out of reset. `max_error` in LSB
+/// (default 40) bounds both the largest difference from a code and the linearity error, the
+/// largest distance from the line through the first and last readings. Values: largest error,
+/// linearity error, then the reading of each code.
+fn dac_readback(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let channel = word(params, 0).unwrap_or(CHANNEL_DAC_PIN);
+    let max_error = word(params, 1).unwrap_or(40);
+    if channel >= CHANNEL_TEMPERATURE {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+
+    let pin = Pin::from_id(DAC_PIN).ok_or(codes::INVALID_ARGUMENT)?;
+    let saved = pin.save();
+    pin.analog();
+    let mut readings = [0; DAC_CODES.len()];
+    let outcome = with_dac(DAC_MODE_PIN, || {
+        Adc::with(0, |adc| {
+            for (reading, &code) in readings.iter_mut().zip(&DAC_CODES) {
+                dac_output(code);
+                *reading = adc.sample(channel)?;
+                check_deadline()?;
+            }
+            Ok(())
+        })
+    });
+    saved.restore();
+    outcome?;
+
+    let error = readings
+        .iter()
+        .zip(&DAC_CODES)
+        .map(|(&reading, &code)| reading.abs_diff(code))
+        .max()
+        .unwrap_or(0);
+    let (first, last) = (readings[0] as i32, readings[DAC_CODES.len() - 1] as i32);
+    let span = (DAC_CODES[DAC_CODES.len() - 1] - DAC_CODES[0]) as i32;
+    let linearity = readings
+        .iter()
+        .zip(&DAC_CODES)
+        .map(|(&reading, &code)| {
+            let fit = first + (last - first) * (code - DAC_CODES[0]) as i32 / span;
+            (reading as i32).abs_diff(fit)
+        })
+        .max()
+        .unwrap_or(0);
+    result.value(error);
+    result.value(linearity);
+    for reading in readings {
+        result.value(reading);
+    }
+    result.message(format_args!(
+        "DAC error up to {} LSB, linearity {} LSB",
+        error, linearity
+    ));
+    if error > max_error || linearity > max_error {
+        return Err(codes::ANALOG_RANGE);
+    }
+    Ok(())
+}
+
+/// COMP1_CSR and COMP2_CSR; like SYSCFG, the comparators have no clock enable of their own.
+const COMP_CSR: [Reg; 2] = [Reg::at(0x4001_0200, 0x00), Reg::at(0x4001_0200, 0x04)];
+const CSR_EN: u32 = 1 << 0;
+const CSR_INMSEL_SHIFT: u32 = 4;
+const CSR_INPSEL_SHIFT: u32 = 7;
+const CSR_BRGEN: u32 = 1 << 22;
+const CSR_SCALEN: u32 = 1 << 23;
+const CSR_VALUE: u32 = 1 << 30;
+const CSR_LOCK: u32 = 1 << 31;
+/// INMSEL 0 to 3 select a quarter, half, three quarters and all of VREFINT.
+const SCALER_STEPS: usize = 4;
+/// The VREFINT scaler's start-up time, with margin.
+const SCALER_START_US: u32 = 200;
+
+pub const COMPARATOR: Test = Test {
+    id: 0x0523,
+    flags: flags::REQUIRES_FIXTURE | flags::REQUIRES_PARAMS,
+    group: group::PERIPHERALS,
+    expected_ms: 5,
+    run: comparator,
+};
+
+/// Sweeps a comparator's plus input with the DAC against each tap of the VREFINT scaler on its
+/// minus input and checks it trips where VREFINT's factory value says it should.
+///
+/// The comparators only take the DAC on their minus input, so the fixture loops DAC_OUT1 (PA10)
+/// to the plus input instead. Parameters: `[comparator, inpsel, tolerance_mv]`; comparator 0 is
+/// COMP1 and 1 COMP2, `inpsel` the plus input the jumper lands on (0 to 2, default 0), whose pin
+/// must be in analog mode, as out of reset. Each threshold must be within `tolerance_mv`
+/// (default 40) of its tap's voltage. Values: VDDA in mV, VREFINT in mV, then the threshold
+/// found for each tap in mV, smallest first.
+fn comparator(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let csr = word(params, 0)
+        .and_then(|index| COMP_CSR.get(index as usize).copied())
+        .ok_or(codes::INVALID_ARGUMENT)?;
+    let inpsel = word(params, 1).unwrap_or(0);
+    let tolerance_mv = word(params, 2).unwrap_or(40);
+    if inpsel > 2 {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+    if csr.read() & CSR_LOCK != 0 {
+        result.message(format_args!("comparator locked until reset"));
+        return Err(codes::INVALID_ARGUMENT);
+    }
+
+    let (vdda_mv, _) = Adc::with(CCR_VREFEN, |adc| adc.vdda_mv())?;
+    let vrefint_mv = CAL_VDDA_MV * calibration(VREFINT_CAL) / FULL_SCALE;
+    let pin = Pin::from_id(DAC_PIN).ok_or(codes::INVALID_ARGUMENT)?;
+    let mut thresholds = [0; SCALER_STEPS];
+    let switched = {
+        let _pin = pin.save();
+        let _csr = Snapshot::registers([csr]);
+        pin.analog();
+        with_dac(DAC_MODE_PIN_UNBUFFERED, || {
+            sweep(csr, inpsel, vdda_mv, &mut thresholds)
+        })
+    };
+
+    result.value(vdda_mv);
+    result.value(vrefint_mv);
+    for threshold in thresholds {
+        result.value(threshold);
+    }
+    if !switched? {
+        result.message(format_args!("comparator output doesn't follow the DAC"));
+        return Err(codes::ANALOG_RANGE);
+    }
+    let off = thresholds.iter().enumerate().any(|(i, &threshold)| {
+        let expected = vrefint_mv * (i as u32 + 1) / SCALER_STEPS as u32;
+        threshold.abs_diff(expected) > tolerance_mv
+    });
+    result.message(format_args!(
+        "thresholds {} / {} / {} / {} mV, VREFINT {} mV",
+        thresholds[0], thresholds[1], thresholds[2], thresholds[3], vrefint_mv
+    ));
+    if off {
+        return Err(codes::ANALOG_RANGE);
+    }
+    Ok(())
+}
+
+/// Finds the threshold of every scaler tap in mV, returning `false` as soon as one of them
+/// doesn't switch at all.
+fn sweep(
+    csr: Reg,
+    inpsel: u32,
+    vdda_mv: u32,
+    thresholds: &mut [u32; SCALER_STEPS],
+) -> Result<bool, ErrorCode> {
+    for (inmsel, threshold) in thresholds.iter_mut().enumerate() {
+        csr.write(
+            CSR_SCALEN
+                | CSR_BRGEN
+                | inpsel << CSR_INPSEL_SHIFT
+                | (inmsel as u32) << CSR_INMSEL_SHIFT,
+        );
+        csr.set_bits(CSR_EN);
+        timeout::wait_us(SCALER_START_US, || false);
+        match trip_code(csr)? {
+            Some(code) => *threshold = code * vdda_mv / FULL_SCALE,
+            None => return Ok(false),
+        }
+    }
+    Ok(true)
+}
+
+/// The lowest DAC code driving the comparator output high, found by bisection between a code
+/// where it must be low and one where it must be high; `None` if it isn't at either end.
+fn trip_code(csr: Reg) -> Result<Option<u32>, ErrorCode> {
+    let high = |code| {
+        dac_output(code);
+        csr.read() & CSR_VALUE != 0
+    };
+    if high(0) || !high(FULL_SCALE) {
+        return Ok(None);
+    }
+    let (mut low_code, mut high_code) = (0, FULL_SCALE);
+    while high_code - low_code > 1 {
+        let code = (low_code + high_code) / 2;
+        if high(code) {
+            high_code = code;
+        } else {
+            low_code = code;
+        }
+        check_deadline()?;
+    }
+    Ok(Some(high_code))
+}
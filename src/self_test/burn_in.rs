@@ -0,0 +1,206 @@
+//! Board-level burn-in, enabled by `self-test-burn-in`.
+//!
+//! One long test keeps the core, SRAM and, with `self-test-radio` also enabled, the radio's PA
+//! busy at once for as long as the host asks, using the algorithm binary already loaded for
+//! programming. It stops at the first fault and records what failed and when.
+
+use flash_algorithm::ErrorCode;
+
+use super::cpu;
+use super::deadline;
+use super::params::word;
+#[cfg(feature = "self-test-radio")]
+use super::radio::Radio;
+use super::ram::{self, Window};
+use super::table::group;
+use super::{check_deadline, SelfTestResult, Test};
+use crate::error::codes;
+use crate::regs::Reg;
+use crate::timeout::{self, CycleCounter};
+
+/// Refreshing an IWDG that was never started has no effect, so the key is written regardless.
+const IWDG_KR: Reg = Reg::at(0x4000_3000, 0x00);
+const KEY_REFRESH: u32 = 0xAAAA;
+
+/// SRAM written and read back per loop; the loop walks the window a chunk at a time.
+const CHUNK_BYTES: u32 = 1_024;
+/// Calls are capped so the deadline, counted in 32-bit CYCCNT cycles, can't wrap; longer
+/// burn-in is a host loop of calls.
+const MAX_DURATION_MS: u32 = 60_000;
+
+pub const BURN_IN: Test = Test {
+    id: 0x0561,
+    flags: 0,
+    group: group::PERIPHERALS,
+    expected_ms: 1_000,
+    run: burn_in,
+};
+
+/// What stopped the burn-in early.
+enum Fault {
+    /// Operand pair index, digest computed, digest expected.
+    Alu(u32, u32, u32),
+    /// Address, expected and read word.
+    Ram(u32, u32, u32),
+    Other(ErrorCode),
+}
+
+impl From<ErrorCode> for Fault {
+    fn from(e: ErrorCode) -> Self {
+        Fault::Other(e)
+    }
+}
+
+/// Loop counts so far, reported whether the burn-in passes or fails.
+#[derive(Default)]
+struct Progress {
+    elapsed_ms: u32,
+    loops: u32,
+    bursts: u32,
+}
+
+/// Loops the CPU test's ALU digests and a pattern write and read-back of the next chunk of the
+/// RAM window for `duration_ms`, refreshing the IWDG each loop, while keying the radio's carrier
+/// on for one burst every `period_ms`.
+///
+/// Parameters: `[duration_ms, period_ms, frequency_hz, power_dbm, burst_ms, options, start,
+/// end]`; `duration_ms` is 1 to 60000 (default 1000) and the host must derive its call timeout
+/// from it rather than the declared duration. A zero or missing `frequency_hz` leaves the radio
+/// alone; otherwise the four words from it are the RF tests' parameters, needing
+/// `self-test-radio`, with the duration as the burst length, shorter than `period_ms` (default
+/// 5000). `start` and `end` pick the RAM window as for the RAM tests. The WWDG must be off.
+/// Values: window start and end, elapsed ms, loops, bursts, then for a RAM or ALU fault what the
+/// RAM tests or the CPU test report.
+fn burn_in(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let duration_ms = word(params, 0).unwrap_or(1_000);
+    let period_ms = word(params, 1).unwrap_or(5_000);
+    let radio = word(params, 2).unwrap_or(0) != 0;
+    let window = ram::window(params.get(24..).unwrap_or(&[]), result)?;
+    if !(1..=MAX_DURATION_MS).contains(&duration_ms) || period_ms == 0 {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+    deadline::extend(duration_ms);
+
+    let mut progress = Progress::default();
+    let outcome = if radio {
+        bursts(params, window, duration_ms, period_ms, &mut progress)
+    } else {
+        stress(window, duration_ms, &mut progress, |_| Ok(()))
+    };
+
+    result.value(progress.elapsed_ms);
+    result.value(progress.loops);
+    result.value(progress.bursts);
+    match outcome {
+        Ok(()) => {
+            result.message(format_args!(
+                "{} loops, {} bursts in {} ms",
+                progress.loops, progress.bursts, progress.elapsed_ms
+            ));
+            Ok(())
+        }
+        Err(Fault::Alu(index, got, expected)) => {
+            result.value(index);
+            result.message(format_args!(
+                "ALU digest {:#010x}, expected {:#010x}",
+                got, expected
+            ));
+            Err(codes::CORE_FAULT)
+        }
+        Err(Fault::Ram(addr, expected, read)) => Err(ram::mismatch(result, addr, expected, read)),
+        Err(Fault::Other(e)) => {
+            result.message(format_args!("stopped after {} ms", progress.elapsed_ms));
+            Err(e)
+        }
+    }
+}
+
+/// Runs [`stress`] with the radio keyed on for the first `burst_ms` of every `period_ms`.
+#[cfg(feature = "self-test-radio")]
+fn bursts(
+    params: &[u8],
+    window: Window,
+    duration_ms: u32,
+    period_ms: u32,
+    progress: &mut Progress,
+) -> Result<(), Fault> {
+    let mut outcome = Ok(());
+    let setup = Radio::with(|radio| {
+        let burst_ms = radio.prepare_bursts(params, 2)?;
+        if burst_ms >= period_ms {
+            return Err(codes::INVALID_ARGUMENT);
+        }
+        let mut keyed = false;
+        outcome = stress(window, duration_ms, progress, |progress| {
+            let on = progress.elapsed_ms % period_ms < burst_ms;
+            if on != keyed {
+                radio.carrier(on)?;
+                keyed = on;
+                progress.bursts += on as u32;
+            }
+            Ok(())
+        });
+        radio.carrier(false)
+    });
+    outcome?;
+    setup.map_err(Fault::Other)
+}
+
+#[cfg(not(feature = "self-test-radio"))]
+fn bursts(
+    _params: &[u8],
+    _window: Window,
+    _duration_ms: u32,
+    _period_ms: u32,
+    _progress: &mut Progress,
+) -> Result<(), Fault> {
+    Err(Fault::Other(codes::INVALID_ARGUMENT))
+}
+
+/// The burn-in loop proper, calling `each` once per loop with the progress so far.
+fn stress(
+    window: Window,
+    duration_ms: u32,
+    progress: &mut Progress,
+    mut each: impl FnMut(&mut Progress) -> Result<(), ErrorCode>,
+) -> Result<(), Fault> {
+    let cycles_per_ms = timeout::cycles_for_us(1_000).max(1) as u64;
+    let mut elapsed = 0u64;
+    CycleCounter::enable();
+    let mut last = CycleCounter::now();
+    let mut chunk = window.start;
+    while progress.elapsed_ms < duration_ms {
+        IWDG_KR.write(KEY_REFRESH);
+        each(progress)?;
+        if let Some((index, got, expected)) = cpu::alu_fault() {
+            return Err(Fault::Alu(index, got, expected));
+        }
+        let end = (chunk + CHUNK_BYTES).min(window.end);
+        traffic(Window { start: chunk, end }, progress.loops)?;
+        chunk = if end == window.end { window.start } else { end };
+        check_deadline()?;
+
+        progress.loops += 1;
+        let now = CycleCounter::now();
+        elapsed += now.wrapping_sub(last) as u64;
+        last = now;
+        progress.elapsed_ms = (elapsed / cycles_per_ms) as u32;
+    }
+    Ok(())
+}
+
+/// Writes a `seed`-dependent pattern over `chunk` and reads it back.
+fn traffic(chunk: Window, seed: u32) -> Result<(), Fault> {
+    let pattern = |addr: u32| addr.wrapping_mul(0x9E37_79B9) ^ seed.rotate_left(seed & 31);
+    let words = (chunk.start..chunk.end).step_by(4);
+    for addr in words.clone() {
+        unsafe { (addr as *mut u32).write_volatile(pattern(addr)) };
+    }
+    for addr in words {
+        let read = unsafe { (addr as *const u32).read_volatile() };
+        if read != pattern(addr) {
+            return Err(Fault::Ram(addr, pattern(addr), read));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,399 @@
+//! Oscillator start-up and accuracy tests, enabled by `self-test-clocks`.
+//!
+//! Frequencies are compared with TIM16/TIM17 input capture, since their TI1 inputs can be
+//! routed internally to LSI, LSE and HSE/32 (RM0461, section 26.4). Every oscillator a test
+//! starts is stopped again, and the system clock is switched back to its previous source.
+
+use flash_algorithm::ErrorCode;
+
+use super::params::word;
+use super::table::group;
+use super::{check_deadline, rcc, SelfTestResult, Test};
+use crate::error::codes;
+use crate::regs::Reg;
+use crate::timeout::{self, CycleCounter};
+
+const CR_MSION: u32 = 1 << 0;
+const CR_MSIRDY: u32 = 1 << 1;
+const CR_HSION: u32 = 1 << 8;
+const CR_HSIRDY: u32 = 1 << 10;
+const CR_HSEON: u32 = 1 << 16;
+pub const CR_HSERDY: u32 = 1 << 17;
+/// Powers the TCXO from PB0-VDDTCXO; only writable while HSEON is clear.
+const CR_HSEBYPPWR: u32 = 1 << 21;
+const CR_PLLON: u32 = 1 << 24;
+const CR_PLLRDY: u32 = 1 << 25;
+
+const RCC_CFGR: Reg = Reg::at(0x5800_0000, 0x08);
+const CFGR_SW_MASK: u32 = 0b11;
+const CFGR_SW_HSI16: u32 = 0b01;
+const CFGR_SWS_SHIFT: u32 = 2;
+/// HPRE and PPRE2, so TIM16/TIM17 run at SYSCLK once both are 0.
+const CFGR_PRESCALERS: u32 = 0xF << 4 | 0b111 << 11;
+
+const APB2ENR_TIM16EN: u32 = 1 << 17;
+const APB2ENR_TIM17EN: u32 = 1 << 18;
+
+/// General-purpose timer with the capture inputs the tests need.
+#[derive(Clone, Copy)]
+pub enum Timer {
+    /// TI1 from LSI (`TISEL` 1) or LSE (2).
+    Tim16,
+    /// TI1 from HSE/32 (`TISEL` 2).
+    Tim17,
+}
+
+const TISEL_LSI: u32 = 0b0001;
+const TISEL_LSE: u32 = 0b0010;
+const TISEL_HSE_DIV32: u32 = 0b0010;
+
+const TIM_CR1: usize = 0x00;
+const TIM_SR: usize = 0x10;
+const TIM_CCMR1: usize = 0x18;
+const TIM_CCER: usize = 0x20;
+const TIM_PSC: usize = 0x28;
+const TIM_ARR: usize = 0x2C;
+const TIM_CCR1: usize = 0x34;
+const TIM_TISEL: usize = 0x68;
+const SR_CC1IF: u32 = 1 << 1;
+/// CC1 as input on TI1, capturing every 8th rising edge.
+const CCMR1_IC1_TI1_DIV8: u32 = 0b01 | 0b11 << 2;
+const CCER_CC1E: u32 = 1 << 0;
+/// Input periods per capture with [`CCMR1_IC1_TI1_DIV8`].
+const PERIODS_PER_CAPTURE: u32 = 8;
+
+impl Timer {
+    fn reg(self, offset: usize) -> Reg {
+        let base = match self {
+            Timer::Tim16 => 0x4001_4400,
+            Timer::Tim17 => 0x4001_4800,
+        };
+        Reg::at(base, offset)
+    }
+
+    fn enable_bit(self) -> u32 {
+        match self {
+            Timer::Tim16 => APB2ENR_TIM16EN,
+            Timer::Tim17 => APB2ENR_TIM17EN,
+        }
+    }
+
+    /// Timer clock cycles spanning `captures * 8` periods of the internal input `tisel`.
+    ///
+    /// The counter free-runs over 16 bits, so one capture interval must stay below 65536 cycles:
+    /// about 1.3 ms at 48 MHz.
+    pub fn capture_cycles(self, tisel: u32, captures: u32) -> Result<u32, ErrorCode> {
+        rcc::with_clock(rcc::APB2ENR, self.enable_bit(), || {
+            self.reg(TIM_CR1).write(0);
+            self.reg(TIM_PSC).write(0);
+            self.reg(TIM_ARR).write(0xFFFF);
+            self.reg(TIM_TISEL).write(tisel);
+            self.reg(TIM_CCMR1).write(CCMR1_IC1_TI1_DIV8);
+            self.reg(TIM_CCER).write(CCER_CC1E);
+            self.reg(TIM_SR).write(0);
+            self.reg(TIM_CR1).write(1);
+
+            let capture = || -> Result<u16, ErrorCode> {
+                while self.reg(TIM_SR).read() & SR_CC1IF == 0 {
+                    check_deadline()?;
+                }
+                Ok(self.reg(TIM_CCR1).read() as u16)
+            };
+            let result = capture().and_then(|first| {
+                let mut total = 0u32;
+                let mut last = first;
+                for _ in 0..captures {
+                    let now = capture()?;
+                    total += now.wrapping_sub(last) as u32;
+                    last = now;
+                }
+                Ok(total)
+            });
+
+            self.reg(TIM_CR1).write(0);
+            self.reg(TIM_CCER).write(0);
+            self.reg(TIM_TISEL).write(0);
+            result
+        })
+    }
+}
+
+/// Sets `on` in `reg` and waits for `ready`, returning the start-up time in microseconds.
+///
+/// Polls the test deadline too, since the LSE may legitimately take seconds.
+fn start(reg: Reg, on: u32, ready: u32, timeout_us: u32) -> Result<u32, ErrorCode> {
+    CycleCounter::enable();
+    let begin = CycleCounter::now();
+    let timeout = timeout::cycles_for_us(timeout_us);
+    reg.set_bits(on);
+    loop {
+        let elapsed = CycleCounter::now().wrapping_sub(begin);
+        if reg.read() & ready != 0 {
+            return Ok(timeout::us_for_cycles(elapsed));
+        }
+        if elapsed >= timeout {
+            return Err(codes::CLOCK_TIMEOUT);
+        }
+        check_deadline()?;
+    }
+}
+
+/// Runs `f` with SYSCLK, HCLK and PCLK2 all on HSI16, then restores the previous configuration.
+///
+/// Flash wait states stay as they are: HSI16 needs none, and any the host configured for a
+/// faster clock only slow the test down.
+fn on_hsi16<R>(f: impl FnOnce() -> Result<R, ErrorCode>) -> Result<R, ErrorCode> {
+    let hsi_was_on = rcc::CR.read() & CR_HSION != 0;
+    start(rcc::CR, CR_HSION, CR_HSIRDY, 100)?;
+    let saved = RCC_CFGR.read();
+    let switch = |cfgr: u32| {
+        RCC_CFGR.write(cfgr);
+        let sw = cfgr & CFGR_SW_MASK;
+        timeout::wait_us(100, || {
+            (RCC_CFGR.read() >> CFGR_SWS_SHIFT) & CFGR_SW_MASK == sw
+        })
+    };
+
+    let result = if switch((saved & !(CFGR_SW_MASK | CFGR_PRESCALERS)) | CFGR_SW_HSI16) {
+        f()
+    } else {
+        Err(codes::CLOCK_TIMEOUT)
+    };
+    switch(saved);
+    if !hsi_was_on {
+        rcc::CR.clear_bits(CR_HSION);
+    }
+    result
+}
+
+pub const HSE32: Test = Test {
+    id: 0x0201,
+    flags: 0,
+    group: group::CLOCKS,
+    expected_ms: 50,
+    run: hse32,
+};
+
+/// HSE/32 captures for the frequency comparison: 512 * 8 periods of 1 us, about 4 ms.
+const HSE_CAPTURES: u32 = 512;
+
+/// Starts HSE32 (powering the TCXO first if asked), measures its start-up time and its frequency
+/// against HSI16, and stops it again unless it was already running.
+///
+/// Parameters: `[options, max_ready_us, max_ppm]`; option bit 0 sets HSEBYPPWR for TCXO boards,
+/// `max_ready_us` defaults to 5000 and `max_ppm` to 0, meaning "report only". HSI16 is itself only
+/// trimmed to about 1 %, so this catches a wrong or pulling crystal rather than fine drift.
+/// Values: start-up time in us (0 if HSE was already on), HSI16 cycles counted, error in ppm.
+fn hse32(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let tcxo = word(params, 0).unwrap_or(0) & 1 != 0;
+    let max_ready_us = word(params, 1).unwrap_or(5_000);
+    let max_ppm = word(params, 2).unwrap_or(0);
+
+    let was_on = rcc::CR.read() & CR_HSEON != 0;
+    let saved_cr = rcc::CR.read() & CR_HSEBYPPWR;
+    let ready_us = if was_on {
+        0
+    } else {
+        if tcxo {
+            rcc::CR.set_bits(CR_HSEBYPPWR);
+        }
+        start(rcc::CR, CR_HSEON, CR_HSERDY, max_ready_us)?
+    };
+    result.value(ready_us);
+
+    let outcome = on_hsi16(|| Timer::Tim17.capture_cycles(TISEL_HSE_DIV32, HSE_CAPTURES));
+    if !was_on {
+        rcc::CR.clear_bits(CR_HSEON);
+        rcc::CR.modify(|v| (v & !CR_HSEBYPPWR) | saved_cr);
+    }
+    let cycles = outcome?;
+
+    // 32 MHz / 32 gives 1 us periods, i.e. 16 HSI16 cycles each.
+    let expected = HSE_CAPTURES * PERIODS_PER_CAPTURE * 16;
+    let ppm = (expected as i64 - cycles as i64) * 1_000_000 / cycles as i64;
+    result.value(cycles);
+    result.value(ppm as i32 as u32);
+    result.message(format_args!(
+        "ready in {} us, {} ppm vs HSI16",
+        ready_us, ppm
+    ));
+    if max_ppm != 0 && ppm.unsigned_abs() > max_ppm as u64 {
+        return Err(codes::CLOCK_INACCURATE);
+    }
+    Ok(())
+}
+
+const BDCR_LSEON: u32 = 1 << 0;
+const BDCR_LSERDY: u32 = 1 << 1;
+const BDCR_LSEDRV_SHIFT: u32 = 3;
+const BDCR_LSEDRV_MASK: u32 = 0b11 << BDCR_LSEDRV_SHIFT;
+
+/// Starts the LSE at drive level `drive` (0 lowest to 3 highest), returning its start-up time in
+/// microseconds, or `None` if it was already running and was left untouched.
+///
+/// A started LSE must be handed back to [`stop_lse`]. Needs backup domain access.
+pub fn start_lse(drive: u32, timeout_us: u32) -> Result<Option<u32>, ErrorCode> {
+    let saved = rcc::BDCR.read();
+    if saved & BDCR_LSEON != 0 {
+        return Ok(None);
+    }
+    rcc::BDCR.write((saved & !BDCR_LSEDRV_MASK) | (drive & 0b11) << BDCR_LSEDRV_SHIFT);
+    match start(rcc::BDCR, BDCR_LSEON, BDCR_LSERDY, timeout_us) {
+        Ok(us) => Ok(Some(us)),
+        Err(e) => {
+            stop_lse(saved);
+            Err(e)
+        }
+    }
+}
+
+/// Stops the LSE again and puts back the drive level from `saved`, the BDCR value before
+/// [`start_lse`].
+pub fn stop_lse(saved: u32) {
+    rcc::BDCR.modify(|v| (v & !(BDCR_LSEON | BDCR_LSEDRV_MASK)) | (saved & BDCR_LSEDRV_MASK));
+}
+
+const CSR_LSION: u32 = 1 << 0;
+const CSR_LSIRDY: u32 = 1 << 1;
+
+/// Starts the LSI, returning whether it was off before and so needs [`stop_lsi`] afterwards.
+pub fn start_lsi() -> Result<bool, ErrorCode> {
+    let was_on = rcc::CSR.read() & CSR_LSION != 0;
+    start(rcc::CSR, CSR_LSION, CSR_LSIRDY, 1_000)?;
+    Ok(!was_on)
+}
+
+pub fn stop_lsi() {
+    rcc::CSR.clear_bits(CSR_LSION);
+}
+
+/// Far longer than any of the oscillators Stop mode switches off takes to restart.
+const RESUME_TIMEOUT_US: u32 = 5_000;
+
+/// Puts the clock tree back to the RCC_CR and RCC_CFGR values from before Stop mode, which wakes
+/// on MSI or HSI16 with HSE32 and the PLL off: restarts the oscillators that were running, in
+/// dependency order, switches SYSCLK back to its source and stops the wakeup clock again if
+/// nothing used it before.
+pub fn resume(saved_cr: u32, saved_cfgr: u32) -> Result<(), ErrorCode> {
+    let oscillators = [
+        (CR_MSION, CR_MSIRDY),
+        (CR_HSION, CR_HSIRDY),
+        (CR_HSEON, CR_HSERDY),
+        (CR_PLLON, CR_PLLRDY),
+    ];
+    for (on, ready) in oscillators {
+        if saved_cr & on != 0 {
+            start(rcc::CR, on, ready, RESUME_TIMEOUT_US)?;
+        }
+    }
+    RCC_CFGR.write(saved_cfgr);
+    let sw = saved_cfgr & CFGR_SW_MASK;
+    if !timeout::wait_us(100, || {
+        (RCC_CFGR.read() >> CFGR_SWS_SHIFT) & CFGR_SW_MASK == sw
+    }) {
+        return Err(codes::CLOCK_TIMEOUT);
+    }
+    rcc::CR.clear_bits((CR_MSION | CR_HSION) & !saved_cr);
+    Ok(())
+}
+
+pub const LSE: Test = Test {
+    id: 0x0202,
+    flags: 0,
+    group: group::CLOCKS,
+    expected_ms: 2_000,
+    run: lse,
+};
+
+/// Starts the 32.768 kHz LSE and times how long LSERDY takes.
+///
+/// Parameters: `[drive, max_ready_ms]`; drive defaults to 3, the strongest, and `max_ready_ms`
+/// to 1000. A crystal that is ready but later than `max_ready_ms` fails with `CLOCK_SLOW`, the
+/// usual sign of marginal load capacitors; one that isn't ready after twice that fails with
+/// `CLOCK_TIMEOUT`, as not starting at all. Values: start-up time in us, or 0 if
+/// the LSE was already running, which the test can't restart and so passes without timing.
+fn lse(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let drive = word(params, 0).unwrap_or(3);
+    let max_ready_ms = word(params, 1).unwrap_or(1_000);
+    if drive > 3 {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+
+    rcc::with_backup_access(|| {
+        let saved = rcc::BDCR.read();
+        let Some(ready_us) = start_lse(drive, max_ready_ms.saturating_mul(2_000))? else {
+            result.message(format_args!("LSE already running"));
+            result.value(0);
+            return Ok(());
+        };
+        stop_lse(saved);
+
+        result.value(ready_us);
+        result.message(format_args!(
+            "LSE ready in {} ms at drive {}",
+            ready_us / 1_000,
+            drive
+        ));
+        if ready_us > max_ready_ms.saturating_mul(1_000) {
+            return Err(codes::CLOCK_SLOW);
+        }
+        Ok(())
+    })
+}
+
+pub const LSI_LSE: Test = Test {
+    id: 0x0203,
+    flags: 0,
+    group: group::CLOCKS,
+    expected_ms: 1_100,
+    run: lsi_lse,
+};
+
+/// Captures of each low-speed clock: 128 * 8 periods, about 31 ms.
+const LOW_SPEED_CAPTURES: u32 = 128;
+const LSE_HZ: u64 = 32_768;
+
+/// Measures LSI against LSE on TIM16, starting either if needed, and reports the LSI frequency
+/// the ratio implies; an application can store it to calibrate LSI-based timeouts.
+///
+/// Parameters: `[drive, max_error_ppm]`; drive is the LSE drive level if the test has to start
+/// it (default 3), `max_error_ppm` bounds how far LSI may be from its nominal 32 kHz, 0 to only
+/// report. Assumes LSIPRE is clear, as it is out of reset. Values: LSE timer cycles, LSI timer
+/// cycles, LSI frequency in Hz.
+fn lsi_lse(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let drive = word(params, 0).unwrap_or(3);
+    let max_error_ppm = word(params, 1).unwrap_or(0);
+    if drive > 3 {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+
+    let (lse_cycles, lsi_cycles) = rcc::with_backup_access(|| {
+        let saved = rcc::BDCR.read();
+        let started_lse = start_lse(drive, 1_000_000)?.is_some();
+        let cycles = start_lsi().and_then(|started_lsi| {
+            let lse = Timer::Tim16.capture_cycles(TISEL_LSE, LOW_SPEED_CAPTURES);
+            let lsi = Timer::Tim16.capture_cycles(TISEL_LSI, LOW_SPEED_CAPTURES);
+            if started_lsi {
+                stop_lsi();
+            }
+            Ok((lse?, lsi?))
+        });
+        if started_lse {
+            stop_lse(saved);
+        }
+        cycles
+    })?;
+
+    // Both spans cover the same number of periods, so the cycle counts are inversely
+    // proportional to the frequencies and the timer clock cancels out.
+    let lsi_hz = (LSE_HZ * lse_cycles as u64 / lsi_cycles.max(1) as u64) as u32;
+    let error_ppm = (lsi_hz as i64 - 32_000) * 1_000_000 / 32_000;
+    result.value(lse_cycles);
+    result.value(lsi_cycles);
+    result.value(lsi_hz);
+    result.message(format_args!("LSI {} Hz ({} ppm)", lsi_hz, error_ppm));
+    if max_error_ppm != 0 && error_ppm.unsigned_abs() > max_error_ppm as u64 {
+        return Err(codes::CLOCK_INACCURATE);
+    }
+    Ok(())
+}
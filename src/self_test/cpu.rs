@@ -0,0 +1,190 @@
+//! Core sanity test, run straight after test 1 as the gate for everything more involved.
+
+use core::hint::black_box;
+
+use flash_algorithm::ErrorCode;
+
+use super::snapshot::Snapshot;
+use super::table::group;
+use super::{SelfTestResult, Test};
+use crate::error::codes;
+use crate::regs::Reg;
+use crate::timeout::{self, CycleCounter};
+
+/// Operand pairs run through [`digest`]: extremes, alternating bits and two irregular values.
+const OPERANDS: [(u32, u32); 5] = [
+    (0, u32::MAX),
+    (0xAAAA_AAAA, 0x5555_5555),
+    (0x8000_0000, 0x0000_0001),
+    (0x1234_5678, 0x9ABC_DEF1),
+    (0xDEAD_BEEF, 0x0000_00FF),
+];
+
+/// Folds a spread of ALU results for `a` and `b` into one word, with a data-dependent loop so the
+/// branch unit takes both sides of every condition. It runs twice: compile-time evaluation gives
+/// the expected digests, and a run-time call on black-boxed operands makes the core compute them.
+const fn digest(a: u32, b: u32) -> u32 {
+    let wide = a as u64 * b as u64;
+    let results = [
+        a.wrapping_add(b),
+        a.wrapping_sub(b),
+        a.wrapping_mul(b),
+        (wide >> 32) as u32,
+        a / (b | 1),
+        (a as i32).wrapping_div((b | 1) as i32) as u32,
+        a % (b | 1),
+        a << (b & 31),
+        a >> (b & 31),
+        ((a as i32) >> (b & 31)) as u32,
+        a.rotate_right(b & 31),
+        a & b,
+        a | !b,
+        a ^ b,
+        a.leading_zeros() | b.trailing_zeros() << 8 | a.count_ones() << 16,
+        a.reverse_bits(),
+        b.swap_bytes(),
+        a.saturating_add(b),
+        (b as i32).saturating_sub(a as i32) as u32,
+    ];
+    let mut acc = 0x811C_9DC5;
+    let mut i = 0;
+    while i < results.len() {
+        acc = (acc ^ results[i]).rotate_left(5).wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    // Collatz steps on a 16-bit seed take both branches in an irregular, operand-driven order.
+    let mut n = (a ^ b.rotate_left(16)) & 0xFFFF | 1;
+    let mut steps = 0;
+    while n != 1 && steps < 1_000 {
+        n = if n & 1 == 0 { n / 2 } else { 3 * n + 1 };
+        steps += 1;
+    }
+    acc ^ steps
+}
+
+const EXPECTED: [u32; OPERANDS.len()] = {
+    let mut out = [0; OPERANDS.len()];
+    let mut i = 0;
+    while i < OPERANDS.len() {
+        out[i] = digest(OPERANDS[i].0, OPERANDS[i].1);
+        i += 1;
+    }
+    out
+};
+
+const SYST_CSR: Reg = Reg::at(0xE000_E010, 0x0);
+const SYST_RVR: Reg = Reg::at(0xE000_E010, 0x4);
+const SYST_CVR: Reg = Reg::at(0xE000_E010, 0x8);
+const CSR_ENABLE: u32 = 1 << 0;
+/// Set for the core clock, clear for the external reference, HCLK / 8 on the WLE5.
+const CSR_CLKSOURCE: u32 = 1 << 2;
+const CSR_COUNTFLAG: u32 = 1 << 16;
+const SYST_MAX: u32 = 0x00FF_FFFF;
+const REFERENCE_DIVIDER: u32 = 8;
+
+pub const CPU: Test = Test {
+    id: 2,
+    flags: 0,
+    group: group::GENERAL,
+    expected_ms: 2,
+    run: cpu,
+};
+
+/// SysTick is compared with CYCCNT over this window, long enough for either to resolve 0.1 %
+/// with the reference clock's / 8.
+const WINDOW_CYCLES: u32 = 100_000;
+/// Reload for the wrap check, so COUNTFLAG is due within a few microseconds.
+const WRAP_RELOAD: u32 = 999;
+
+/// Checks integer arithmetic, shifts, bit manipulation and branches against digests computed at
+/// compile time, then that SysTick counts in step with DWT CYCCNT, from the core clock and from
+/// its / 8 reference, and flags a wrap.
+///
+/// SysTick may belong to the previous firmware: its interrupt stays disabled throughout and its
+/// configuration is restored afterwards, only the current value being lost. Values: operand
+/// pairs checked, then SysTick ticks and CYCCNT cycles over the window on the core clock, then
+/// the same on the reference clock.
+fn cpu(_params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    if let Some((index, got, expected)) = alu_fault() {
+        result.value(index);
+        result.message(format_args!(
+            "ALU digest {:#010x}, expected {:#010x}",
+            got, expected
+        ));
+        return Err(codes::CORE_FAULT);
+    }
+    result.value(OPERANDS.len() as u32);
+
+    // Any write clears CVR, so putting it back reloads the counter from RVR.
+    let _systick = Snapshot::peripheral([SYST_RVR, SYST_CVR, SYST_CSR]);
+    systick(result)
+}
+
+/// Runs every operand pair through [`digest`], returning the first one that came out wrong as
+/// its index, the digest computed and the one expected.
+pub fn alu_fault() -> Option<(u32, u32, u32)> {
+    OPERANDS
+        .iter()
+        .zip(&EXPECTED)
+        .enumerate()
+        .find_map(|(i, (&(a, b), &expected))| {
+            let got = digest(black_box(a), black_box(b));
+            (got != expected).then_some((i as u32, got, expected))
+        })
+}
+
+fn systick(result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    CycleCounter::enable();
+    for (clksource, divider) in [(CSR_CLKSOURCE, 1), (0, REFERENCE_DIVIDER)] {
+        let Some((ticks, cycles)) = measure(clksource) else {
+            result.message(format_args!("SysTick / {} or CYCCNT not counting", divider));
+            return Err(codes::CLOCK_TIMEOUT);
+        };
+        result.value(ticks);
+        result.value(cycles);
+        let expected = cycles / divider;
+        if ticks.abs_diff(expected) > expected / 1_000 + 2 {
+            result.message(format_args!(
+                "SysTick / {}: {} ticks in {} cycles",
+                divider, ticks, cycles
+            ));
+            return Err(codes::CLOCK_INACCURATE);
+        }
+    }
+
+    SYST_CSR.write(0);
+    SYST_RVR.write(WRAP_RELOAD);
+    SYST_CVR.write(0);
+    SYST_CSR.write(CSR_CLKSOURCE | CSR_ENABLE);
+    // Reading CSR clears COUNTFLAG, so the flag seen here comes from a wrap after this point.
+    let _ = SYST_CSR.read();
+    if !timeout::wait_us(100, || SYST_CSR.read() & CSR_COUNTFLAG != 0) {
+        result.message(format_args!("SysTick never wrapped"));
+        return Err(codes::CLOCK_TIMEOUT);
+    }
+    result.message(format_args!("ALU and SysTick ok"));
+    Ok(())
+}
+
+/// Runs SysTick down from its maximum on `clksource` for [`WINDOW_CYCLES`], returning its ticks
+/// and the CYCCNT cycles between the two samples, or `None` if either of them stalls.
+fn measure(clksource: u32) -> Option<(u32, u32)> {
+    SYST_CSR.write(0);
+    SYST_RVR.write(SYST_MAX);
+    SYST_CVR.write(0);
+    SYST_CSR.write(clksource | CSR_ENABLE);
+    // The reload only happens on the first tick, so sample from the second one on.
+    if !timeout::wait_us(100, || SYST_CVR.read() >= SYST_MAX / 2) {
+        return None;
+    }
+    let first = (CycleCounter::now(), SYST_CVR.read());
+    while CycleCounter::now().wrapping_sub(first.0) < WINDOW_CYCLES {
+        // SysTick ticks at most once a cycle, so running past the window on it means CYCCNT
+        // stopped; the window is too short for SysTick to wrap first.
+        if first.1 - SYST_CVR.read() > WINDOW_CYCLES {
+            return None;
+        }
+    }
+    let last = (CycleCounter::now(), SYST_CVR.read());
+    Some((first.1 - last.1, last.0.wrapping_sub(first.0)))
+}
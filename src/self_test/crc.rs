@@ -0,0 +1,230 @@
+//! Driver for the CRC calculation unit (RM0461, section 14), shared by the tests that need it,
+//! and the unit's own known-answer test.
+
+use flash_algorithm::ErrorCode;
+
+use super::rcc;
+use super::snapshot::Snapshot;
+use super::table::group;
+use super::{SelfTestResult, Test};
+use crate::error::codes;
+use crate::regs::Reg;
+
+const CRC: usize = 0x4002_3000;
+const DR: Reg = Reg::at(CRC, 0x00);
+const CR: Reg = Reg::at(CRC, 0x08);
+const INIT: Reg = Reg::at(CRC, 0x10);
+const POL: Reg = Reg::at(CRC, 0x14);
+
+const CR_RESET: u32 = 1 << 0;
+const CR_POLYSIZE_SHIFT: u32 = 3;
+const CR_REV_IN_BYTE: u32 = 0b01 << 5;
+const CR_REV_IN_WORD: u32 = 0b11 << 5;
+const CR_REV_OUT: u32 = 1 << 7;
+/// POL's reset value, and the polynomial of every 32-bit CRC host tools default to.
+const POLY_CRC32: u32 = 0x04C1_1DB7;
+
+/// Runs `f` with the unit clocked, restoring its registers and clock afterwards.
+fn with_unit<R>(f: impl FnOnce() -> R) -> R {
+    rcc::with_clock(rcc::AHB1ENR, rcc::AHB1ENR_CRCEN, || {
+        let _unit = Snapshot::registers([POL, INIT, CR]);
+        f()
+    })
+}
+
+/// Loads a polynomial and initial value, and resets the unit with the `cr` options.
+fn configure(poly: u32, init: u32, cr: u32) {
+    POL.write(poly);
+    INIT.write(init);
+    CR.write(cr | CR_RESET);
+}
+
+/// The unit configured for CRC-32/ISO-HDLC, the zlib and Ethernet CRC host tools compute.
+pub struct Crc32(());
+
+impl Crc32 {
+    /// Runs `f` with the unit clocked and configured, restoring its registers and clock afterwards.
+    pub fn with<R>(f: impl FnOnce(&mut Crc32) -> R) -> R {
+        with_unit(|| {
+            configure(POLY_CRC32, u32::MAX, CR_REV_IN_BYTE | CR_REV_OUT);
+            f(&mut Crc32(()))
+        })
+    }
+
+    /// Feeds `bytes` in address order; byte writes keep arbitrary lengths and alignments exact.
+    pub fn update(&mut self, bytes: &[u8]) {
+        let dr = CRC as *mut u8;
+        for &byte in bytes {
+            unsafe { dr.write_volatile(byte) };
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !DR.read()
+    }
+}
+
+/// A CRC in the catalogue's terms, checked against its value for `data`.
+struct Vector {
+    name: &'static str,
+    /// 7, 8, 16 or 32.
+    width: u32,
+    poly: u32,
+    init: u32,
+    /// Input and output both reflected.
+    reflect: bool,
+    xorout: u32,
+    /// Fed a word at a time rather than a byte; `data` is then a multiple of four bytes.
+    words: bool,
+    data: &'static [u8],
+    check: u32,
+}
+
+const CHECK_DATA: &[u8] = b"123456789";
+
+const VECTORS: [Vector; 6] = [
+    // The unit's reset configuration.
+    Vector {
+        name: "CRC-32/MPEG-2",
+        width: 32,
+        poly: POLY_CRC32,
+        init: u32::MAX,
+        reflect: false,
+        xorout: 0,
+        words: false,
+        data: CHECK_DATA,
+        check: 0x0376_E6E7,
+    },
+    Vector {
+        name: "CRC-32/ISO-HDLC words",
+        width: 32,
+        poly: POLY_CRC32,
+        init: u32::MAX,
+        reflect: true,
+        xorout: u32::MAX,
+        words: true,
+        data: b"12345678",
+        check: 0x9AE0_DAAF,
+    },
+    Vector {
+        name: "CRC-32/ISCSI",
+        width: 32,
+        poly: 0x1EDC_6F41,
+        init: u32::MAX,
+        reflect: true,
+        xorout: u32::MAX,
+        words: false,
+        data: CHECK_DATA,
+        check: 0xE306_9283,
+    },
+    Vector {
+        name: "CRC-16/IBM-3740",
+        width: 16,
+        poly: 0x1021,
+        init: 0xFFFF,
+        reflect: false,
+        xorout: 0,
+        words: false,
+        data: CHECK_DATA,
+        check: 0x29B1,
+    },
+    Vector {
+        name: "CRC-8/SMBUS",
+        width: 8,
+        poly: 0x07,
+        init: 0,
+        reflect: false,
+        xorout: 0,
+        words: false,
+        data: CHECK_DATA,
+        check: 0xF4,
+    },
+    Vector {
+        name: "CRC-7/MMC",
+        width: 7,
+        poly: 0x09,
+        init: 0,
+        reflect: false,
+        xorout: 0,
+        words: false,
+        data: CHECK_DATA,
+        check: 0x75,
+    },
+];
+
+/// CRC-32/ISO-HDLC of [`CHECK_DATA`], computed through [`Crc32`] itself.
+const CRC32_CHECK: u32 = 0xCBF4_3926;
+
+pub const CRC_KAT: Test = Test {
+    id: 0x0310,
+    flags: 0,
+    group: group::MEMORY,
+    expected_ms: 1,
+    run: crc_kat,
+};
+
+/// Checks [`Crc32`], which the flash tests rely on, then runs catalogue CRCs of other widths
+/// and polynomials, fed by byte and by word, through the unit. Values: vectors run, bitmap of
+/// the ones that produced the wrong output, bit 0 being [`Crc32`]'s.
+fn crc_kat(_params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let crc32 = Crc32::with(|crc| {
+        crc.update(CHECK_DATA);
+        crc.finish()
+    });
+    let mut failed = (crc32 != CRC32_CHECK) as u32;
+    with_unit(|| {
+        for (i, vector) in VECTORS.iter().enumerate() {
+            if compute(vector) != vector.check {
+                failed |= 1 << (i + 1);
+            }
+        }
+    });
+
+    result.value(VECTORS.len() as u32 + 1);
+    result.value(failed);
+    let name = match failed.trailing_zeros() {
+        32 => {
+            result.message(format_args!("{} vectors passed", VECTORS.len() + 1));
+            return Ok(());
+        }
+        0 => "CRC-32/ISO-HDLC",
+        i => VECTORS[i as usize - 1].name,
+    };
+    result.message(format_args!("{} mismatch", name));
+    Err(codes::KAT_MISMATCH)
+}
+
+fn compute(vector: &Vector) -> u32 {
+    // POLYSIZE counts down from 32 bits: 0b00 is 32, 0b01 16, 0b10 8 and 0b11 7.
+    let polysize = match vector.width {
+        32 => 0b00,
+        16 => 0b01,
+        8 => 0b10,
+        _ => 0b11,
+    };
+    let reflect = match (vector.reflect, vector.words) {
+        (false, _) => 0,
+        (true, false) => CR_REV_IN_BYTE | CR_REV_OUT,
+        (true, true) => CR_REV_IN_WORD | CR_REV_OUT,
+    };
+    configure(
+        vector.poly,
+        vector.init,
+        polysize << CR_POLYSIZE_SHIFT | reflect,
+    );
+    if vector.words {
+        for chunk in vector.data.chunks_exact(4) {
+            let bytes = chunk.try_into().unwrap_or([0; 4]);
+            // Reflected CRCs take the first byte first only with the whole word reversed.
+            let word = if vector.reflect {
+                u32::from_le_bytes(bytes)
+            } else {
+                u32::from_be_bytes(bytes)
+            };
+            DR.write(word);
+        }
+    } else {
+        Crc32(()).update(vector.data);
+    }
+    (DR.read() ^ vector.xorout) & (u32::MAX >> (32 - vector.width))
+}
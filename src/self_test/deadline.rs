@@ -0,0 +1,77 @@
+//! Time budget of the running test, so a hung test fails instead of hanging the whole session.
+//!
+//! The budget is kept in DWT cycles, or with `timeout-spin`, whose probes keep DWT for
+//! themselves, in deadline checks calibrated like `SpinLoop` polls.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use flash_algorithm::ErrorCode;
+
+use crate::error::codes;
+use crate::timeout::{self, CycleCounter};
+
+/// DWT count when the test started, or with `timeout-spin` the deadline checks made since.
+static START: AtomicU32 = AtomicU32::new(0);
+/// Stored by [`arm`] before any check; zero-initialised to stay out of `.data`.
+static BUDGET: AtomicU32 = AtomicU32::new(0);
+
+/// Twice the declared duration plus 10 ms, so slow-but-good boards don't trip it.
+fn budget_us(expected_ms: u32) -> u32 {
+    expected_ms
+        .saturating_mul(2)
+        .saturating_add(10)
+        .saturating_mul(1_000)
+}
+
+/// The budget for `expected_ms` in the units `START` counts.
+fn budget(expected_ms: u32) -> u32 {
+    let cycles = timeout::cycles_for_us(budget_us(expected_ms));
+    if cfg!(feature = "timeout-spin") {
+        cycles / timeout::CYCLES_PER_POLL
+    } else {
+        cycles
+    }
+}
+
+/// Starts the budget for a test declaring `expected_ms`.
+pub fn arm(expected_ms: u32) {
+    let start = if cfg!(feature = "timeout-spin") {
+        0
+    } else {
+        CycleCounter::enable();
+        CycleCounter::now()
+    };
+    START.store(start, Ordering::Relaxed);
+    BUDGET.store(budget(expected_ms), Ordering::Relaxed);
+}
+
+/// Microseconds since [`arm`]; always 0 with `timeout-spin`.
+pub fn elapsed_us() -> u32 {
+    if cfg!(feature = "timeout-spin") {
+        return 0;
+    }
+    timeout::us_for_cycles(CycleCounter::now().wrapping_sub(START.load(Ordering::Relaxed)))
+}
+
+/// Re-budgets the running test for `expected_ms` from its original start, for tests whose
+/// duration the host picks.
+#[cfg(feature = "self-test-burn-in")]
+pub fn extend(expected_ms: u32) {
+    BUDGET.store(budget(expected_ms), Ordering::Relaxed);
+}
+
+/// Fails with `TEST_TIMEOUT` once the running test is over its budget.
+///
+/// Tests call this from every loop that waits on hardware and return the error with `?`, so the
+/// guards they hold put their peripherals back on the way out.
+pub fn check_deadline() -> Result<(), ErrorCode> {
+    let elapsed = if cfg!(feature = "timeout-spin") {
+        START.fetch_add(1, Ordering::Relaxed)
+    } else {
+        CycleCounter::now().wrapping_sub(START.load(Ordering::Relaxed))
+    };
+    if elapsed >= BUDGET.load(Ordering::Relaxed) {
+        return Err(codes::TEST_TIMEOUT);
+    }
+    Ok(())
+}
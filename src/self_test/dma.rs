@@ -0,0 +1,237 @@
+//! DMA memory-to-memory test, part of `self-test-memory` (RM0461, chapters 13 and 14).
+//!
+//! Memory-to-memory transfers don't wait for a request, but each DMA channel still sits behind
+//! its DMAMUX channel, which the test clears for the duration so no request line can interfere.
+
+use flash_algorithm::ErrorCode;
+
+use super::ram::{self, Window};
+use super::snapshot::Snapshot;
+use super::table::group;
+use super::{check_deadline, rcc, SelfTestResult, Test};
+use crate::error::codes;
+use crate::regs::Reg;
+use crate::timeout;
+
+/// DMA1 and DMA2.
+const CONTROLLERS: [usize; 2] = [0x4002_0000, 0x4002_0400];
+const CHANNELS: usize = 7;
+const ISR: usize = 0x00;
+const IFCR: usize = 0x04;
+/// Channel 1's registers; each further channel follows at [`CHANNEL_STRIDE`].
+const CCR: usize = 0x08;
+const CNDTR: usize = 0x0C;
+const CPAR: usize = 0x10;
+const CMAR: usize = 0x14;
+const CHANNEL_STRIDE: usize = 0x14;
+/// The channel registers the test reconfigures, in the order they are put back: CCR holds EN.
+const SAVED_REGS: [usize; 4] = [CNDTR, CPAR, CMAR, CCR];
+
+/// Flags in each channel's nibble of ISR and IFCR.
+const FLAG_GIF: u32 = 1 << 0;
+const FLAG_TCIF: u32 = 1 << 1;
+const FLAG_TEIF: u32 = 1 << 3;
+const CCR_EN: u32 = 1 << 0;
+const CCR_PINC: u32 = 1 << 6;
+const CCR_MINC: u32 = 1 << 7;
+const CCR_PSIZE_SHIFT: u32 = 8;
+const CCR_MSIZE_SHIFT: u32 = 10;
+const CCR_MEM2MEM: u32 = 1 << 14;
+
+/// DMAMUX1 channel configuration: DMA1 channels 1 to 7 use 0 to 6, DMA2's 7 to 13.
+const DMAMUX_C0CR: usize = 0x4002_0800;
+/// DMA1EN, DMA2EN and DMAMUX1EN.
+const AHB1ENR_DMA: u32 = 0b111;
+
+/// Bytes per transfer, at most; each width moves the same amount.
+const TRANSFER_BYTES: u32 = 1_024;
+/// Far longer than a kilobyte takes even a byte at a time.
+const TRANSFER_TIMEOUT_US: u32 = 1_000;
+
+#[derive(Clone, Copy)]
+struct Channel {
+    controller: usize,
+    /// Channel number minus one.
+    index: usize,
+}
+
+impl Channel {
+    fn reg(self, offset: usize) -> Reg {
+        let base = CONTROLLERS[self.controller];
+        if offset == ISR || offset == IFCR {
+            Reg::at(base, offset)
+        } else {
+            Reg::at(base, offset + self.index * CHANNEL_STRIDE)
+        }
+    }
+
+    fn mux(self) -> Reg {
+        Reg::at(DMAMUX_C0CR, (self.controller * CHANNELS + self.index) * 4)
+    }
+
+    /// This channel's nibble of ISR.
+    fn flags(self) -> u32 {
+        (self.reg(ISR).read() >> (self.index * 4)) & 0xF
+    }
+
+    fn clear_flags(self) {
+        self.reg(IFCR).write(FLAG_GIF << (self.index * 4));
+    }
+}
+
+/// A word that came back differently from what the source held.
+struct Mismatch {
+    addr: u32,
+    expected: u32,
+    read: u32,
+}
+
+pub const DMA_M2M: Test = Test {
+    id: 0x0304,
+    flags: 0,
+    group: group::MEMORY,
+    expected_ms: 30,
+    run: dma_m2m,
+};
+
+/// Copies between the two halves of the RAM window with every channel of both DMA controllers,
+/// a byte, a half-word and a word at a time and in both directions, and checks the flags and
+/// the data of every transfer.
+///
+/// Parameters: `[start, end]` as for the other RAM tests; a window straddling 0x2000_8000 has
+/// each transfer cross between SRAM1 and SRAM2. Every transfer moves up to 1 KiB. Values:
+/// window start and end, then either the number of transfers and bytes per transfer, or the
+/// failing channel (0x11 for DMA1 channel 1) followed, for a data error, by the address,
+/// expected and read words.
+fn dma_m2m(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let window = ram::window(params, result)?;
+    let half = ((window.end - window.start) / 2) & !3;
+    let bytes = half.min(TRANSFER_BYTES);
+    if bytes == 0 {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+    let halves = [window.start, window.start + half];
+
+    rcc::with_clock(rcc::AHB1ENR, AHB1ENR_DMA, || {
+        let mut transfers = 0;
+        for controller in 0..CONTROLLERS.len() {
+            for index in 0..CHANNELS {
+                let channel = Channel { controller, index };
+                let outcome = {
+                    let _mux = Snapshot::registers([channel.mux()]);
+                    let _channel =
+                        Snapshot::peripheral(SAVED_REGS.map(|offset| channel.reg(offset)));
+                    exercise(channel, halves, bytes, &mut transfers)
+                };
+                channel.clear_flags();
+
+                let name = ((controller + 1) << 4 | (index + 1)) as u32;
+                match outcome {
+                    Ok(None) => {}
+                    Ok(Some(mismatch)) => {
+                        result.value(name);
+                        ram::mismatch(result, mismatch.addr, mismatch.expected, mismatch.read);
+                        return Err(codes::DMA_FAULT);
+                    }
+                    Err(e) => {
+                        result.value(name);
+                        result.message(format_args!(
+                            "DMA{} channel {} didn't complete",
+                            controller + 1,
+                            index + 1
+                        ));
+                        return Err(e);
+                    }
+                }
+                check_deadline()?;
+            }
+        }
+        result.value(transfers);
+        result.value(bytes);
+        result.message(format_args!("{} transfers of {} bytes", transfers, bytes));
+        Ok(())
+    })
+}
+
+/// Runs every width in both directions on `channel`, stopping at the first data error.
+fn exercise(
+    channel: Channel,
+    [low, high]: [u32; 2],
+    bytes: u32,
+    transfers: &mut u32,
+) -> Result<Option<Mismatch>, ErrorCode> {
+    channel.mux().write(0);
+    for width in 0..3 {
+        for (source, destination) in [(low, high), (high, low)] {
+            let seed = transfers.wrapping_mul(0x0101_0101);
+            let source = Window {
+                start: source,
+                end: source + bytes,
+            };
+            let destination = Window {
+                start: destination,
+                end: destination + bytes,
+            };
+            let mismatch = transfer(channel, source, destination, width, seed)?;
+            *transfers += 1;
+            if mismatch.is_some() {
+                return Ok(mismatch);
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Fills `source` with a pattern and `destination` with its complement, copies one over the other
+/// in `1 << width`-byte items, and compares.
+fn transfer(
+    channel: Channel,
+    source: Window,
+    destination: Window,
+    width: u32,
+    seed: u32,
+) -> Result<Option<Mismatch>, ErrorCode> {
+    let words = (source.end - source.start) / 4;
+    let pattern = |index: u32| index.wrapping_mul(0x9E37_79B9) ^ seed;
+    let word = |window: Window, index: u32| (window.start + index * 4) as *mut u32;
+    for index in 0..words {
+        unsafe {
+            word(source, index).write_volatile(pattern(index));
+            word(destination, index).write_volatile(!pattern(index));
+        }
+    }
+
+    channel.clear_flags();
+    channel.reg(CCR).write(0);
+    channel
+        .reg(CNDTR)
+        .write((source.end - source.start) >> width);
+    channel.reg(CPAR).write(source.start);
+    channel.reg(CMAR).write(destination.start);
+    channel.reg(CCR).write(
+        CCR_MEM2MEM
+            | width << CCR_MSIZE_SHIFT
+            | width << CCR_PSIZE_SHIFT
+            | CCR_MINC
+            | CCR_PINC
+            | CCR_EN,
+    );
+    timeout::wait_us(TRANSFER_TIMEOUT_US, || {
+        channel.flags() & (FLAG_TCIF | FLAG_TEIF) != 0
+    });
+    let flags = channel.flags();
+    channel.reg(CCR).write(0);
+    channel.clear_flags();
+    if flags & FLAG_TEIF != 0 || flags & FLAG_TCIF == 0 {
+        return Err(codes::DMA_FAULT);
+    }
+
+    Ok((0..words).find_map(|index| {
+        let read = unsafe { word(destination, index).read_volatile() };
+        (read != pattern(index)).then_some(Mismatch {
+            addr: destination.start + index * 4,
+            expected: pattern(index),
+            read,
+        })
+    }))
+}
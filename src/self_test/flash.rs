@@ -0,0 +1,196 @@
+//! Main flash checks, enabled by the `self-test-flash` feature.
+
+use flash_algorithm::ErrorCode;
+
+use super::crc::Crc32;
+use super::params::word;
+use super::table::{flags, group};
+use super::{check_deadline, SelfTestResult, Test};
+use crate::error::codes;
+use crate::flash;
+use crate::regs::Reg;
+
+/// Bytes hashed or scanned between two deadline polls.
+const POLL_INTERVAL: usize = 1024;
+
+const FLASH_ECCR: Reg = Reg::at(0x5800_4000, 0x18);
+/// Double word (8 bytes) offset of the last ECC event into main flash.
+const ECCR_ADDR_MASK: u32 = 0x1_FFFF;
+/// Set for events in system flash rather than main flash.
+const ECCR_SYSF: u32 = 1 << 20;
+const ECCR_ECCC: u32 = 1 << 30;
+const ECCR_ECCD: u32 = 1 << 31;
+
+pub const CRC: Test = Test {
+    id: 0x0311,
+    flags: 0,
+    group: group::MEMORY,
+    expected_ms: 100,
+    run: crc,
+};
+
+pub const ECC: Test = Test {
+    id: 0x0312,
+    flags: 0,
+    group: group::MEMORY,
+    expected_ms: 100,
+    run: ecc,
+};
+
+pub const SCRATCH: Test = Test {
+    id: 0x0313,
+    flags: flags::DESTRUCTIVE,
+    group: group::MEMORY,
+    expected_ms: 100,
+    run: scratch,
+};
+
+/// Resolves `[start, len]` from the parameter words at `index`, defaulting to all of main flash.
+fn range(params: &[u8], index: usize) -> Result<(u32, u32), ErrorCode> {
+    let start = word(params, index).unwrap_or(flash::BASE);
+    let len = word(params, index + 1).unwrap_or(flash::BASE + flash::SIZE - start);
+    let end = start.checked_add(len).ok_or(codes::INVALID_ARGUMENT)?;
+    if start < flash::BASE || end > flash::BASE + flash::SIZE {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+    Ok((start, len))
+}
+
+/// CRC-32/ISO-HDLC over a flash range with the CRC unit, so the factory can confirm the golden
+/// image after programming without reading it back over SWD.
+///
+/// Parameters: `[start, len, expected]`, all optional; without `expected` the test only reports
+/// the CRC, in the third value and the message.
+fn crc(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let (start, len) = range(params, 0)?;
+    let image = unsafe { core::slice::from_raw_parts(start as *const u8, len as usize) };
+    let crc = Crc32::with(|crc| -> Result<u32, ErrorCode> {
+        for chunk in image.chunks(POLL_INTERVAL) {
+            check_deadline()?;
+            crc.update(chunk);
+        }
+        Ok(crc.finish())
+    })?;
+
+    result.value(start);
+    result.value(len);
+    result.value(crc);
+    result.message(format_args!("crc32 {:#010x}", crc));
+    match word(params, 2) {
+        Some(expected) if expected != crc => Err(codes::CRC_MISMATCH),
+        _ => Ok(()),
+    }
+}
+
+/// Reads every double word of a flash range and collects the ECC corrections this causes, to
+/// screen for marginal cells that still read back correctly.
+///
+/// Parameters: `[start, len, allowed]`, all optional; the test fails once more than `allowed`
+/// (default 0) corrections show up. Values: corrections seen, then the first corrected address.
+/// A double error raises an NMI the core can't mask; `fault-capture` records it in
+/// `FaultMailbox`, otherwise the session halts.
+fn ecc(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let (start, len) = range(params, 0)?;
+    let allowed = word(params, 2).unwrap_or(0);
+    let start = start & !7;
+
+    let mut corrections = 0u32;
+    let mut first = None;
+    FLASH_ECCR.write(ECCR_ECCC | ECCR_ECCD);
+    for offset in (0..len).step_by(8) {
+        let addr = start + offset;
+        let _ = unsafe { (addr as *const u32).read_volatile() };
+
+        let eccr = FLASH_ECCR.read();
+        if eccr & ECCR_ECCC != 0 {
+            FLASH_ECCR.write(ECCR_ECCC);
+            if eccr & ECCR_SYSF == 0 {
+                corrections += 1;
+                first.get_or_insert(flash::BASE + (eccr & ECCR_ADDR_MASK) * 8);
+            }
+        }
+        if (offset as usize).is_multiple_of(POLL_INTERVAL) {
+            check_deadline()?;
+        }
+    }
+
+    result.value(corrections);
+    result.value(first.unwrap_or(0));
+    match first {
+        Some(addr) => result.message(format_args!(
+            "{} corrections from {:#010x}",
+            corrections, addr
+        )),
+        None => result.message(format_args!("no ECC corrections")),
+    }
+    if corrections > allowed {
+        return Err(codes::FLASH_ECC);
+    }
+    Ok(())
+}
+
+/// Pattern programmed into the scratch page: each word's address, with alternating bits flipped so
+/// neighbouring words and both cell states get exercised.
+fn pattern(addr: u32) -> u32 {
+    addr ^ 0xA5A5_A5A5
+}
+
+/// Compares every word of `page` with `expected`, recording the first difference.
+fn compare(
+    page: u32,
+    expected: impl Fn(u32) -> u32,
+    result: &mut SelfTestResult,
+) -> Result<(), ErrorCode> {
+    for addr in (page..page + flash::PAGE_SIZE).step_by(4) {
+        let (want, read) = (expected(addr), unsafe {
+            (addr as *const u32).read_volatile()
+        });
+        if read != want {
+            result.value(addr);
+            result.value(want);
+            result.value(read);
+            result.message(format_args!(
+                "{:#010x}: want {:#010x}, read {:#010x}",
+                addr, want, read
+            ));
+            return Err(codes::VERIFY_MISMATCH);
+        }
+    }
+    Ok(())
+}
+
+/// Erases a scratch page, programs and verifies a pattern, then erases and blank-checks it again,
+/// to exercise the whole programming path before the production image goes in.
+///
+/// Parameter: the page address, by default the last page of main flash. The page is left blank.
+fn scratch(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    const BLANK: u32 = u32::MAX;
+    let page = word(params, 0).unwrap_or(flash::BASE + flash::SIZE - flash::PAGE_SIZE);
+    // The driver rounds down to the containing page, which would erase data the host didn't name.
+    if !page.is_multiple_of(flash::PAGE_SIZE) {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+    result.value(page);
+
+    flash::erase_page(page)?;
+    compare(page, |_| BLANK, result)?;
+    check_deadline()?;
+
+    let mut chunk = [0u8; 64];
+    for start in (page..page + flash::PAGE_SIZE).step_by(chunk.len()) {
+        for (i, word) in chunk.chunks_exact_mut(4).enumerate() {
+            word.copy_from_slice(&pattern(start + i as u32 * 4).to_le_bytes());
+        }
+        flash::program(start, &chunk)?;
+        check_deadline()?;
+    }
+    compare(page, pattern, result)?;
+
+    flash::erase_page(page)?;
+    compare(page, |_| BLANK, result)?;
+    result.message(format_args!(
+        "page {:#010x} erased, programmed, erased",
+        page
+    ));
+    Ok(())
+}
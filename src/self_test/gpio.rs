@@ -0,0 +1,269 @@
+//! GPIO access for the tests that drive or sense package pins (RM0461, section 9).
+#![allow(dead_code)] // Shared by the analog, bus, GPIO and radio tests, each using its own subset.
+
+use super::rcc;
+use crate::regs::Reg;
+
+const GPIO: usize = 0x4800_0000;
+const PORT_STRIDE: usize = 0x400;
+const MODER: usize = 0x00;
+const OTYPER: usize = 0x04;
+const PUPDR: usize = 0x0C;
+const IDR: usize = 0x10;
+const ODR: usize = 0x14;
+/// AFRL, for pins 0 to 7; AFRH follows it for pins 8 to 15.
+const AFRL: usize = 0x20;
+/// GPIOA, B, C and H; the WLE5 has no ports D to G.
+const PORTS: u8 = 0b1000_0111;
+const PORT_COUNT: usize = PORTS.count_ones() as usize;
+/// SWDIO (PA13) and SWCLK (PA14).
+const SWD_PINS: u16 = 1 << 13 | 1 << 14;
+
+/// One pin, named in parameters by a byte with the port in the high nibble (A = 0, B = 1, C = 2,
+/// H = 7) and the pin number in the low one, so PB5 is `0x15`. PA13 and PA14 carry SWD and are
+/// refused, since reconfiguring them would cut the probe off mid-test.
+#[derive(Clone, Copy)]
+pub struct Pin {
+    port: u8,
+    pin: u8,
+}
+
+/// A pin's configuration before a test touched it, put back when dropped.
+pub struct Saved {
+    pin: Pin,
+    moder: u32,
+    otyper: u32,
+    pupdr: u32,
+    odr: u32,
+    afr: u32,
+}
+
+impl Pin {
+    pub fn from_id(id: u8) -> Option<Self> {
+        let (port, pin) = (id >> 4, id & 0xF);
+        let swd = port == 0 && SWD_PINS & (1 << pin) != 0;
+        (port < 8 && PORTS & (1 << port) != 0 && !swd).then_some(Self { port, pin })
+    }
+
+    pub fn id(self) -> u8 {
+        self.port << 4 | self.pin
+    }
+
+    /// This pin's bit in a 64-bit map of all ports, 16 bits each in port order A, B, C, H.
+    pub fn bit(self) -> u64 {
+        1 << (self.port_index() * 16 + self.pin as usize)
+    }
+
+    /// Index among the ports that exist, for per-port tables.
+    fn port_index(self) -> usize {
+        (PORTS & ((1 << self.port) - 1)).count_ones() as usize
+    }
+
+    fn reg(self, offset: usize) -> Reg {
+        Reg::at(GPIO + self.port as usize * PORT_STRIDE, offset)
+    }
+
+    /// The alternate function register holding this pin's four-bit field, and the field's shift.
+    fn afr(self) -> (Reg, u32) {
+        let pin = self.pin as usize;
+        (self.reg(AFRL + pin / 8 * 4), (pin % 8) as u32 * 4)
+    }
+
+    /// Replaces the two bits of this pin in a two-bit-per-pin register.
+    fn set_field(self, offset: usize, value: u32) {
+        let shift = self.pin as u32 * 2;
+        self.reg(offset)
+            .modify(|v| (v & !(0b11 << shift)) | (value << shift));
+    }
+
+    /// Switches the port clock on, leaving it on: other pins of the port may be in use by the
+    /// board, and they keep their configuration either way.
+    fn enable_port(self) {
+        rcc::AHB2ENR.set_bits(1 << self.port);
+        let _ = rcc::AHB2ENR.read();
+    }
+
+    pub fn save(self) -> Saved {
+        self.enable_port();
+        let bit = 1 << self.pin;
+        let field = 0b11 << (self.pin * 2);
+        let (afr, shift) = self.afr();
+        Saved {
+            pin: self,
+            moder: self.reg(MODER).read() & field,
+            otyper: self.reg(OTYPER).read() & bit,
+            pupdr: self.reg(PUPDR).read() & field,
+            odr: self.reg(ODR).read() & bit,
+            afr: afr.read() & (0xF << shift),
+        }
+    }
+
+    /// Push-pull output driving `high`.
+    pub fn output(self, high: bool) {
+        self.set(high);
+        self.reg(OTYPER).clear_bits(1 << self.pin);
+        self.set_field(PUPDR, 0b00);
+        self.set_field(MODER, 0b01);
+    }
+
+    /// Input with the given pull resistor.
+    pub fn input(self, pull: Pull) {
+        self.set_field(PUPDR, pull as u32);
+        self.set_field(MODER, 0b00);
+    }
+
+    /// Analog mode, as out of reset: digital input off, free for the ADC, DAC or comparators.
+    pub fn analog(self) {
+        self.set_field(PUPDR, 0b00);
+        self.set_field(MODER, 0b11);
+    }
+
+    /// Alternate function `af`, push-pull or open-drain, with the given pull resistor.
+    pub fn alternate(self, af: u8, open_drain: bool, pull: Pull) {
+        let (afr, shift) = self.afr();
+        afr.modify(|v| (v & !(0xF << shift)) | (af as u32 & 0xF) << shift);
+        if open_drain {
+            self.reg(OTYPER).set_bits(1 << self.pin);
+        } else {
+            self.reg(OTYPER).clear_bits(1 << self.pin);
+        }
+        self.set_field(PUPDR, pull as u32);
+        self.set_field(MODER, 0b10);
+    }
+
+    pub fn set(self, high: bool) {
+        if high {
+            self.reg(ODR).set_bits(1 << self.pin);
+        } else {
+            self.reg(ODR).clear_bits(1 << self.pin);
+        }
+    }
+
+    pub fn is_high(self) -> bool {
+        self.reg(IDR).read() & (1 << self.pin) != 0
+    }
+}
+
+impl Saved {
+    /// Puts the pin back now rather than at the end of the scope.
+    pub fn restore(self) {
+        drop(self);
+    }
+}
+
+impl Drop for Saved {
+    fn drop(&mut self) {
+        let pin = self.pin;
+        let bit = 1 << pin.pin;
+        let field = 0b11 << (pin.pin * 2);
+        pin.reg(ODR).modify(|v| (v & !bit) | self.odr);
+        pin.reg(OTYPER).modify(|v| (v & !bit) | self.otyper);
+        pin.reg(PUPDR).modify(|v| (v & !field) | self.pupdr);
+        let (afr, shift) = pin.afr();
+        afr.modify(|v| (v & !(0xF << shift)) | self.afr);
+        pin.reg(MODER).modify(|v| (v & !field) | self.moder);
+    }
+}
+
+/// PUPDR values.
+#[derive(Clone, Copy)]
+pub enum Pull {
+    None = 0b00,
+    Up = 0b01,
+    Down = 0b10,
+}
+
+impl Pull {
+    /// The pull towards `high`.
+    pub fn towards(high: bool) -> Self {
+        if high {
+            Pull::Up
+        } else {
+            Pull::Down
+        }
+    }
+}
+
+/// A set of pins saved and restored together, for tests taking a whole table of them: a port's
+/// registers cost the same stack whether one pin or sixteen of them are in use.
+#[derive(Clone, Copy, Default)]
+pub struct PinSet {
+    masks: [u16; PORT_COUNT],
+}
+
+/// The registers of every port a [`PinSet`] touches, from before the test, put back when
+/// dropped.
+pub struct SavedSet {
+    pins: PinSet,
+    regs: [[u32; 4]; PORT_COUNT],
+}
+
+const SAVED_REGS: [usize; 4] = [ODR, OTYPER, PUPDR, MODER];
+
+impl PinSet {
+    /// The pins set in a 16-bit mask per port, in port order A, B, C, H; `None` if any of them
+    /// is an SWD pin.
+    pub fn from_masks(masks: [u16; PORT_COUNT]) -> Option<Self> {
+        (masks[0] & SWD_PINS == 0).then_some(Self { masks })
+    }
+
+    /// Every pin in the set, in port order.
+    pub fn pins(self) -> impl Iterator<Item = Pin> {
+        self.ports().flat_map(move |(index, port)| {
+            (0..16u8)
+                .filter(move |pin| self.masks[index] & (1 << pin) != 0)
+                .map(move |pin| Pin { pin, ..port })
+        })
+    }
+
+    pub fn insert(&mut self, pin: Pin) {
+        self.masks[pin.port_index()] |= 1 << pin.pin;
+    }
+
+    pub fn save(self) -> SavedSet {
+        let mut regs = [[0; 4]; PORT_COUNT];
+        for (index, port) in self.ports() {
+            port.enable_port();
+            for (saved, &offset) in regs[index].iter_mut().zip(&SAVED_REGS) {
+                *saved = port.reg(offset).read();
+            }
+        }
+        SavedSet { pins: self, regs }
+    }
+
+    /// Pin 0 of every port with pins in the set, with its table index.
+    fn ports(self) -> impl Iterator<Item = (usize, Pin)> {
+        (0..8u8)
+            .filter(|port| PORTS & (1 << port) != 0)
+            .map(|port| Pin { port, pin: 0 })
+            .enumerate()
+            .filter(move |&(index, _)| self.masks[index] != 0)
+    }
+}
+
+impl SavedSet {
+    /// Puts the set back now rather than at the end of the scope.
+    pub fn restore(self) {
+        drop(self);
+    }
+}
+
+impl Drop for SavedSet {
+    /// Puts back the set's pins only; other pins of the same ports keep whatever they have now.
+    fn drop(&mut self) {
+        for (index, port) in self.pins.ports() {
+            let bits = self.pins.masks[index] as u32;
+            let fields = (0..16)
+                .filter(|pin| bits & (1 << pin) != 0)
+                .fold(0, |fields, pin| fields | 0b11 << (pin * 2));
+            for (&saved, &offset) in self.regs[index].iter().zip(&SAVED_REGS) {
+                let mask = if offset == ODR || offset == OTYPER {
+                    bits
+                } else {
+                    fields
+                };
+                port.reg(offset).modify(|v| (v & !mask) | (saved & mask));
+            }
+        }
+    }
+}
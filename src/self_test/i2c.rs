@@ -0,0 +1,201 @@
+//! I2C bus scan, part of `self-test-bus`.
+//!
+//! Each address is probed with an address-only write, which every conforming device acknowledges
+//! without side effects; the controller's AUTOEND then closes the transfer with a STOP either way.
+
+use flash_algorithm::ErrorCode;
+
+use super::gpio::{Pin, Pull};
+use super::params::word;
+use super::snapshot::Snapshot;
+use super::table::{flags, group};
+use super::{check_deadline, rcc, SelfTestResult, Test};
+use crate::error::codes;
+use crate::regs::Reg;
+use crate::timeout;
+
+const CR1: usize = 0x00;
+const CR2: usize = 0x04;
+const TIMINGR: usize = 0x10;
+const ISR: usize = 0x18;
+const ICR: usize = 0x1C;
+/// The registers the test reconfigures, in the order they are put back: CR1 holds PE, so last.
+const SAVED_REGS: [usize; 3] = [TIMINGR, CR2, CR1];
+
+const CR1_PE: u32 = 1 << 0;
+const CR2_START: u32 = 1 << 13;
+const CR2_AUTOEND: u32 = 1 << 25;
+const ISR_NACKF: u32 = 1 << 4;
+const ISR_STOPF: u32 = 1 << 5;
+const ISR_BERR: u32 = 1 << 8;
+const ISR_ARLO: u32 = 1 << 9;
+/// NACKCF, STOPCF, BERRCF and ARLOCF.
+const ICR_FLAGS: u32 = ISR_NACKF | ISR_STOPF | ISR_BERR | ISR_ARLO;
+
+/// 100 kHz standard mode from a 4 MHz prescaled clock: SCLL 5 us, SCLH 4 us, SDADEL 500 ns and
+/// SCLDEL 1.25 us, the reference manual's example timings.
+const TIMINGR_100KHZ: u32 = 0x4 << 20 | 0x2 << 16 | 0x0F << 8 | 0x13;
+const TIMINGR_PRESC_SHIFT: u32 = 28;
+const PRESCALED_HZ: u32 = 4_000_000;
+
+/// Every I2C instance uses AF4 on every pin it can reach.
+const AF_I2C: u8 = 4;
+/// Addresses outside this range are reserved by the I2C specification.
+const ADDRESSES: core::ops::RangeInclusive<u8> = 0x08..=0x77;
+
+struct Instance {
+    base: usize,
+    enable_mask: u32,
+    /// Position of the two-bit kernel clock field in RCC_CCIPR; 0 selects PCLK1.
+    ccipr_shift: u32,
+}
+
+/// I2C1 to I2C3, all enabled from APB1ENR1.
+const INSTANCES: [Instance; 3] = [
+    Instance {
+        base: 0x4000_5400,
+        enable_mask: 1 << 21,
+        ccipr_shift: 12,
+    },
+    Instance {
+        base: 0x4000_5800,
+        enable_mask: 1 << 22,
+        ccipr_shift: 14,
+    },
+    Instance {
+        base: 0x4000_5C00,
+        enable_mask: 1 << 23,
+        ccipr_shift: 16,
+    },
+];
+
+impl Instance {
+    fn reg(&self, offset: usize) -> Reg {
+        Reg::at(self.base, offset)
+    }
+}
+
+pub const I2C_SCAN: Test = Test {
+    id: 0x0542,
+    flags: flags::REQUIRES_PARAMS,
+    group: group::PERIPHERALS,
+    expected_ms: 20,
+    run: i2c_scan,
+};
+
+/// Long enough for the internal pull-ups to lift a bus with a few devices on it.
+const PULL_UP_US: u32 = 100;
+/// Far longer than an address-only transfer takes at 100 kHz, clock stretching included.
+const PROBE_TIMEOUT_US: u32 = 1_000;
+
+/// Probes an I2C bus at 100 kHz for devices acknowledging their address, checking first that
+/// neither line is held low.
+///
+/// Parameters: `[instance, pins, targets...]`; instance 0 to 2 is I2C1 to I2C3, `pins` holds
+/// the SCL and SDA [`Pin`] IDs in bytes 0 and 1, and the bytes after it are optional 7-bit
+/// target addresses. With targets only they are probed and each must answer; without, the
+/// whole 0x08 to 0x77 range is scanned and any set of responders passes. The internal pull-ups
+/// are enabled alongside the board's. Values: responders as a 128-bit map in four words
+/// (addresses 0 to 31 first), responders, targets that didn't answer. The instance and pins are
+/// restored after.
+fn i2c_scan(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let instance = word(params, 0)
+        .and_then(|index| INSTANCES.get(index as usize))
+        .ok_or(codes::INVALID_ARGUMENT)?;
+    let pins = word(params, 1).ok_or(codes::INVALID_ARGUMENT)?;
+    let scl = Pin::from_id(pins as u8).ok_or(codes::INVALID_ARGUMENT)?;
+    let sda = Pin::from_id((pins >> 8) as u8)
+        .filter(|sda| sda.id() != scl.id())
+        .ok_or(codes::INVALID_ARGUMENT)?;
+    let targets = params.get(8..).unwrap_or(&[]);
+    if !targets.iter().all(|address| ADDRESSES.contains(address)) {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+
+    let mut found = [0u32; 4];
+    let outcome = rcc::with_clock(rcc::APB1ENR1, instance.enable_mask, || {
+        // Dropped in reverse: the I2C block first, then its pins, then its clock selection.
+        let _ccipr = Snapshot::registers([rcc::CCIPR]);
+        let _scl = scl.save();
+        let _sda = sda.save();
+        let _i2c = Snapshot::peripheral(SAVED_REGS.map(|offset| instance.reg(offset)));
+
+        rcc::CCIPR.clear_bits(0b11 << instance.ccipr_shift);
+        idle(scl, sda)?;
+        scl.alternate(AF_I2C, true, Pull::Up);
+        sda.alternate(AF_I2C, true, Pull::Up);
+        scan(instance, targets, &mut found)
+    });
+
+    let responded = |address: u8| found[address as usize / 32] & (1 << (address % 32)) != 0;
+    let missing = targets
+        .iter()
+        .filter(|&&address| !responded(address))
+        .count() as u32;
+    let count = found.iter().map(|word| word.count_ones()).sum::<u32>();
+    for word in found {
+        result.value(word);
+    }
+    result.value(count);
+    result.value(missing);
+    outcome?;
+    if missing != 0 {
+        result.message(format_args!(
+            "{} of {} targets missing",
+            missing,
+            targets.len()
+        ));
+        return Err(codes::DEVICE_MISSING);
+    }
+    result.message(format_args!("{} devices responding", count));
+    Ok(())
+}
+
+/// Checks both lines float high with only the pull-ups on them.
+fn idle(scl: Pin, sda: Pin) -> Result<(), ErrorCode> {
+    scl.input(Pull::Up);
+    sda.input(Pull::Up);
+    cortex_m::asm::delay(timeout::cycles_for_us(PULL_UP_US));
+    if !scl.is_high() || !sda.is_high() {
+        return Err(codes::BUS_STUCK);
+    }
+    Ok(())
+}
+
+/// Enables the instance and probes `targets`, or the whole address range if there are none,
+/// setting the responders' bits in `found`.
+fn scan(instance: &Instance, targets: &[u8], found: &mut [u32; 4]) -> Result<(), ErrorCode> {
+    let presc = rcc::pclk1_hz().div_ceil(PRESCALED_HZ).clamp(1, 16) - 1;
+    instance.reg(CR1).write(0);
+    instance
+        .reg(TIMINGR)
+        .write(presc << TIMINGR_PRESC_SHIFT | TIMINGR_100KHZ);
+    instance.reg(CR1).write(CR1_PE);
+
+    let probed = ADDRESSES.filter(|address| targets.is_empty() || targets.contains(address));
+    for address in probed {
+        if probe(instance, address)? {
+            found[address as usize / 32] |= 1 << (address % 32);
+        }
+        check_deadline()?;
+    }
+    Ok(())
+}
+
+/// Sends `address` with no data and returns whether it was acknowledged.
+fn probe(instance: &Instance, address: u8) -> Result<bool, ErrorCode> {
+    let isr = instance.reg(ISR);
+    instance.reg(ICR).write(ICR_FLAGS);
+    instance
+        .reg(CR2)
+        .write(CR2_AUTOEND | CR2_START | (address as u32) << 1);
+    if !timeout::wait_us(PROBE_TIMEOUT_US, || isr.read() & ISR_STOPF != 0) {
+        return Err(codes::BUS_TIMEOUT);
+    }
+    let flags = isr.read();
+    instance.reg(ICR).write(ICR_FLAGS);
+    if flags & (ISR_BERR | ISR_ARLO) != 0 {
+        return Err(codes::BUS_STUCK);
+    }
+    Ok(flags & ISR_NACKF == 0)
+}
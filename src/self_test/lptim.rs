@@ -0,0 +1,387 @@
+//! LPTIM1 counter and compare test, and the Stop2 wakeup test it times, part of
+//! `self-test-clocks`.
+//!
+//! LPTIM1 on LSE is the usual tick of a LoRaWAN stack, and the usual way out of Stop2; the tests
+//! run it from the low-speed clock the host picks, starting that oscillator if needed, and put
+//! the timer, its kernel clock selection and the oscillator back afterwards.
+
+use flash_algorithm::ErrorCode;
+
+use super::clocks;
+use super::params::word;
+use super::snapshot::Snapshot;
+use super::table::group;
+use super::{check_deadline, rcc, SelfTestResult, Test};
+use crate::error::codes;
+use crate::regs::Reg;
+use crate::timeout::{self, CycleCounter};
+
+const LPTIM: usize = 0x4000_7C00;
+const LPTIM_ISR: Reg = Reg::at(LPTIM, 0x00);
+const LPTIM_ICR: Reg = Reg::at(LPTIM, 0x04);
+const LPTIM_IER: Reg = Reg::at(LPTIM, 0x08);
+const LPTIM_CFGR: Reg = Reg::at(LPTIM, 0x0C);
+const LPTIM_CR: Reg = Reg::at(LPTIM, 0x10);
+const LPTIM_CMP: Reg = Reg::at(LPTIM, 0x14);
+const LPTIM_ARR: Reg = Reg::at(LPTIM, 0x18);
+const LPTIM_CNT: Reg = Reg::at(LPTIM, 0x1C);
+
+const ISR_CMPM: u32 = 1 << 0;
+const ISR_ARRM: u32 = 1 << 1;
+const ISR_CMPOK: u32 = 1 << 3;
+const ISR_ARROK: u32 = 1 << 4;
+const IER_CMPMIE: u32 = 1 << 0;
+const IER_ARRMIE: u32 = 1 << 1;
+const CR_ENABLE: u32 = 1 << 0;
+const CR_CNTSTRT: u32 = 1 << 2;
+const ARR_MAX: u32 = 0xFFFF;
+
+const APB1ENR1_LPTIM1EN: u32 = 1 << 31;
+const APB1SMENR1_LPTIM1SMEN: u32 = 1 << 31;
+const CCIPR_LPTIM1SEL_SHIFT: u32 = 18;
+const CCIPR_LPTIM1SEL_MASK: u32 = 0b11 << CCIPR_LPTIM1SEL_SHIFT;
+
+/// LPTIM1 reaches the NVIC through direct EXTI line 29, which must be unmasked.
+const EXTI_C1IMR1: Reg = Reg::at(0x5800_0800, 0x80);
+const EXTI_LINE_LPTIM1: u32 = 1 << 29;
+const LPTIM1_IRQ: u32 = 39;
+const NVIC_ISPR1: Reg = Reg::at(0xE000_E200, 4);
+const NVIC_ICPR1: Reg = Reg::at(0xE000_E280, 4);
+
+/// The low-speed clocks LPTIM1 can count, by parameter index.
+#[derive(Clone, Copy)]
+enum Source {
+    Lse,
+    Lsi,
+}
+
+impl Source {
+    /// Source 0 (the default) is LSE and 1 LSI.
+    fn from_param(param: Option<u32>) -> Result<Self, ErrorCode> {
+        match param.unwrap_or(0) {
+            0 => Ok(Source::Lse),
+            1 => Ok(Source::Lsi),
+            _ => Err(codes::INVALID_ARGUMENT),
+        }
+    }
+
+    /// LPTIM1SEL value.
+    fn select(self) -> u32 {
+        match self {
+            Source::Lse => 0b11,
+            Source::Lsi => 0b01,
+        }
+    }
+
+    fn hz(self) -> u32 {
+        match self {
+            Source::Lse => 32_768,
+            Source::Lsi => 32_000,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Source::Lse => "LSE",
+            Source::Lsi => "LSI",
+        }
+    }
+}
+
+pub const LPTIM_TEST: Test = Test {
+    id: 0x0205,
+    flags: 0,
+    group: group::CLOCKS,
+    expected_ms: 1_300,
+    run: lptim,
+};
+
+/// Long enough for one tick to be under 200 ppm of the count at 32 kHz.
+const WINDOW_US: u32 = 200_000;
+/// About 10 ms at 32 kHz between arming the compare and its match.
+const COMPARE_TICKS: u32 = 328;
+const LSE_TIMEOUT_US: u32 = 1_000_000;
+
+/// Counts LPTIM1 ticks over a window timed by the core clock, then arms a compare and checks it
+/// matches and raises the LPTIM1 interrupt.
+///
+/// Parameters: `[source, max_error_ppm, drive]`; source 0 (the default) is LSE and 1 LSI,
+/// `max_error_ppm` bounds the tick count's error against nominal (default 50000, as the window
+/// is only as accurate as the core clock), and `drive` is the LSE drive level should the test
+/// start it (default 3). The interrupt is only ever left pending in the NVIC, never enabled.
+/// Values: ticks counted, ticks expected, error in ppm (signed), compare latency in us.
+fn lptim(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let source = Source::from_param(word(params, 0))?;
+    let max_error_ppm = word(params, 1).unwrap_or(50_000);
+    let drive = word(params, 2).unwrap_or(3);
+    if drive > 3 {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+    with_lptim(source, drive, || check(source, max_error_ppm, result))
+}
+
+/// Runs `f` with LPTIM1 clocked from `source`, starting the oscillator at `drive` if needed,
+/// then puts the timer, LPTIM1SEL and the oscillator back.
+fn with_lptim(
+    source: Source,
+    drive: u32,
+    f: impl FnOnce() -> Result<(), ErrorCode>,
+) -> Result<(), ErrorCode> {
+    rcc::with_backup_access(|| {
+        let saved_bdcr = rcc::BDCR.read();
+        let started = match source {
+            Source::Lse => clocks::start_lse(drive, LSE_TIMEOUT_US)?.is_some(),
+            Source::Lsi => clocks::start_lsi()?,
+        };
+        let outcome = rcc::with_clock(rcc::APB1ENR1, APB1ENR1_LPTIM1EN, || {
+            let _ccipr = Snapshot::registers([rcc::CCIPR]);
+            let select = source.select() << CCIPR_LPTIM1SEL_SHIFT;
+            rcc::CCIPR.modify(|v| (v & !CCIPR_LPTIM1SEL_MASK) | select);
+            let _timer = Saved::take();
+            f()
+        });
+        if started {
+            match source {
+                Source::Lse => clocks::stop_lse(saved_bdcr),
+                Source::Lsi => clocks::stop_lsi(),
+            }
+        }
+        outcome
+    })
+}
+
+/// LPTIM1's configuration from before the test, put back when dropped.
+struct Saved {
+    cr: u32,
+    cfgr: u32,
+    ier: u32,
+    cmp: u32,
+    arr: u32,
+    exti_unmasked: bool,
+}
+
+impl Saved {
+    fn take() -> Self {
+        Saved {
+            cr: LPTIM_CR.read(),
+            cfgr: LPTIM_CFGR.read(),
+            ier: LPTIM_IER.read(),
+            cmp: LPTIM_CMP.read(),
+            arr: LPTIM_ARR.read(),
+            exti_unmasked: EXTI_C1IMR1.read() & EXTI_LINE_LPTIM1 != 0,
+        }
+    }
+}
+
+impl Drop for Saved {
+    /// CFGR and IER only accept writes while the timer is disabled, CMP and ARR only while it is
+    /// enabled, so the timer is put back in three steps.
+    fn drop(&mut self) {
+        LPTIM_CR.write(0);
+        LPTIM_CFGR.write(self.cfgr);
+        LPTIM_IER.write(self.ier);
+        LPTIM_CR.write(CR_ENABLE);
+        // Best effort: without a kernel clock the writes never complete, and nothing is lost.
+        let _ = load(LPTIM_ARR, self.arr, ISR_ARROK);
+        let _ = load(LPTIM_CMP, self.cmp, ISR_CMPOK);
+        LPTIM_CR.write(self.cr);
+        LPTIM_ICR.write(ISR_CMPM | ISR_ARRM | ISR_CMPOK | ISR_ARROK);
+        NVIC_ICPR1.write(1 << (LPTIM1_IRQ - 32));
+        if !self.exti_unmasked {
+            EXTI_C1IMR1.clear_bits(EXTI_LINE_LPTIM1);
+        }
+    }
+}
+
+/// Starts the counter from 0 on the internal clock, unprescaled, with the `ier` interrupts.
+fn start(ier: u32) -> Result<(), ErrorCode> {
+    LPTIM_CR.write(0);
+    LPTIM_CFGR.write(0);
+    LPTIM_IER.write(ier);
+    LPTIM_CR.write(CR_ENABLE);
+    load(LPTIM_ARR, ARR_MAX, ISR_ARROK)?;
+    LPTIM_CR.set_bits(CR_CNTSTRT);
+    Ok(())
+}
+
+fn check(source: Source, max_error_ppm: u32, result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    start(IER_CMPMIE)?;
+
+    let expected = (source.hz() as u64 * WINDOW_US as u64 / 1_000_000) as u32;
+    CycleCounter::enable();
+    let first = count();
+    let begin = CycleCounter::now();
+    let window = timeout::cycles_for_us(WINDOW_US);
+    while CycleCounter::now().wrapping_sub(begin) < window {
+        check_deadline()?;
+    }
+    let ticks = count().wrapping_sub(first) & ARR_MAX;
+    let error_ppm = (ticks as i64 - expected as i64) * 1_000_000 / expected as i64;
+    result.value(ticks);
+    result.value(expected);
+    result.value(error_ppm as u32);
+    if error_ppm.unsigned_abs() > max_error_ppm as u64 {
+        result.message(format_args!(
+            "LPTIM1 on {}: {} ticks, expected {}",
+            source.name(),
+            ticks,
+            expected
+        ));
+        return Err(codes::CLOCK_INACCURATE);
+    }
+
+    let compare_us = compare()?;
+    result.value(compare_us);
+    result.message(format_args!(
+        "LPTIM1 on {}: {} ppm, compare in {} us",
+        source.name(),
+        error_ppm,
+        compare_us
+    ));
+    Ok(())
+}
+
+/// Arms a compare [`COMPARE_TICKS`] ahead and returns how long its match took to raise the
+/// interrupt.
+fn compare() -> Result<u32, ErrorCode> {
+    let irq = 1 << (LPTIM1_IRQ - 32);
+    EXTI_C1IMR1.set_bits(EXTI_LINE_LPTIM1);
+    load(LPTIM_CMP, (count() + COMPARE_TICKS) & ARR_MAX, ISR_CMPOK)?;
+    LPTIM_ICR.write(ISR_CMPM);
+    NVIC_ICPR1.write(irq);
+    CycleCounter::enable();
+    let begin = CycleCounter::now();
+    // Three times the nominal 10 ms, for LSI running slow.
+    let matched = timeout::wait_us(30_000, || LPTIM_ISR.read() & ISR_CMPM != 0);
+    let us = timeout::us_for_cycles(CycleCounter::now().wrapping_sub(begin));
+    if !matched || NVIC_ISPR1.read() & irq == 0 {
+        return Err(codes::CLOCK_TIMEOUT);
+    }
+    Ok(us)
+}
+
+const PWR_CR1: Reg = Reg::at(0x5800_0400, 0x00);
+const PWR_EXTSCR: Reg = Reg::at(0x5800_0400, 0x88);
+const CR1_LPMS_MASK: u32 = 0b111;
+const CR1_LPMS_STOP2: u32 = 0b010;
+/// Clears the Stop and Standby flags.
+const EXTSCR_C1CSSF: u32 = 1 << 0;
+const EXTSCR_C1STOP2F: u32 = 1 << 12;
+const SCB_SCR: Reg = Reg::at(0xE000_ED10, 0);
+const SCR_SLEEPDEEP: u32 = 1 << 2;
+/// Lets a pending interrupt wake WFE even while disabled in the NVIC.
+const SCR_SEVONPEND: u32 = 1 << 4;
+
+pub const STOP2: Test = Test {
+    id: 0x0113,
+    flags: 0,
+    group: group::POWER,
+    expected_ms: 1_100,
+    run: stop2,
+};
+
+/// Enters Stop2 with an LPTIM1 compare armed to wake the core, and checks it stopped, woke from
+/// the compare on time and got its clocks back.
+///
+/// Parameters: `[source, sleep_ms, max_latency_us, drive]`; source 0 (the default) is LSE and 1
+/// LSI, `sleep_ms` is 1 to 1000 (default 10), `max_latency_us` bounds the time from the compare
+/// to the first instruction after WFE (default 1000, at one tick's resolution), and `drive` is
+/// the LSE drive level should the test start it (default 3). The core waits in WFE for the
+/// interrupt to turn pending, which it never enables, and LPTIM1's reload match after about two
+/// seconds backs up the compare. HSE32, the PLL and SYSCLK are restored before the test returns.
+/// A debugger that set DBGMCU_CR.DBG_STOP keeps the core clocked through Stop2, so the latency
+/// then leaves out the clock restart. Values: ticks slept, ticks expected, latency in us
+/// (signed, negative for an early wakeup), 1 if PWR saw Stop2 entered.
+fn stop2(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let source = Source::from_param(word(params, 0))?;
+    let sleep_ms = word(params, 1).unwrap_or(10);
+    let max_latency_us = word(params, 2).unwrap_or(1_000);
+    let drive = word(params, 3).unwrap_or(3);
+    if !(1..=1_000).contains(&sleep_ms) || drive > 3 {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+    let ticks = source.hz() * sleep_ms / 1_000;
+
+    with_lptim(source, drive, || {
+        rcc::with_clock(rcc::APB1SMENR1, APB1SMENR1_LPTIM1SMEN, || {
+            let (armed, woke, entered) = sleep(ticks)?;
+            let slept = woke.wrapping_sub(armed) & ARR_MAX;
+            let late = slept as i32 - ticks as i32;
+            let latency_us = late as i64 * 1_000_000 / source.hz() as i64;
+            result.value(slept);
+            result.value(ticks);
+            result.value(latency_us as i32 as u32);
+            result.value(entered as u32);
+            result.message(format_args!(
+                "Stop2 on {}: {} of {} ticks, {} us late",
+                source.name(),
+                slept,
+                ticks,
+                latency_us
+            ));
+            if !entered || late < 0 || latency_us > max_latency_us as i64 {
+                return Err(codes::LOW_POWER_FAULT);
+            }
+            Ok(())
+        })
+    })
+}
+
+/// Arms the compare `ticks` ahead and sleeps in Stop2 until it wakes the core, returning the
+/// count at arming and at wakeup and whether PWR flagged Stop2.
+fn sleep(ticks: u32) -> Result<(u32, u32, bool), ErrorCode> {
+    // The counter starts from 0, so the compare comes well before the reload match.
+    start(IER_CMPMIE | IER_ARRMIE)?;
+    let irq = 1 << (LPTIM1_IRQ - 32);
+    EXTI_C1IMR1.set_bits(EXTI_LINE_LPTIM1);
+    let armed = count();
+    load(LPTIM_CMP, armed + ticks, ISR_CMPOK)?;
+    LPTIM_ICR.write(ISR_CMPM | ISR_ARRM);
+    NVIC_ICPR1.write(irq);
+    PWR_EXTSCR.write(EXTSCR_C1CSSF);
+
+    let saved_cr = rcc::CR.read();
+    let saved_cfgr = rcc::CFGR.read();
+    let saved_pwr = PWR_CR1.read();
+    let saved_scr = SCB_SCR.read();
+    PWR_CR1.modify(|v| (v & !CR1_LPMS_MASK) | CR1_LPMS_STOP2);
+    SCB_SCR.set_bits(SCR_SLEEPDEEP | SCR_SEVONPEND);
+    // The first WFE only consumes the event SEV latched, so the second one really sleeps.
+    cortex_m::asm::dsb();
+    cortex_m::asm::sev();
+    cortex_m::asm::wfe();
+    cortex_m::asm::wfe();
+    let woke = count();
+    SCB_SCR.write(saved_scr);
+    PWR_CR1.write(saved_pwr);
+
+    let resumed = clocks::resume(saved_cr, saved_cfgr);
+    let entered = PWR_EXTSCR.read() & EXTSCR_C1STOP2F != 0;
+    PWR_EXTSCR.write(EXTSCR_C1CSSF);
+    NVIC_ICPR1.write(irq);
+    resumed?;
+    Ok((armed, woke, entered))
+}
+
+/// The counter, read until two reads agree: it runs on the asynchronous kernel clock.
+fn count() -> u32 {
+    loop {
+        let count = LPTIM_CNT.read();
+        if LPTIM_CNT.read() == count {
+            return count;
+        }
+    }
+}
+
+/// Writes CMP or ARR and waits for the value to cross into the kernel clock domain, signalled
+/// by `ok` in ISR, which is then cleared.
+fn load(reg: Reg, value: u32, ok: u32) -> Result<(), ErrorCode> {
+    LPTIM_ICR.write(ok);
+    reg.write(value);
+    // A few kernel clock cycles, well under a millisecond at 32 kHz.
+    if !timeout::wait_us(1_000, || LPTIM_ISR.read() & ok != 0) {
+        return Err(codes::CLOCK_TIMEOUT);
+    }
+    LPTIM_ICR.write(ok);
+    Ok(())
+}
@@ -0,0 +1,275 @@
+//! Self tests advertised in `SelfTestInfo` and `SelfTestTable`, dispatched through the `SelfTest` entry point.
+//!
+//! The macro's `self_tests` metadata only describes the tests; [`TESTS`] binds each advertised
+//! ID to the code that runs it. Macro input can't be feature-gated, so the metadata lists only
+//! the always-present tests and `SelfTestTable` is the complete list for a given build, including
+//! the `self-test-*` families. Each run leaves its
+//! [`SelfTestResult`] in the exported `SelfTestMailbox`; parameters come from the bytes the host
+//! wrote into `SelfTestParams` beforehand. `SelfTestAll` runs the whole registry in order, with
+//! no parameters, and records each status in `SelfTestStatuses` and the passes in
+//! `SelfTestPassed`; with `self-test-record`, `SelfTestRecord` then keeps that run in flash.
+//! With `self-test-postcard` each result is also left in `SelfTestEncoded`, as postcard bytes
+//! holding only the values the test recorded.
+
+use flash_algorithm::ErrorCode;
+
+use crate::error::codes;
+use crate::timeout;
+
+#[cfg(feature = "self-test-analog")]
+mod analog;
+#[cfg(feature = "self-test-burn-in")]
+mod burn_in;
+#[cfg(feature = "self-test-clocks")]
+mod clocks;
+mod cpu;
+#[cfg(feature = "self-test-flash")]
+mod crc;
+#[cfg(feature = "self-test-crypto")]
+mod crypto;
+mod deadline;
+#[cfg(feature = "self-test-memory")]
+mod dma;
+#[cfg(feature = "self-test-flash")]
+mod flash;
+#[cfg(any(
+    feature = "self-test-analog",
+    feature = "self-test-bus",
+    feature = "self-test-gpio",
+    feature = "self-test-radio",
+    feature = "self-test-timers"
+))]
+mod gpio;
+#[cfg(feature = "self-test-bus")]
+mod i2c;
+#[cfg(feature = "self-test-clocks")]
+mod lptim;
+mod params;
+#[cfg(feature = "self-test-gpio")]
+mod pins;
+#[cfg(feature = "self-test-power")]
+mod power;
+#[cfg(feature = "self-test-radio")]
+mod radio;
+#[cfg(feature = "self-test-memory")]
+mod ram;
+mod rcc;
+#[cfg(feature = "self-test-record")]
+mod record;
+mod result;
+#[cfg(feature = "self-test-clocks")]
+mod rtc;
+mod snapshot;
+#[cfg(feature = "self-test-bus")]
+mod spi;
+mod table;
+#[cfg(feature = "self-test-timers")]
+mod timers;
+#[cfg(feature = "self-test-bus")]
+mod uart;
+#[cfg(feature = "self-test-watchdog")]
+mod watchdog;
+
+pub use deadline::check_deadline;
+use params::SelfTestParams;
+#[cfg(feature = "self-test-record")]
+pub use record::save_record;
+#[cfg(feature = "self-test-postcard")]
+use result::SelfTestEncoded;
+use result::{SelfTestMailbox, SelfTestPassed, SelfTestSkip, SelfTestStatuses};
+pub use result::{SelfTestResult, MASK_WORDS, MAX_TESTS};
+
+/// Implementation of one self test bound to the ID it is advertised under.
+pub struct Test {
+    pub id: u32,
+    /// [`table::flags`] bits, exported in `SelfTestTable`.
+    pub flags: u32,
+    /// [`table::group`] the test belongs to; groups run in ascending order.
+    pub group: u32,
+    pub expected_ms: u32,
+    pub run: fn(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode>,
+}
+
+/// In execution order, sorted by group; bit `n` of the `SelfTestAll` masks refers to `TESTS[n]`.
+const TESTS: &[Test] = &[
+    Test {
+        id: 1,
+        flags: 0,
+        group: table::group::GENERAL,
+        expected_ms: 1,
+        run: simple,
+    },
+    cpu::CPU,
+    #[cfg(feature = "self-test-analog")]
+    analog::VDDA,
+    #[cfg(feature = "self-test-power")]
+    power::REGULATOR,
+    #[cfg(feature = "self-test-power")]
+    power::BACKUP_RETENTION,
+    #[cfg(feature = "self-test-clocks")]
+    lptim::STOP2,
+    #[cfg(feature = "self-test-power")]
+    power::WAKEUP_PIN,
+    #[cfg(feature = "self-test-clocks")]
+    clocks::HSE32,
+    #[cfg(feature = "self-test-clocks")]
+    clocks::LSE,
+    #[cfg(feature = "self-test-clocks")]
+    clocks::LSI_LSE,
+    #[cfg(feature = "self-test-clocks")]
+    rtc::RTC_TEST,
+    #[cfg(feature = "self-test-clocks")]
+    lptim::LPTIM_TEST,
+    #[cfg(feature = "self-test-memory")]
+    ram::MARCH_C,
+    #[cfg(feature = "self-test-memory")]
+    ram::PATTERNS,
+    #[cfg(feature = "self-test-memory")]
+    ram::SRAM2_PARITY,
+    #[cfg(feature = "self-test-memory")]
+    dma::DMA_M2M,
+    #[cfg(feature = "self-test-flash")]
+    crc::CRC_KAT,
+    #[cfg(feature = "self-test-flash")]
+    flash::CRC,
+    #[cfg(feature = "self-test-flash")]
+    flash::ECC,
+    #[cfg(feature = "self-test-flash")]
+    flash::SCRATCH,
+    #[cfg(feature = "self-test-radio")]
+    radio::PRESENCE,
+    #[cfg(feature = "self-test-radio")]
+    radio::CONTINUOUS_WAVE,
+    #[cfg(feature = "self-test-radio")]
+    radio::RSSI,
+    #[cfg(feature = "self-test-radio")]
+    radio::RF_SWITCH,
+    #[cfg(feature = "self-test-watchdog")]
+    watchdog::IWDG_TEST,
+    #[cfg(feature = "self-test-watchdog")]
+    watchdog::WWDG_TEST,
+    #[cfg(feature = "self-test-crypto")]
+    crypto::TRNG,
+    #[cfg(feature = "self-test-crypto")]
+    crypto::AES_KAT,
+    #[cfg(feature = "self-test-crypto")]
+    crypto::PKA_KAT,
+    #[cfg(feature = "self-test-analog")]
+    analog::TEMPERATURE,
+    #[cfg(feature = "self-test-analog")]
+    analog::DAC_READBACK,
+    #[cfg(feature = "self-test-analog")]
+    analog::COMPARATOR,
+    #[cfg(feature = "self-test-gpio")]
+    pins::LOOPBACK,
+    #[cfg(feature = "self-test-gpio")]
+    pins::PULLS,
+    #[cfg(feature = "self-test-bus")]
+    uart::UART_LOOPBACK,
+    #[cfg(feature = "self-test-bus")]
+    i2c::I2C_SCAN,
+    #[cfg(feature = "self-test-bus")]
+    spi::SPI_PROBE,
+    #[cfg(feature = "self-test-timers")]
+    timers::PWM,
+    #[cfg(feature = "self-test-burn-in")]
+    burn_in::BURN_IN,
+];
+
+const _: () = assert!(TESTS.len() <= MAX_TESTS, "one mask bit per test");
+const _: () = assert!(codes::TEST_SKIPPED.get() == protocol::STATUS_SKIPPED);
+
+/// Test 1: reaching the test code at all proves the entry point, stack and RTT are usable.
+///
+/// Reports the core clock the host passed to `Init`, which every timed test depends on.
+fn simple(_params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let clock_hz = timeout::clock_hz();
+    result.value(clock_hz);
+    result.message(format_args!("core clock {} Hz", clock_hz));
+    Ok(())
+}
+
+pub fn run(id: u32) -> Result<(), ErrorCode> {
+    let test = TESTS
+        .iter()
+        .find(|test| test.id == id)
+        .ok_or(codes::UNKNOWN_TEST)?;
+
+    let mut outcome = Ok(());
+    SelfTestParams.update(|params| {
+        let len = core::mem::take(&mut params.len) as usize;
+        outcome = match params.data.get(..len) {
+            Some(params) => execute(test, params),
+            None => Err(codes::INVALID_ARGUMENT),
+        };
+    });
+    outcome
+}
+
+/// Runs `id` with `words` as its parameters, as if the host had written them into
+/// `SelfTestParams`, and hands the result it left in `SelfTestMailbox` to `report`.
+#[cfg(feature = "console")]
+pub fn run_with(
+    id: u32,
+    words: &[u32],
+    report: impl FnOnce(&SelfTestResult),
+) -> Result<(), ErrorCode> {
+    let params = protocol::SelfTestParameters::from_words(words).ok_or(codes::INVALID_ARGUMENT)?;
+    SelfTestParams.write(params);
+    let outcome = run(id);
+    SelfTestMailbox.update(|result| report(result));
+    outcome
+}
+
+/// Flags of the tests `SelfTestAll` leaves out: those that can't pass without what only a host
+/// running them alone provides, and those that wear the part or leave a watchdog running.
+const RUN_ALL_EXCLUDES: u32 =
+    table::flags::DESTRUCTIVE | table::flags::REQUIRES_FIXTURE | table::flags::REQUIRES_PARAMS;
+
+/// Runs every test not set in `SelfTestSkip` or `skip_mask` nor flagged in [`RUN_ALL_EXCLUDES`],
+/// leaving the ones that passed in `SelfTestPassed` and returning its first word; the others are
+/// left as [`codes::TEST_SKIPPED`] in `SelfTestStatuses`.
+pub fn run_all(skip_mask: u32) -> u32 {
+    let mut skip = [0; MASK_WORDS];
+    SelfTestSkip.update(|mask| skip = core::mem::take(mask));
+    skip[0] |= skip_mask;
+    SelfTestStatuses.write([codes::TEST_SKIPPED.get(); MAX_TESTS]);
+    let mut passed = [0; MASK_WORDS];
+    for (bit, test) in TESTS.iter().enumerate() {
+        if protocol::mask_has(&skip, bit) || test.flags & RUN_ALL_EXCLUDES != 0 {
+            continue;
+        }
+        let outcome = execute(test, &[]);
+        SelfTestStatuses.update(|statuses| {
+            statuses[bit] = match outcome {
+                Ok(()) => 0,
+                Err(e) => e.get(),
+            }
+        });
+        if outcome.is_ok() {
+            protocol::mask_set(&mut passed, bit);
+        }
+    }
+    SelfTestPassed.write(passed);
+    passed[0]
+}
+
+/// Runs one test, leaving its detailed result in `SelfTestMailbox`.
+fn execute(test: &Test, params: &[u8]) -> Result<(), ErrorCode> {
+    let mut outcome = Ok(());
+    SelfTestMailbox.update(|result| {
+        result.start(test.id);
+        deadline::arm(test.expected_ms);
+        // A test that never polled its deadline still fails if it finished late.
+        outcome = (test.run)(params, result).and_then(|()| check_deadline());
+        let duration_us = deadline::elapsed_us();
+        let status = match outcome {
+            Ok(()) => 0,
+            Err(e) => e.get(),
+        };
+        result.finish(status, duration_us);
+        #[cfg(feature = "self-test-postcard")]
+        SelfTestEncoded.update(|encoded| encoded.encode(result));
+    });
+    outcome
+}
@@ -0,0 +1,14 @@
+//! Parameter block the host fills in before calling `SelfTest(id)`, laid out by the protocol
+//! crate.
+
+use protocol::SelfTestParameters;
+
+use crate::mailbox::Mailbox;
+
+#[allow(unused_imports)] // Only the `self-test-*` families take parameters.
+pub use protocol::word;
+
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+pub static SelfTestParams: Mailbox<SelfTestParameters> = Mailbox::new(SelfTestParameters::EMPTY);
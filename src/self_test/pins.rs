@@ -0,0 +1,172 @@
+//! Board-level pin tests, enabled by `self-test-gpio`, for connector and solder-joint checks.
+
+use flash_algorithm::ErrorCode;
+
+use super::gpio::{Pin, PinSet, Pull};
+use super::params::word;
+use super::table::{flags, group};
+use super::{check_deadline, SelfTestResult, Test};
+use crate::error::codes;
+use crate::timeout;
+
+/// Long enough for an internal pull (about 40 kOhm) to charge a connector and cable.
+const SETTLE_US: u32 = 20;
+
+fn settle() {
+    cortex_m::asm::delay(timeout::cycles_for_us(SETTLE_US));
+}
+
+pub const LOOPBACK: Test = Test {
+    id: 0x0531,
+    flags: flags::REQUIRES_FIXTURE | flags::REQUIRES_PARAMS,
+    group: group::PERIPHERALS,
+    expected_ms: 5,
+    run: loopback,
+};
+
+/// Loopback faults, two bits per pair in the result values; both together mean neither level
+/// came through, an open joint or missing jumper.
+const STUCK_LOW: u64 = 0b01;
+const STUCK_HIGH: u64 = 0b10;
+const MAX_PAIRS: usize = 32;
+
+/// The (drive, sense) pairs in `params`, which [`loopback`] has checked are all valid pins.
+fn pairs(params: &[u8]) -> impl Iterator<Item = (Pin, Pin)> + '_ {
+    params
+        .chunks_exact(2)
+        .filter_map(|pair| Some((Pin::from_id(pair[0])?, Pin::from_id(pair[1])?)))
+}
+
+/// Checks a table of (drive, sense) pin pairs wired together by the fixture, in both directions.
+///
+/// Parameters: up to 32 pairs of [`Pin`] IDs, one byte each, drive first. For each pair and
+/// level, every sense pin is pulled to the opposite level and only the pair's own drive pin is
+/// an output, so its sense pin must follow while all the others must not; one that does is
+/// shorted to the driven net. Values: number of pairs, the faults of pairs 0 to 15 and 16 to 31
+/// at two bits each (1 stuck low, 2 stuck high, 3 open), then the bitmap of pairs involved in a
+/// short. Pins are restored after.
+fn loopback(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let count = params.len() / 2;
+    if count == 0 || count > MAX_PAIRS || !params.len().is_multiple_of(2) {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+    let mut set = PinSet::default();
+    for &id in params {
+        set.insert(Pin::from_id(id).ok_or(codes::INVALID_ARGUMENT)?);
+    }
+
+    let saved = set.save();
+    let mut faults = 0;
+    let mut shorts = 0;
+    let outcome = exercise(params, &mut faults, &mut shorts);
+    saved.restore();
+    outcome?;
+
+    result.value(count as u32);
+    result.value(faults as u32);
+    result.value((faults >> 32) as u32);
+    result.value(shorts);
+    let faulty = (0..count)
+        .filter(|&i| faults >> (i * 2) & 0b11 != 0 || shorts & (1 << i) != 0)
+        .count();
+    if faulty != 0 {
+        result.message(format_args!("{} of {} pairs faulty", faulty, count));
+        return Err(codes::PIN_FAULT);
+    }
+    result.message(format_args!("{} pairs OK", count));
+    Ok(())
+}
+
+fn exercise(params: &[u8], faults: &mut u64, shorts: &mut u32) -> Result<(), ErrorCode> {
+    for (i, (drive, sense)) in pairs(params).enumerate() {
+        for level in [true, false] {
+            for (other_drive, other_sense) in pairs(params) {
+                other_drive.input(Pull::None);
+                other_sense.input(Pull::towards(!level));
+            }
+            drive.output(level);
+            settle();
+
+            if sense.is_high() != level {
+                *faults |= if level { STUCK_LOW } else { STUCK_HIGH } << (i * 2);
+            }
+            for (j, (_, other_sense)) in pairs(params).enumerate() {
+                if j != i && other_sense.is_high() == level {
+                    *shorts |= 1 << i | 1 << j;
+                }
+            }
+            check_deadline()?;
+        }
+    }
+    Ok(())
+}
+
+pub const PULLS: Test = Test {
+    id: 0x0532,
+    flags: flags::REQUIRES_PARAMS,
+    group: group::PERIPHERALS,
+    expected_ms: 5,
+    run: pulls,
+};
+
+/// Pulls every pin in the host's mask up and then down and checks it follows each time, which
+/// an unconnected pin does and one shorted to a rail or strongly driven net doesn't.
+///
+/// Parameters: `[a_b, c_h]`, the pins of ports A and B in the low and high half of word 0 and
+/// of C and H in word 1, both required. Pins the board drives on purpose belong outside the
+/// mask, as do PA13 and PA14, which are refused. Values: pins failing the pull-up as
+/// `[a_b, c_h]`, then those failing the pull-down the same way. Pins are restored after.
+fn pulls(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let (a_b, c_h) = match (word(params, 0), word(params, 1)) {
+        (Some(a_b), Some(c_h)) => (a_b, c_h),
+        _ => return Err(codes::INVALID_ARGUMENT),
+    };
+    let masks = [
+        a_b as u16,
+        (a_b >> 16) as u16,
+        c_h as u16,
+        (c_h >> 16) as u16,
+    ];
+    let set = PinSet::from_masks(masks).ok_or(codes::INVALID_ARGUMENT)?;
+    if set.pins().next().is_none() {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+
+    let saved = set.save();
+    let mut failed = [0; 2];
+    let outcome = pull_each(set, &mut failed);
+    saved.restore();
+    outcome?;
+
+    for failed in failed {
+        result.value(failed as u32);
+        result.value((failed >> 32) as u32);
+    }
+    let count = set.pins().count();
+    let faulty = (failed[0] | failed[1]).count_ones();
+    if faulty != 0 {
+        result.message(format_args!(
+            "{} of {} pins don't follow their pull",
+            faulty, count
+        ));
+        return Err(codes::PIN_FAULT);
+    }
+    result.message(format_args!("{} pins follow both pulls", count));
+    Ok(())
+}
+
+/// Pulls `set` up, then down, recording the pins that don't follow in `failed[0]` and
+/// `failed[1]`.
+fn pull_each(set: PinSet, failed: &mut [u64; 2]) -> Result<(), ErrorCode> {
+    for (failed, level) in failed.iter_mut().zip([true, false]) {
+        for pin in set.pins() {
+            pin.input(Pull::towards(level));
+        }
+        settle();
+        for pin in set.pins().filter(|pin| pin.is_high() != level) {
+            *failed |= pin.bit();
+        }
+        check_deadline()?;
+    }
+    Ok(())
+}
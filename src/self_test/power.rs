@@ -0,0 +1,236 @@
+//! Power controller tests, enabled by `self-test-power` (RM0461, chapter 5).
+
+use flash_algorithm::ErrorCode;
+
+use super::params::word;
+use super::table::{flags, group};
+use super::{check_deadline, rcc, SelfTestResult, Test};
+use crate::error::codes;
+use crate::regs::Reg;
+use crate::timeout::{self, CycleCounter};
+
+const PWR: usize = 0x5800_0400;
+const PWR_CR3: Reg = Reg::at(PWR, 0x08);
+const PWR_CR4: Reg = Reg::at(PWR, 0x0C);
+const PWR_SR1: Reg = Reg::at(PWR, 0x10);
+const PWR_SR2: Reg = Reg::at(PWR, 0x14);
+const PWR_SCR: Reg = Reg::at(PWR, 0x18);
+const PWR_CR5: Reg = Reg::at(PWR, 0x80);
+const SR2_SMPSRDY: u32 = 1 << 3;
+const SR2_LDORDY: u32 = 1 << 4;
+/// Set while the main regulator runs from the SMPS, clear on the LDO.
+const SR2_REGMRS: u32 = 1 << 6;
+const CR5_SMPSEN: u32 = 1 << 15;
+
+pub const REGULATOR: Test = Test {
+    id: 0x0111,
+    flags: 0,
+    group: group::POWER,
+    expected_ms: 5,
+    run: regulator,
+};
+
+const OPTION_SMPS: u32 = 1 << 0;
+/// Far longer than either converter takes to take over the supply.
+const SWITCH_TIMEOUT_US: u32 = 2_000;
+
+/// Switches the main regulator to the LDO and, on boards with the SMPS inductor fitted, to the
+/// SMPS, timing each hand-over; the previous mode is restored afterwards.
+///
+/// Parameters: `[options]`; bit 0 tells the test the board has the SMPS fitted, without it only
+/// the LDO is checked. A missing or misplaced inductor shows up as the SMPS never becoming ready.
+/// Values: LDO switch time in us, SMPS switch time in us (0 if not checked).
+fn regulator(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let smps = word(params, 0).unwrap_or(0) & OPTION_SMPS != 0;
+    let saved = PWR_CR5.read() & CR5_SMPSEN;
+
+    let outcome = switch(false).and_then(|ldo_us| {
+        result.value(ldo_us);
+        let smps_us = if smps { switch(true)? } else { 0 };
+        result.value(smps_us);
+        result.message(format_args!("LDO in {} us, SMPS in {} us", ldo_us, smps_us));
+        Ok(())
+    });
+    PWR_CR5.modify(|v| (v & !CR5_SMPSEN) | saved);
+    outcome
+}
+
+/// Selects the SMPS or the LDO and waits until the regulator reports running from it.
+fn switch(smps: bool) -> Result<u32, ErrorCode> {
+    let (ready, mode) = if smps {
+        (SR2_SMPSRDY, SR2_REGMRS)
+    } else {
+        (SR2_LDORDY, 0)
+    };
+    CycleCounter::enable();
+    let begin = CycleCounter::now();
+    if smps {
+        PWR_CR5.set_bits(CR5_SMPSEN);
+    } else {
+        PWR_CR5.clear_bits(CR5_SMPSEN);
+    }
+    let timeout = timeout::cycles_for_us(SWITCH_TIMEOUT_US);
+    loop {
+        let elapsed = CycleCounter::now().wrapping_sub(begin);
+        let sr2 = PWR_SR2.read();
+        if sr2 & ready != 0 && sr2 & SR2_REGMRS == mode {
+            return Ok(timeout::us_for_cycles(elapsed));
+        }
+        if elapsed >= timeout {
+            return Err(codes::REGULATOR_FAULT);
+        }
+        check_deadline()?;
+    }
+}
+
+/// TAMP_BKP0R; the 20 backup registers follow it.
+const TAMP_BKP0R: usize = 0x4000_B100;
+const BACKUP_REGISTERS: u32 = 20;
+const APB1ENR1_RTCAPBEN: u32 = 1 << 10;
+const PATTERN: u32 = 0x5AA5_C33C;
+
+pub const BACKUP_RETENTION: Test = Test {
+    id: 0x0112,
+    flags: flags::DESTRUCTIVE,
+    group: group::POWER,
+    expected_ms: 1,
+    run: backup_retention,
+};
+
+const PHASE_WRITE: u32 = 0;
+const PHASE_VERIFY: u32 = 1;
+
+/// Register `index`'s pattern, different in every byte from its neighbours'.
+fn pattern(index: u32) -> u32 {
+    PATTERN ^ index.wrapping_mul(0x0101_0101)
+}
+
+/// Checks the backup registers keep their contents across a supply interruption, in two
+/// invocations with the fixture's power cycle in between.
+///
+/// Parameters: `[phase, mask]`; phase 0 (the default) writes a pattern to every register in
+/// `mask`, phase 1 checks it and then clears them. `mask` defaults to all 20 registers, whose
+/// previous contents are lost either way. Values: mask, then for phase 1 the bitmap of registers
+/// that lost their data.
+fn backup_retention(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let phase = word(params, 0).unwrap_or(PHASE_WRITE);
+    let mask = word(params, 1).unwrap_or((1 << BACKUP_REGISTERS) - 1);
+    if phase > PHASE_VERIFY || mask >> BACKUP_REGISTERS != 0 {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+    result.value(mask);
+
+    let lost = rcc::with_backup_access(|| {
+        rcc::with_clock(rcc::APB1ENR1, APB1ENR1_RTCAPBEN, || {
+            let mut lost = 0;
+            for index in (0..BACKUP_REGISTERS).filter(|index| mask & (1 << index) != 0) {
+                let reg = Reg::at(TAMP_BKP0R, index as usize * 4);
+                if phase == PHASE_WRITE {
+                    reg.write(pattern(index));
+                } else {
+                    if reg.read() != pattern(index) {
+                        lost |= 1 << index;
+                    }
+                    reg.write(0);
+                }
+            }
+            lost
+        })
+    });
+
+    if phase == PHASE_WRITE {
+        result.message(format_args!("pattern written, power cycle then verify"));
+        return Ok(());
+    }
+    result.value(lost);
+    result.message(format_args!(
+        "{} of {} registers lost data",
+        lost.count_ones(),
+        mask.count_ones()
+    ));
+    if lost != 0 {
+        return Err(codes::RETENTION_LOST);
+    }
+    Ok(())
+}
+
+pub const WAKEUP_PIN: Test = Test {
+    id: 0x0114,
+    flags: flags::REQUIRES_FIXTURE | flags::REQUIRES_PARAMS,
+    group: group::POWER,
+    expected_ms: 1_000,
+    run: wakeup_pin,
+};
+
+/// WKUP1 is PA0, WKUP2 PC13 and WKUP3 PB3.
+const WAKEUP_PINS: u32 = 3;
+const PHASE_ARM: u32 = 0;
+const PHASE_CHECK: u32 = 1;
+/// Long enough for a pin already at its active level to raise the flag again.
+const ARM_SETTLE_US: u32 = 100;
+
+/// Checks a WKUP pin raises its wakeup flag, the one that would bring the device out of
+/// Standby, in two invocations with the fixture asserting the pin in between.
+///
+/// Parameters: `[phase, pin, polarity, timeout_ms]`; `pin` is 1 to 3 for WKUP1 to WKUP3 and
+/// `polarity` 0 (the default) for a rising edge, 1 for a falling one. Phase 0 (the default)
+/// clears the pin's flag, arms it and fails if the flag sets again straight away, the pin
+/// already being active; the fixture then asserts it. Phase 1, given the same pin, waits up to
+/// `timeout_ms` (default 500, at most 900) for the flag, then clears it and disarms the pin, so
+/// the fixture may also assert it only once phase 1 has started. The polarity bit is left as
+/// phase 0 set it. Values: pin, polarity, then for phase 1 the time until the flag in us.
+fn wakeup_pin(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let phase = word(params, 0).unwrap_or(PHASE_ARM);
+    let pin = word(params, 1).ok_or(codes::INVALID_ARGUMENT)?;
+    let polarity = word(params, 2).unwrap_or(0);
+    let timeout_ms = word(params, 3).unwrap_or(500);
+    if phase > PHASE_CHECK || !(1..=WAKEUP_PINS).contains(&pin) || polarity > 1 || timeout_ms > 900
+    {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+    let bit = 1 << (pin - 1);
+    result.value(pin);
+    result.value(polarity);
+
+    if phase == PHASE_ARM {
+        PWR_CR3.clear_bits(bit);
+        PWR_CR4.modify(|v| (v & !bit) | polarity << (pin - 1));
+        PWR_SCR.write(bit);
+        PWR_CR3.set_bits(bit);
+        cortex_m::asm::delay(timeout::cycles_for_us(ARM_SETTLE_US));
+        if PWR_SR1.read() & bit != 0 {
+            PWR_CR3.clear_bits(bit);
+            PWR_SCR.write(bit);
+            result.message(format_args!("WKUP{} already active", pin));
+            return Err(codes::LOW_POWER_FAULT);
+        }
+        result.message(format_args!("WKUP{} armed, assert then check", pin));
+        return Ok(());
+    }
+
+    if PWR_CR3.read() & bit == 0 {
+        result.message(format_args!("WKUP{} not armed", pin));
+        return Err(codes::INVALID_ARGUMENT);
+    }
+    CycleCounter::enable();
+    let begin = CycleCounter::now();
+    let timeout = timeout::cycles_for_us(timeout_ms * 1_000);
+    let outcome = loop {
+        let elapsed = CycleCounter::now().wrapping_sub(begin);
+        if PWR_SR1.read() & bit != 0 {
+            break Ok(timeout::us_for_cycles(elapsed));
+        }
+        if elapsed >= timeout {
+            break Err(codes::LOW_POWER_FAULT);
+        }
+        if let Err(e) = check_deadline() {
+            break Err(e);
+        }
+    };
+    PWR_CR3.clear_bits(bit);
+    PWR_SCR.write(bit);
+    let us = outcome?;
+    result.value(us);
+    result.message(format_args!("WKUP{} flagged after {} us", pin, us));
+    Ok(())
+}
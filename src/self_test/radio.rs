@@ -0,0 +1,522 @@
+//! SubGHz radio tests over the internal SUBGHZSPI link, enabled by `self-test-radio`.
+//!
+//! The radio is an SX126x-class transceiver behind a dedicated SPI master (RM0461, sections 4
+//! and 5): NSS is driven from PWR_SUBGHZSPICR and BUSY read from PWR_SR2.
+
+use flash_algorithm::ErrorCode;
+
+use super::gpio::{Pin, Saved};
+use super::params::word;
+use super::snapshot::Snapshot;
+use super::table::{flags, group};
+use super::{check_deadline, rcc, SelfTestResult, Test};
+use crate::error::codes;
+use crate::regs::Reg;
+use crate::timeout::{self, Deadline, Timeout};
+
+const SPI: usize = 0x5801_0000;
+const SPI_CR1: Reg = Reg::at(SPI, 0x00);
+const SPI_CR2: Reg = Reg::at(SPI, 0x04);
+const SPI_SR: Reg = Reg::at(SPI, 0x08);
+const SPI_DR: usize = SPI + 0x0C;
+
+const CR1_MSTR: u32 = 1 << 2;
+/// fPCLK3 / 8, at most 6 MHz at the WLE5's 48 MHz maximum; the radio accepts up to 16 MHz.
+const CR1_BR_DIV8: u32 = 0b010 << 3;
+const CR1_SPE: u32 = 1 << 6;
+const CR1_SSI: u32 = 1 << 8;
+const CR1_SSM: u32 = 1 << 9;
+const CR2_DS_8BIT: u32 = 0b0111 << 8;
+const CR2_FRXTH: u32 = 1 << 12;
+const SR_RXNE: u32 = 1 << 0;
+const SR_TXE: u32 = 1 << 1;
+
+const PWR_SR2: Reg = Reg::at(0x5800_0400, 0x14);
+const SR2_RFBUSYS: u32 = 1 << 1;
+const PWR_SUBGHZSPICR: Reg = Reg::at(0x5800_0400, 0x90);
+const SUBGHZSPICR_NSS: u32 = 1 << 15;
+
+const APB3ENR_SUBGHZSPIEN: u32 = 1 << 0;
+/// Holds the radio in reset while set; RFRSTF reads back whether it still is.
+const CSR_RFRST: u32 = 1 << 15;
+const CSR_RFRSTF: u32 = 1 << 14;
+
+const GET_RSSI_INST: u8 = 0x15;
+const GET_STATUS: u8 = 0xC0;
+const READ_REGISTER: u8 = 0x1D;
+const SET_STANDBY: u8 = 0x80;
+const SET_RX: u8 = 0x82;
+const SET_RF_FREQUENCY: u8 = 0x86;
+const CALIBRATE: u8 = 0x89;
+const SET_PACKET_TYPE: u8 = 0x8A;
+const SET_TX_PARAMS: u8 = 0x8E;
+const SET_PA_CONFIG: u8 = 0x95;
+const SET_TCXO_MODE: u8 = 0x97;
+const CALIBRATE_IMAGE: u8 = 0x98;
+const SET_TX_CONTINUOUS_WAVE: u8 = 0xD1;
+
+const PACKET_TYPE_LORA: u8 = 0x01;
+/// Calibrate every block: RC oscillators, PLL, ADC and image rejection.
+const CALIBRATE_ALL: u8 = 0x7F;
+/// TCXO start-up budget in 15.625 us steps, 5 ms.
+const TCXO_TIMEOUT: u32 = 320;
+/// PA ramp of 200 us.
+const RAMP_200US: u8 = 0x04;
+/// SetRx timeout value that keeps the receiver on until the next command.
+const RX_CONTINUOUS: [u8; 3] = [0xFF, 0xFF, 0xFF];
+/// Spacing of RSSI samples, about the radio's own RSSI averaging window.
+const RSSI_INTERVAL_US: u32 = 1_000;
+/// Upper bound on RF emission per call, so a wrong parameter can't leave the carrier on.
+const MAX_RF_MS: u32 = 5_000;
+
+/// `options` bit selecting the high-power PA (up to +22 dBm) instead of the low-power one.
+const OPTION_HP_PA: u32 = 1 << 0;
+/// `options` bit powering the TCXO from VDDTCXO, at the voltage code in bits 10:8.
+const OPTION_TCXO: u32 = 1 << 11;
+
+/// Chip mode field (bits 6:4) of the status byte in standby with the RC oscillator.
+const MODE_STANDBY_RC: u8 = 2;
+
+/// LoRa sync word registers and their reset value, which only a live radio returns.
+const LSYNCR: u16 = 0x0740;
+const LSYNCR_RESET: [u8; 2] = [0x14, 0x24];
+
+/// Wake from sleep takes a few hundred microseconds, a cold start with calibration ~3.5 ms.
+const BUSY_TIMEOUT_US: u32 = 10_000;
+const BYTE_TIMEOUT_US: u32 = 100;
+
+pub const PRESENCE: Test = Test {
+    id: 0x0401,
+    flags: 0,
+    group: group::RADIO,
+    expected_ms: 20,
+    run: presence,
+};
+
+pub const CONTINUOUS_WAVE: Test = Test {
+    id: 0x0402,
+    flags: flags::REQUIRES_FIXTURE | flags::REQUIRES_PARAMS,
+    group: group::RADIO,
+    expected_ms: MAX_RF_MS,
+    run: continuous_wave,
+};
+
+pub const RSSI: Test = Test {
+    id: 0x0403,
+    flags: flags::REQUIRES_PARAMS,
+    group: group::RADIO,
+    expected_ms: MAX_RF_MS,
+    run: rssi,
+};
+
+pub const RF_SWITCH: Test = Test {
+    id: 0x0404,
+    flags: flags::REQUIRES_PARAMS,
+    group: group::RADIO,
+    expected_ms: 200,
+    run: rf_switch,
+};
+
+/// RSSI sampling time per switch state in the RF switch cross-check.
+const SWITCH_RSSI_MS: u32 = 20;
+/// Least RSSI spread across switch states that shows the switch actually changes the RX path.
+const SWITCH_MIN_SPREAD_DB: i32 = 6;
+/// Switch control lines the RF switch test drives, enough for the usual 1- to 3-line switches.
+const MAX_SWITCH_PINS: usize = 3;
+
+/// Parameters shared by the RF tests: `[frequency_hz, power_dbm, duration_ms, options]`.
+///
+/// The frequency is required; power defaults to 0 dBm and the duration to 1 s, capped at 5 s.
+/// `options` takes the `OPTION_*` bits. The board's RF switch must already route the path under
+/// test to the antenna port.
+struct RfParams {
+    frequency_hz: u32,
+    power_dbm: i32,
+    duration_ms: u32,
+    options: u32,
+}
+
+impl RfParams {
+    /// Reads the four words starting at word `first`.
+    fn parse(params: &[u8], first: usize) -> Result<Self, ErrorCode> {
+        let frequency_hz = word(params, first).ok_or(codes::INVALID_ARGUMENT)?;
+        if !(150_000_000..=960_000_000).contains(&frequency_hz) {
+            return Err(codes::INVALID_ARGUMENT);
+        }
+        Ok(Self {
+            frequency_hz,
+            power_dbm: word(params, first + 1).unwrap_or(0) as i32,
+            duration_ms: word(params, first + 2).unwrap_or(1_000).min(MAX_RF_MS),
+            options: word(params, first + 3).unwrap_or(0),
+        })
+    }
+}
+
+/// CalibrateImage frequency pair for the band containing `hz` (SX126x datasheet, table 9-2).
+fn image_band(hz: u32) -> [u8; 2] {
+    match hz {
+        0..=440_000_000 => [0x6B, 0x6F],
+        440_000_001..=510_000_000 => [0x75, 0x81],
+        510_000_001..=787_000_000 => [0xC1, 0xC5],
+        787_000_001..=870_000_000 => [0xD7, 0xDB],
+        _ => [0xE1, 0xE9],
+    }
+}
+
+/// The SPI link to the radio, clocked and configured while it exists.
+pub struct Radio(());
+
+impl Radio {
+    /// Runs `f` with the radio freshly reset and awake, restoring the SPI block and its clock after.
+    ///
+    /// The reset gives every test the same starting point. The radio is left in standby; its
+    /// previous configuration is the application's to redo, as after any power cycle.
+    pub fn with<R>(f: impl FnOnce(&mut Radio) -> Result<R, ErrorCode>) -> Result<R, ErrorCode> {
+        rcc::with_clock(rcc::APB3ENR, APB3ENR_SUBGHZSPIEN, || {
+            let _spi = Snapshot::peripheral([SPI_CR2, SPI_CR1]);
+            SPI_CR1.write(0);
+            SPI_CR2.write(CR2_DS_8BIT | CR2_FRXTH);
+            SPI_CR1.write(CR1_MSTR | CR1_BR_DIV8 | CR1_SSM | CR1_SSI | CR1_SPE);
+
+            let mut radio = Radio(());
+            let result = radio.wake().and_then(|()| f(&mut radio));
+            let _ = radio.write(SET_STANDBY, &[0]);
+            result
+        })
+    }
+
+    fn wake(&mut self) -> Result<(), ErrorCode> {
+        rcc::CSR.set_bits(CSR_RFRST);
+        if !timeout::wait_us(BUSY_TIMEOUT_US, || rcc::CSR.read() & CSR_RFRSTF != 0) {
+            return Err(codes::RADIO_TIMEOUT);
+        }
+        rcc::CSR.clear_bits(CSR_RFRST);
+        if !timeout::wait_us(BUSY_TIMEOUT_US, || rcc::CSR.read() & CSR_RFRSTF == 0) {
+            return Err(codes::RADIO_TIMEOUT);
+        }
+        // A falling edge on NSS wakes the radio from sleep; BUSY drops once it is ready.
+        PWR_SUBGHZSPICR.clear_bits(SUBGHZSPICR_NSS);
+        cortex_m::asm::delay(100);
+        PWR_SUBGHZSPICR.set_bits(SUBGHZSPICR_NSS);
+        self.wait_ready()
+    }
+
+    fn wait_ready(&mut self) -> Result<(), ErrorCode> {
+        if !timeout::wait_us(BUSY_TIMEOUT_US, || PWR_SR2.read() & SR2_RFBUSYS == 0) {
+            return Err(codes::RADIO_TIMEOUT);
+        }
+        Ok(())
+    }
+
+    fn transfer(&mut self, byte: u8) -> Result<u8, ErrorCode> {
+        let dr = SPI_DR as *mut u8;
+        if !timeout::wait_us(BYTE_TIMEOUT_US, || SPI_SR.read() & SR_TXE != 0) {
+            return Err(codes::RADIO_TIMEOUT);
+        }
+        unsafe { dr.write_volatile(byte) };
+        if !timeout::wait_us(BYTE_TIMEOUT_US, || SPI_SR.read() & SR_RXNE != 0) {
+            return Err(codes::RADIO_TIMEOUT);
+        }
+        Ok(unsafe { dr.read_volatile() })
+    }
+
+    /// One NSS-framed transaction: sends `tx`, then clocks out `rx.len()` bytes into `rx`.
+    fn transaction(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<(), ErrorCode> {
+        self.wait_ready()?;
+        PWR_SUBGHZSPICR.clear_bits(SUBGHZSPICR_NSS);
+        let result = tx
+            .iter()
+            .try_for_each(|&byte| self.transfer(byte).map(|_| ()))
+            .and_then(|()| {
+                rx.iter_mut()
+                    .try_for_each(|slot| self.transfer(0).map(|byte| *slot = byte))
+            });
+        PWR_SUBGHZSPICR.set_bits(SUBGHZSPICR_NSS);
+        result
+    }
+
+    /// Sends a write command with `args`.
+    pub fn write(&mut self, opcode: u8, args: &[u8]) -> Result<(), ErrorCode> {
+        let mut frame = [0u8; 9];
+        let frame = frame
+            .get_mut(..args.len() + 1)
+            .ok_or(codes::INVALID_ARGUMENT)?;
+        frame[0] = opcode;
+        frame[1..].copy_from_slice(args);
+        self.transaction(frame, &mut [])
+    }
+
+    /// Sends a read command and returns its status byte, filling `data` with what follows it.
+    pub fn read(&mut self, opcode: u8, data: &mut [u8]) -> Result<u8, ErrorCode> {
+        let mut rx = [0u8; 9];
+        let rx = rx
+            .get_mut(..data.len() + 1)
+            .ok_or(codes::INVALID_ARGUMENT)?;
+        self.transaction(&[opcode], rx)?;
+        data.copy_from_slice(&rx[1..]);
+        Ok(rx[0])
+    }
+
+    pub fn read_registers(&mut self, addr: u16, data: &mut [u8]) -> Result<(), ErrorCode> {
+        let [hi, lo] = addr.to_be_bytes();
+        let mut rx = [0u8; 9];
+        let rx = rx
+            .get_mut(..data.len() + 1)
+            .ok_or(codes::INVALID_ARGUMENT)?;
+        // The byte after the address is the status, the register contents follow.
+        self.transaction(&[READ_REGISTER, hi, lo], rx)?;
+        data.copy_from_slice(&rx[1..]);
+        Ok(())
+    }
+
+    pub fn status(&mut self) -> Result<u8, ErrorCode> {
+        self.read(GET_STATUS, &mut [])
+    }
+
+    /// Brings the oscillator up, calibrates and tunes to the requested frequency.
+    fn tune(&mut self, rf: &RfParams) -> Result<(), ErrorCode> {
+        if rf.options & OPTION_TCXO != 0 {
+            let [_, t2, t1, t0] = TCXO_TIMEOUT.to_be_bytes();
+            let voltage = ((rf.options >> 8) & 0x7) as u8;
+            self.write(SET_TCXO_MODE, &[voltage, t2, t1, t0])?;
+        }
+        self.write(CALIBRATE, &[CALIBRATE_ALL])?;
+        self.write(SET_PACKET_TYPE, &[PACKET_TYPE_LORA])?;
+        self.write(CALIBRATE_IMAGE, &image_band(rf.frequency_hz))?;
+        // The synthesizer step is 32 MHz / 2^25.
+        let steps = ((rf.frequency_hz as u64) << 25) / 32_000_000;
+        self.write(SET_RF_FREQUENCY, &(steps as u32).to_be_bytes())
+    }
+
+    /// Selects and configures a PA for `power_dbm`, using the datasheet's optimal settings.
+    fn set_power(&mut self, rf: &RfParams) -> Result<(), ErrorCode> {
+        if rf.options & OPTION_HP_PA != 0 {
+            if !(-9..=22).contains(&rf.power_dbm) {
+                return Err(codes::INVALID_ARGUMENT);
+            }
+            self.write(SET_PA_CONFIG, &[0x04, 0x07, 0x00, 0x01])?;
+        } else {
+            if !(-17..=14).contains(&rf.power_dbm) {
+                return Err(codes::INVALID_ARGUMENT);
+            }
+            self.write(SET_PA_CONFIG, &[0x04, 0x00, 0x01, 0x01])?;
+        }
+        self.write(SET_TX_PARAMS, &[rf.power_dbm as i8 as u8, RAMP_200US])
+    }
+}
+
+#[cfg(feature = "self-test-burn-in")]
+impl Radio {
+    /// Tunes and configures the PA from the [`RfParams`] at word `first`, leaving the carrier to
+    /// [`Radio::carrier`], and returns the duration they ask for as the length of one burst.
+    pub fn prepare_bursts(&mut self, params: &[u8], first: usize) -> Result<u32, ErrorCode> {
+        let rf = RfParams::parse(params, first)?;
+        self.tune(&rf)?;
+        self.set_power(&rf)?;
+        Ok(rf.duration_ms)
+    }
+
+    /// Keys the unmodulated carrier on, or drops back to standby.
+    pub fn carrier(&mut self, on: bool) -> Result<(), ErrorCode> {
+        if on {
+            self.write(SET_TX_CONTINUOUS_WAVE, &[])
+        } else {
+            self.write(SET_STANDBY, &[0])
+        }
+    }
+}
+
+/// Instantaneous RSSI statistics over a sampling window, in dBm.
+struct RssiStats {
+    min: i32,
+    avg: i32,
+    max: i32,
+}
+
+impl Radio {
+    /// Samples the instantaneous RSSI every millisecond for `ms`, with the receiver already on.
+    fn sample_rssi(&mut self, ms: u32) -> Result<RssiStats, ErrorCode> {
+        let (mut min, mut max, mut sum, mut count) = (i32::MAX, i32::MIN, 0i32, 0i32);
+        let mut window = Deadline::start_us(ms.saturating_mul(1_000));
+        loop {
+            let mut raw = [0u8];
+            self.read(GET_RSSI_INST, &mut raw)?;
+            let dbm = -(raw[0] as i32) / 2;
+            (min, max) = (min.min(dbm), max.max(dbm));
+            sum += dbm;
+            count += 1;
+
+            let mut gap = Deadline::start_us(RSSI_INTERVAL_US);
+            while !gap.expired() {
+                check_deadline()?;
+            }
+            if window.expired() {
+                break;
+            }
+        }
+        Ok(RssiStats {
+            min,
+            avg: sum / count,
+            max,
+        })
+    }
+}
+
+/// Waits `ms` milliseconds while polling the test deadline.
+fn hold(ms: u32) -> Result<(), ErrorCode> {
+    let mut done = Deadline::start_us(ms.saturating_mul(1_000));
+    while !done.expired() {
+        check_deadline()?;
+    }
+    Ok(())
+}
+
+/// Resets and wakes the radio and checks it answers: a status byte showing standby, and the LoRa
+/// sync word registers at their reset value. Values: status byte, then the two sync word bytes.
+fn presence(_params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    Radio::with(|radio| {
+        radio.write(SET_STANDBY, &[0])?;
+        let status = radio.status()?;
+        let mut sync = [0u8; 2];
+        radio.read_registers(LSYNCR, &mut sync)?;
+
+        result.value(status as u32);
+        result.value(sync[0] as u32);
+        result.value(sync[1] as u32);
+        result.message(format_args!(
+            "status {:#04x}, sync word {:02x}{:02x}",
+            status, sync[0], sync[1]
+        ));
+        if (status >> 4) & 0x7 != MODE_STANDBY_RC || sync != LSYNCR_RESET {
+            return Err(codes::RADIO_FAULT);
+        }
+        Ok(())
+    })
+}
+
+/// Emits an unmodulated carrier for the RF station to measure output power and frequency error,
+/// then drops back to standby. Takes [`RfParams`]; values: frequency, power, duration.
+fn continuous_wave(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let rf = RfParams::parse(params, 0)?;
+    result.value(rf.frequency_hz);
+    result.value(rf.power_dbm as u32);
+    result.value(rf.duration_ms);
+
+    Radio::with(|radio| {
+        radio.tune(&rf)?;
+        radio.set_power(&rf)?;
+        radio.write(SET_TX_CONTINUOUS_WAVE, &[])?;
+        hold(rf.duration_ms)?;
+        radio.write(SET_STANDBY, &[0])?;
+        result.message(format_args!(
+            "CW {} Hz at {} dBm for {} ms",
+            rf.frequency_hz, rf.power_dbm, rf.duration_ms
+        ));
+        Ok(())
+    })
+}
+
+/// Receives on a frequency and reports the instantaneous RSSI spread, for conducted sensitivity
+/// and antenna-path checks. Takes [`RfParams`] (power unused) plus an optional fifth word: the
+/// lowest acceptable average in dBm. Values: frequency, then min, average and max RSSI.
+fn rssi(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let rf = RfParams::parse(params, 0)?;
+    let floor = word(params, 4).map(|dbm| dbm as i32);
+    result.value(rf.frequency_hz);
+
+    Radio::with(|radio| {
+        radio.tune(&rf)?;
+        radio.write(SET_RX, &RX_CONTINUOUS)?;
+        let stats = radio.sample_rssi(rf.duration_ms)?;
+        radio.write(SET_STANDBY, &[0])?;
+
+        result.value(stats.min as u32);
+        result.value(stats.avg as u32);
+        result.value(stats.max as u32);
+        result.message(format_args!(
+            "RSSI min {} avg {} max {} dBm",
+            stats.min, stats.avg, stats.max
+        ));
+        match floor {
+            Some(floor) if stats.avg < floor => Err(codes::RADIO_FAULT),
+            _ => Ok(()),
+        }
+    })
+}
+
+/// Drives the board's RF switch control lines (e.g. FE_CTRL1..3) through every combination and
+/// checks each pin reads back what it drives, catching lines shorted to a rail or to each other.
+///
+/// Parameters: word 0 holds up to three [`Pin`] IDs, one per byte from the lowest, with `0xFF`
+/// for unused bytes. If [`RfParams`] follow from word 1, every state also gets an RSSI reading, and
+/// the test fails unless they spread by at least 6 dB, which catches unsoldered control lines the
+/// read-back can't see. Values: the average RSSI per state, when measured. Pins are restored after.
+fn rf_switch(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let ids = word(params, 0)
+        .ok_or(codes::INVALID_ARGUMENT)?
+        .to_le_bytes();
+    let mut pins = [None; MAX_SWITCH_PINS];
+    let mut count = 0;
+    for &id in ids.iter().filter(|&&id| id != 0xFF) {
+        let slot = pins.get_mut(count).ok_or(codes::INVALID_ARGUMENT)?;
+        *slot = Some(Pin::from_id(id).ok_or(codes::INVALID_ARGUMENT)?);
+        count += 1;
+    }
+    let pins = &pins[..count];
+    let rf = match word(params, 1) {
+        Some(_) => Some(RfParams::parse(params, 1)?),
+        None => None,
+    };
+
+    let _saved: [Option<Saved>; MAX_SWITCH_PINS] =
+        core::array::from_fn(|i| pins.get(i).copied().flatten().map(|pin| pin.save()));
+    drive_states(pins, rf.as_ref(), result)
+}
+
+fn drive_states(
+    pins: &[Option<Pin>],
+    rf: Option<&RfParams>,
+    result: &mut SelfTestResult,
+) -> Result<(), ErrorCode> {
+    let pins = || pins.iter().flatten().copied().enumerate();
+    let (mut lowest, mut highest) = (i32::MAX, i32::MIN);
+    for state in 0..1u32 << pins().count() {
+        for (i, pin) in pins() {
+            pin.output(state & (1 << i) != 0);
+        }
+        cortex_m::asm::delay(100);
+        for (i, pin) in pins() {
+            if pin.is_high() != (state & (1 << i) != 0) {
+                result.value(pin.id() as u32);
+                result.value(state);
+                result.message(format_args!(
+                    "pin {:#04x} doesn't follow in state {:#b}",
+                    pin.id(),
+                    state
+                ));
+                return Err(codes::PIN_FAULT);
+            }
+        }
+
+        if let Some(rf) = rf {
+            let avg = Radio::with(|radio| {
+                radio.tune(rf)?;
+                radio.write(SET_RX, &RX_CONTINUOUS)?;
+                Ok(radio.sample_rssi(SWITCH_RSSI_MS)?.avg)
+            })?;
+            result.value(avg as u32);
+            (lowest, highest) = (lowest.min(avg), highest.max(avg));
+        }
+        check_deadline()?;
+    }
+
+    if rf.is_some() && highest - lowest < SWITCH_MIN_SPREAD_DB {
+        result.message(format_args!(
+            "RSSI spread {} dB, switch has no effect",
+            highest - lowest
+        ));
+        return Err(codes::RADIO_FAULT);
+    }
+    result.message(format_args!("{} switch lines OK", pins().count()));
+    Ok(())
+}
@@ -0,0 +1,269 @@
+//! SRAM tests over a window of the RAM declared in `algorithm!`, enabled by `self-test-memory`.
+//!
+//! The host loads the algorithm from the bottom of that RAM with its page buffer and stack on
+//! top, so by default the window starts a safety margin above the stack pointer and runs to the
+//! end of RAM. Parameters: `[start, end]` as little-endian words override it, and are refused if
+//! they cover the live stack.
+
+use flash_algorithm::ErrorCode;
+
+use super::params::word;
+use super::table::group;
+use super::{check_deadline, SelfTestResult, Test};
+use crate::error::codes;
+use crate::regs::Reg;
+
+const RAM_START: u32 = 0x2000_0000;
+const RAM_END: u32 = 0x2001_0000;
+/// Stack in use above the stack pointer at test entry: this test's callers and the host's frame.
+const STACK_ABOVE_SP: u32 = 0x400;
+/// Stack the test itself may still push below the stack pointer at entry.
+const STACK_BELOW_SP: u32 = 0x100;
+/// Words tested between two deadline polls.
+const POLL_INTERVAL: u32 = 256;
+
+const SRAM2_START: u32 = 0x2000_8000;
+const FLASH_OPTR: Reg = Reg::at(0x5800_4000, 0x20);
+/// Option bit that *disables* SRAM2 parity when set, the factory default.
+const OPTR_SRAM2_PE: u32 = 1 << 24;
+const SYSCFG_CFGR2: Reg = Reg::at(0x4001_0000, 0x1C);
+/// SRAM2 parity error flag, cleared by writing 1.
+const CFGR2_SPF: u32 = 1 << 8;
+
+pub const MARCH_C: Test = Test {
+    id: 0x0301,
+    flags: 0,
+    group: group::MEMORY,
+    expected_ms: 150,
+    run: march_c,
+};
+
+pub const PATTERNS: Test = Test {
+    id: 0x0302,
+    flags: 0,
+    group: group::MEMORY,
+    expected_ms: 60,
+    run: patterns,
+};
+
+pub const SRAM2_PARITY: Test = Test {
+    id: 0x0303,
+    flags: 0,
+    group: group::MEMORY,
+    expected_ms: 1,
+    run: sram2_parity,
+};
+
+/// Word-aligned range of RAM under test; `end` is exclusive.
+#[derive(Clone, Copy)]
+pub struct Window {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Window {
+    fn words(self) -> u32 {
+        (self.end - self.start) / 4
+    }
+
+    fn addr(self, index: u32) -> *mut u32 {
+        (self.start + index * 4) as *mut u32
+    }
+}
+
+/// Picks the window from `params` or the default, recording it in the first two values.
+pub fn window(params: &[u8], result: &mut SelfTestResult) -> Result<Window, ErrorCode> {
+    let sp = cortex_m::register::msp::read();
+    let stack_low = sp - STACK_BELOW_SP;
+    let stack_high = sp.saturating_add(STACK_ABOVE_SP).min(RAM_END);
+
+    let window = match (word(params, 0), word(params, 1)) {
+        (Some(start), Some(end)) => Window { start, end },
+        (None, _) => Window {
+            start: (stack_high + 0xff) & !0xff,
+            end: RAM_END,
+        },
+        (Some(_), None) => return Err(codes::INVALID_ARGUMENT),
+    };
+    let inside_ram =
+        RAM_START <= window.start && window.start < window.end && window.end <= RAM_END;
+    let aligned = window.start.is_multiple_of(4) && window.end.is_multiple_of(4);
+    let clear_of_stack = window.end <= stack_low || window.start >= stack_high;
+    if !(inside_ram && aligned && clear_of_stack) {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+
+    result.value(window.start);
+    result.value(window.end);
+    Ok(window)
+}
+
+/// Records the failing word after the window bounds and returns [`codes::RAM_FAULT`].
+pub fn mismatch(result: &mut SelfTestResult, addr: u32, expected: u32, read: u32) -> ErrorCode {
+    result.value(addr);
+    result.value(expected);
+    result.value(read);
+    result.message(format_args!(
+        "{:#010x}: wrote {:#010x}, read {:#010x}",
+        addr, expected, read
+    ));
+    codes::RAM_FAULT
+}
+
+#[derive(Clone, Copy)]
+enum Order {
+    Up,
+    Down,
+}
+
+/// One march element: at each word in `order`, reads back `expect` if given, then writes `write`.
+fn element(
+    window: Window,
+    order: Order,
+    expect: Option<u32>,
+    write: Option<u32>,
+    result: &mut SelfTestResult,
+) -> Result<(), ErrorCode> {
+    let words = window.words();
+    for n in 0..words {
+        let index = match order {
+            Order::Up => n,
+            Order::Down => words - 1 - n,
+        };
+        let ptr = window.addr(index);
+        if let Some(expected) = expect {
+            let read = unsafe { ptr.read_volatile() };
+            if read != expected {
+                return Err(mismatch(result, ptr as u32, expected, read));
+            }
+        }
+        if let Some(value) = write {
+            unsafe { ptr.write_volatile(value) };
+        }
+        if n % POLL_INTERVAL == 0 {
+            check_deadline()?;
+        }
+    }
+    Ok(())
+}
+
+/// March C- with all-zero/all-one word backgrounds: catches stuck-at, transition and
+/// address-decoder faults as well as coupling between cells.
+fn march_c(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    const ZERO: u32 = 0;
+    const ONES: u32 = u32::MAX;
+
+    let window = window(params, result)?;
+    element(window, Order::Up, None, Some(ZERO), result)?;
+    element(window, Order::Up, Some(ZERO), Some(ONES), result)?;
+    element(window, Order::Up, Some(ONES), Some(ZERO), result)?;
+    element(window, Order::Down, Some(ZERO), Some(ONES), result)?;
+    element(window, Order::Down, Some(ONES), Some(ZERO), result)?;
+    element(window, Order::Down, Some(ZERO), None, result)?;
+    result.message(format_args!("{} words passed", window.words()));
+    Ok(())
+}
+
+/// Writes `pattern(addr)` to every word of the window, then reads all of them back.
+fn fill_and_verify(
+    window: Window,
+    pattern: impl Fn(u32) -> u32,
+    result: &mut SelfTestResult,
+) -> Result<(), ErrorCode> {
+    for n in 0..window.words() {
+        let ptr = window.addr(n);
+        unsafe { ptr.write_volatile(pattern(ptr as u32)) };
+        if n % POLL_INTERVAL == 0 {
+            check_deadline()?;
+        }
+    }
+    for n in 0..window.words() {
+        let ptr = window.addr(n);
+        let (expected, read) = (pattern(ptr as u32), unsafe { ptr.read_volatile() });
+        if read != expected {
+            return Err(mismatch(result, ptr as u32, expected, read));
+        }
+        if n % POLL_INTERVAL == 0 {
+            check_deadline()?;
+        }
+    }
+    Ok(())
+}
+
+/// Quick screen for production lines: a checkerboard for shorts between neighbouring bits and
+/// words, then each word's own address for stuck or bridged address lines, four accesses per word
+/// against March C-'s ten.
+fn patterns(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let window = window(params, result)?;
+    let checkerboard = |addr: u32| {
+        if addr & 4 == 0 {
+            0x5555_5555
+        } else {
+            0xAAAA_AAAA
+        }
+    };
+    fill_and_verify(window, checkerboard, result)?;
+    fill_and_verify(window, |addr| addr, result)?;
+    result.message(format_args!("{} words passed", window.words()));
+    Ok(())
+}
+
+/// Checks SRAM2 parity through the SYSCFG error flag on a scratch word at the top of the window.
+///
+/// Parity is computed per byte on every write and the WLE5 has no way to inject a bad parity bit,
+/// so the test proves the clean path instead: the flag clears, and byte, half-word and word
+/// accesses of both parities leave it clear. Parameter word 2 set to 1 also fails the test when
+/// the `SRAM2_PE` option bit leaves parity disabled, as it is on virgin parts.
+fn sram2_parity(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let enabled = FLASH_OPTR.read() & OPTR_SRAM2_PE == 0;
+    result.value(enabled as u32);
+    if !enabled {
+        result.message(format_args!("parity disabled by SRAM2_PE"));
+        return match word(params, 2) {
+            Some(1) => Err(codes::PARITY_DISABLED),
+            _ => Ok(()),
+        };
+    }
+
+    let window = window(params, result)?;
+    if window.end <= SRAM2_START {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+    let scratch = window.end - 4;
+    let ptr = scratch as *mut u32;
+    let saved = unsafe { ptr.read_volatile() };
+
+    SYSCFG_CFGR2.set_bits(CFGR2_SPF);
+    let stale = SYSCFG_CFGR2.read() & CFGR2_SPF != 0;
+    let mut outcome = if stale {
+        result.message(format_args!("SPF doesn't clear"));
+        Err(codes::PARITY_ERROR)
+    } else {
+        Ok(())
+    };
+    for pattern in [0x0000_0000, 0x0101_0101, 0xFEFE_FEFE, 0xFFFF_FFFF] {
+        if outcome.is_err() {
+            break;
+        }
+        unsafe {
+            ptr.write_volatile(pattern);
+            (ptr as *mut u16).write_volatile(pattern as u16);
+            (ptr as *mut u8).add(3).write_volatile(pattern as u8);
+            let _ = ptr.read_volatile();
+            let _ = (ptr as *const u8).add(1).read_volatile();
+        }
+        if SYSCFG_CFGR2.read() & CFGR2_SPF != 0 {
+            result.value(scratch);
+            result.value(pattern);
+            result.message(format_args!("parity error at {:#010x}", scratch));
+            SYSCFG_CFGR2.set_bits(CFGR2_SPF);
+            outcome = Err(codes::PARITY_ERROR);
+        }
+    }
+
+    unsafe { ptr.write_volatile(saved) };
+    if outcome.is_ok() {
+        result.message(format_args!("no parity error at {:#010x}", scratch));
+    }
+    outcome
+}
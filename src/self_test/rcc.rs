@@ -0,0 +1,114 @@
+//! RCC clock-enable registers the self tests switch peripherals on with (RM0461, section 6.4).
+#![allow(dead_code)] // A register map; each `self-test-*` family uses its own subset.
+
+use crate::regs::Reg;
+use crate::timeout;
+
+const RCC: usize = 0x5800_0000;
+pub const CR: Reg = Reg::at(RCC, 0x00);
+pub const CFGR: Reg = Reg::at(RCC, 0x08);
+pub const AHB1ENR: Reg = Reg::at(RCC, 0x48);
+pub const AHB2ENR: Reg = Reg::at(RCC, 0x4C);
+pub const AHB3ENR: Reg = Reg::at(RCC, 0x50);
+pub const APB1ENR1: Reg = Reg::at(RCC, 0x58);
+pub const APB1ENR2: Reg = Reg::at(RCC, 0x5C);
+pub const APB2ENR: Reg = Reg::at(RCC, 0x60);
+pub const APB3ENR: Reg = Reg::at(RCC, 0x64);
+/// Clock gating in Sleep and Stop modes; every peripheral keeps its clock out of reset.
+pub const APB1SMENR1: Reg = Reg::at(RCC, 0x78);
+/// Kernel clock selection of the peripherals that have one.
+pub const CCIPR: Reg = Reg::at(RCC, 0x88);
+pub const BDCR: Reg = Reg::at(RCC, 0x90);
+pub const CSR: Reg = Reg::at(RCC, 0x94);
+
+pub const AHB1ENR_CRCEN: u32 = 1 << 12;
+
+/// PWR_CR1, whose DBP bit gates writes to BDCR and the rest of the backup domain.
+const PWR_CR1: Reg = Reg::at(0x5800_0400, 0x00);
+const PWR_CR1_DBP: u32 = 1 << 8;
+
+const CFGR_PPRE1_SHIFT: u32 = 8;
+const CFGR_PPRE2_SHIFT: u32 = 11;
+
+/// PCLK1, derived from the core clock the host passed to `Init` and PPRE1.
+pub fn pclk1_hz() -> u32 {
+    pclk_hz(CFGR_PPRE1_SHIFT)
+}
+
+/// PCLK2, derived from the core clock the host passed to `Init` and PPRE2.
+pub fn pclk2_hz() -> u32 {
+    pclk_hz(CFGR_PPRE2_SHIFT)
+}
+
+/// The core clock divided by the APB prescaler at `shift` in CFGR: 0b0xx is / 1, 0b100 to 0b111
+/// are / 2 to / 16.
+fn pclk_hz(shift: u32) -> u32 {
+    let ppre = (CFGR.read() >> shift) & 0b111;
+    let div_log2 = if ppre & 0b100 != 0 {
+        (ppre & 0b11) + 1
+    } else {
+        0
+    };
+    timeout::clock_hz() >> div_log2
+}
+
+/// A clock switched on by [`enable`], switched off again when dropped unless it already ran.
+pub struct ClockEnable {
+    reg: Reg,
+    /// The bits that were clear before.
+    mask: u32,
+}
+
+/// Sets `mask` in the enable register `reg` until the returned guard is dropped, which puts back
+/// whichever of those bits were clear before, so a test never leaves a clock running that it
+/// switched on, early returns included.
+pub fn enable(reg: Reg, mask: u32) -> ClockEnable {
+    let was_enabled = reg.read() & mask;
+    reg.set_bits(mask);
+    // The clock only starts two cycles after the enable write; the read-back covers the gap.
+    let _ = reg.read();
+    ClockEnable {
+        reg,
+        mask: mask & !was_enabled,
+    }
+}
+
+impl Drop for ClockEnable {
+    fn drop(&mut self) {
+        self.reg.clear_bits(self.mask);
+    }
+}
+
+/// Runs `f` with `mask` set in the enable register `reg`, as [`enable`] does for a scope.
+pub fn with_clock<R>(reg: Reg, mask: u32, f: impl FnOnce() -> R) -> R {
+    let _clock = enable(reg, mask);
+    f()
+}
+
+/// Backup domain write access granted by [`backup_access`], revoked again when dropped unless
+/// it was granted before.
+pub struct BackupAccess {
+    was_enabled: bool,
+}
+
+/// Enables writes to the backup domain (BDCR, RTC and TAMP) until the returned guard is dropped.
+pub fn backup_access() -> BackupAccess {
+    let was_enabled = PWR_CR1.read() & PWR_CR1_DBP != 0;
+    PWR_CR1.set_bits(PWR_CR1_DBP);
+    let _ = PWR_CR1.read();
+    BackupAccess { was_enabled }
+}
+
+impl Drop for BackupAccess {
+    fn drop(&mut self) {
+        if !self.was_enabled {
+            PWR_CR1.clear_bits(PWR_CR1_DBP);
+        }
+    }
+}
+
+/// Runs `f` with backup domain write access, as [`backup_access`] grants it for a scope.
+pub fn with_backup_access<R>(f: impl FnOnce() -> R) -> R {
+    let _access = backup_access();
+    f()
+}
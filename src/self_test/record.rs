@@ -0,0 +1,94 @@
+//! The last `SelfTestAll` run kept in a main flash page, enabled by `self-test-record`.
+//!
+//! Lets devices carry their factory test record for field failure analysis, readable with any
+//! debugger as plain words. The page holds, at fixed offsets and all little-endian: the
+//! [`Header`] words, then the ID of each registered test, then the `SelfTestStatuses` of the run,
+//! then the `SelfTestMailbox` block of its last test. ID slots past the registry's end stay erased.
+
+use core::mem::size_of;
+
+use flash_algorithm::ErrorCode;
+
+use super::result::{SelfTestMailbox, SelfTestPassed, SelfTestStatuses, TestMask};
+use super::{SelfTestResult, MAX_TESTS, TESTS};
+use crate::error::codes;
+use crate::flash;
+use crate::mailbox::{record_error, Operation};
+
+/// "STRC" when read as bytes.
+pub const MAGIC: u32 = u32::from_le_bytes(*b"STRC");
+/// Bumped whenever the layout changes.
+pub const VERSION: u32 = 2;
+
+/// The page used when the host passes address 0: the last one of main flash.
+const DEFAULT_PAGE: u32 = flash::BASE + flash::SIZE - flash::PAGE_SIZE;
+
+#[repr(C)]
+struct Header {
+    magic: u32,
+    version: u32,
+    uid: [u32; 3],
+    /// Whatever the host passed, conventionally Unix seconds; the device has no wall clock.
+    timestamp: u32,
+    /// Number of valid ID and status slots.
+    tests: u32,
+    /// Zero; keeps the header a whole number of flash doublewords.
+    reserved: u32,
+    /// The run's `SelfTestPassed`.
+    passed: TestMask,
+}
+
+const IDS_OFFSET: u32 = size_of::<Header>() as u32;
+const STATUSES_OFFSET: u32 = IDS_OFFSET + (MAX_TESTS * 4) as u32;
+const RESULT_OFFSET: u32 = STATUSES_OFFSET + (MAX_TESTS * 4) as u32;
+const RECORD_SIZE: u32 = RESULT_OFFSET + size_of::<SelfTestResult>() as u32;
+
+const _: () = assert!(RECORD_SIZE <= flash::PAGE_SIZE, "the record fits one page");
+const _: () = assert!(IDS_OFFSET.is_multiple_of(8) && RESULT_OFFSET.is_multiple_of(8));
+
+/// Test IDs programmed per step, so the list is never built whole on the stack.
+const IDS_PER_STEP: usize = 8;
+
+/// Erases the page at `addr`, or the last page of main flash for 0, and programs the record of
+/// the last `SelfTestAll` run into it.
+pub fn save_record(addr: u32, timestamp: u32) -> Result<(), ErrorCode> {
+    let addr = if addr == 0 { DEFAULT_PAGE } else { addr };
+    if !addr.is_multiple_of(flash::PAGE_SIZE) {
+        return Err(record_error(Operation::EraseSector, addr, codes::ALIGNMENT));
+    }
+    flash::erase_page(addr).map_err(|e| record_error(Operation::EraseSector, addr, e.into()))?;
+
+    let program = |offset: u32, data: &[u8]| {
+        flash::program(addr + offset, data)
+            .map_err(|e| record_error(Operation::ProgramPage, addr + offset, e.into()))
+    };
+    let header = Header {
+        magic: MAGIC,
+        version: VERSION,
+        uid: crate::device::uid(),
+        timestamp,
+        tests: TESTS.len() as u32,
+        reserved: 0,
+        passed: SelfTestPassed.read(),
+    };
+    program(0, bytes(&header))?;
+    for (step, tests) in TESTS.chunks(IDS_PER_STEP).enumerate() {
+        let mut ids = [0u32; IDS_PER_STEP];
+        for (id, test) in ids.iter_mut().zip(tests) {
+            *id = test.id;
+        }
+        let offset = IDS_OFFSET + (step * IDS_PER_STEP * 4) as u32;
+        program(offset, &bytes(&ids)[..tests.len() * 4])?;
+    }
+
+    let mut outcome = Ok(());
+    SelfTestStatuses.update(|statuses| outcome = program(STATUSES_OFFSET, bytes(statuses)));
+    outcome?;
+    SelfTestMailbox.update(|result| outcome = program(RESULT_OFFSET, bytes(result)));
+    outcome
+}
+
+/// The in-memory bytes of a `repr(C)` block of words, which has no padding to leak.
+fn bytes<T>(value: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
@@ -0,0 +1,42 @@
+//! Detailed outcome of the last self test, for factory diagnostics beyond pass/fail.
+//!
+//! The layouts live in the protocol crate, which host runners decode them with.
+
+#[cfg(feature = "self-test-postcard")]
+use protocol::encoded::EncodedResult;
+
+use crate::mailbox::Mailbox;
+
+pub use protocol::{SelfTestResult, TestMask, MASK_WORDS, MAX_TESTS};
+
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+pub static SelfTestMailbox: Mailbox<SelfTestResult> = Mailbox::new(SelfTestResult::EMPTY);
+
+/// `SelfTestMailbox` again, as the postcard encoding of its valid fields.
+#[cfg(feature = "self-test-postcard")]
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+pub static SelfTestEncoded: Mailbox<EncodedResult> = Mailbox::new(EncodedResult::EMPTY);
+
+/// Status of each test in the last `SelfTestAll` run, indexed like its bitmap: zero on pass,
+/// `TEST_SKIPPED` for tests it didn't run and bits past the table, otherwise the code the test
+/// returned.
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+pub static SelfTestStatuses: Mailbox<[u32; MAX_TESTS]> = Mailbox::new([0; MAX_TESTS]);
+
+/// Tests the next `SelfTestAll` skips on top of its `skip_mask`, cleared once it has read them.
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+pub static SelfTestSkip: Mailbox<TestMask> = Mailbox::new([0; MASK_WORDS]);
+
+/// Tests that passed in the last `SelfTestAll` run, which only returns the first word.
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+pub static SelfTestPassed: Mailbox<TestMask> = Mailbox::new([0; MASK_WORDS]);
@@ -0,0 +1,269 @@
+//! RTC tick and wakeup-timer test, part of `self-test-clocks` (RM0461, chapter 30).
+//!
+//! The RTC clock source can only be changed by resetting the whole backup domain, which would also
+//! wipe the backup registers the application keeps there, so the test never does that: it runs
+//! the RTC from whatever RTCSEL already selects and only picks a source if none is selected yet.
+//! Everything else it touches (RTCEN, the wakeup timer, the oscillator it started) is put back.
+
+use flash_algorithm::ErrorCode;
+
+use super::clocks::{self, CR_HSERDY};
+use super::params::word;
+use super::table::group;
+use super::{check_deadline, rcc, SelfTestResult, Test};
+use crate::error::codes;
+use crate::regs::Reg;
+use crate::timeout::{self, CycleCounter};
+
+const RTC: usize = 0x4000_2800;
+const RTC_SSR: Reg = Reg::at(RTC, 0x08);
+const RTC_DR: Reg = Reg::at(RTC, 0x04);
+const RTC_ICSR: Reg = Reg::at(RTC, 0x0C);
+const RTC_PRER: Reg = Reg::at(RTC, 0x10);
+const RTC_WUTR: Reg = Reg::at(RTC, 0x14);
+const RTC_CR: Reg = Reg::at(RTC, 0x18);
+const RTC_WPR: Reg = Reg::at(RTC, 0x24);
+const RTC_SR: Reg = Reg::at(RTC, 0x50);
+const RTC_SCR: Reg = Reg::at(RTC, 0x5C);
+
+const ICSR_WUTWF: u32 = 1 << 2;
+const ICSR_RSF: u32 = 1 << 5;
+/// RTCCLK / 16.
+const CR_WUCKSEL_DIV16: u32 = 0b000;
+const CR_WUCKSEL_MASK: u32 = 0b111;
+const CR_WUTE: u32 = 1 << 10;
+const CR_WUTIE: u32 = 1 << 14;
+const SR_WUTF: u32 = 1 << 2;
+
+const BDCR_RTCSEL_SHIFT: u32 = 8;
+const BDCR_RTCSEL_MASK: u32 = 0b11 << BDCR_RTCSEL_SHIFT;
+const BDCR_RTCEN: u32 = 1 << 15;
+const APB1ENR1_RTCAPBEN: u32 = 1 << 10;
+
+/// The wakeup timer reaches the NVIC through direct EXTI line 19, which must be unmasked.
+const EXTI_C1IMR1: Reg = Reg::at(0x5800_0800, 0x80);
+const EXTI_LINE_RTC_WAKEUP: u32 = 1 << 19;
+const RTC_WKUP_IRQ: u32 = 3;
+const NVIC_ISPR0: Reg = Reg::at(0xE000_E200, 0);
+const NVIC_ICPR0: Reg = Reg::at(0xE000_E280, 0);
+
+/// RTCSEL values.
+#[derive(Clone, Copy)]
+enum Source {
+    Lse = 1,
+    Lsi = 2,
+    HseDiv32 = 3,
+}
+
+impl Source {
+    fn hz(self) -> u32 {
+        match self {
+            Source::Lse => 32_768,
+            Source::Lsi => 32_000,
+            Source::HseDiv32 => 1_000_000,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Source::Lse => "LSE",
+            Source::Lsi => "LSI",
+            Source::HseDiv32 => "HSE/32",
+        }
+    }
+}
+
+pub const RTC_TEST: Test = Test {
+    id: 0x0204,
+    flags: 0,
+    group: group::CLOCKS,
+    expected_ms: 1_300,
+    run: rtc,
+};
+
+/// Subsecond ticks are counted over this window, long enough for a 256 Hz tick to resolve 2 %.
+const WINDOW_US: u32 = 200_000;
+/// About 10 ms at RTCCLK / 16 from LSE.
+const WAKEUP_RELOAD: u32 = 20;
+
+/// Checks the RTC counts at the rate its prescalers imply and that the wakeup timer raises its
+/// interrupt.
+///
+/// With no RTCSEL yet, the RTC runs from LSE, falling back to LSI if the crystal doesn't start;
+/// that choice then stays until the next backup domain reset. Parameters: `[drive]`, the LSE
+/// drive level should the test start it (default 3). Values: RTCSEL source, subsecond ticks
+/// counted, ticks expected, wakeup latency in us. The interrupt is only ever left pending in
+/// the NVIC, never enabled, so the host's vector table is never entered.
+fn rtc(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let drive = word(params, 0).unwrap_or(3);
+    if drive > 3 {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+
+    rcc::with_backup_access(|| {
+        rcc::with_clock(rcc::APB1ENR1, APB1ENR1_RTCAPBEN, || {
+            let saved = rcc::BDCR.read();
+            let mut started = Started::default();
+            let outcome = select_source(saved, drive, &mut started).and_then(|source| {
+                rcc::BDCR.set_bits(BDCR_RTCEN);
+                let outcome = unlocked(|| check(source, result));
+                if saved & BDCR_RTCEN == 0 {
+                    rcc::BDCR.clear_bits(BDCR_RTCEN);
+                }
+                outcome
+            });
+            if started.lsi {
+                clocks::stop_lsi();
+            }
+            if started.lse {
+                clocks::stop_lse(saved);
+            }
+            outcome
+        })
+    })
+}
+
+/// Oscillators the test switched on and must switch off again.
+#[derive(Default)]
+struct Started {
+    lse: bool,
+    lsi: bool,
+}
+
+const LSE_TIMEOUT_US: u32 = 1_000_000;
+
+/// The clock the RTC will run from, selecting and starting one if needed.
+fn select_source(saved: u32, drive: u32, started: &mut Started) -> Result<Source, ErrorCode> {
+    let source = match (saved & BDCR_RTCSEL_MASK) >> BDCR_RTCSEL_SHIFT {
+        1 => {
+            started.lse = clocks::start_lse(drive, LSE_TIMEOUT_US)?.is_some();
+            Source::Lse
+        }
+        2 => {
+            started.lsi = clocks::start_lsi()?;
+            Source::Lsi
+        }
+        3 if rcc::CR.read() & CR_HSERDY != 0 => Source::HseDiv32,
+        3 => return Err(codes::CLOCK_TIMEOUT),
+        _ => match clocks::start_lse(drive, LSE_TIMEOUT_US) {
+            Ok(lse) => {
+                started.lse = lse.is_some();
+                Source::Lse
+            }
+            Err(_) => {
+                started.lsi = clocks::start_lsi()?;
+                Source::Lsi
+            }
+        },
+    };
+    rcc::BDCR.modify(|v| v | (source as u32) << BDCR_RTCSEL_SHIFT);
+    Ok(source)
+}
+
+/// Runs `f` with the RTC registers write-enabled, write-protecting them again afterwards.
+fn unlocked<R>(f: impl FnOnce() -> R) -> R {
+    RTC_WPR.write(0xCA);
+    RTC_WPR.write(0x53);
+    let result = f();
+    RTC_WPR.write(0xFF);
+    result
+}
+
+fn check(source: Source, result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    result.value(source as u32);
+    wait(|| RTC_ICSR.read() & ICSR_RSF != 0, 10_000)?;
+
+    // SSR counts down from PREDIV_S once per ck_apre = RTCCLK / (PREDIV_A + 1).
+    let prer = RTC_PRER.read();
+    let prediv_s = prer & 0x7FFF;
+    let prediv_a = (prer >> 16) & 0x7F;
+    let expected =
+        (source.hz() as u64 * WINDOW_US as u64 / 1_000_000 / (prediv_a as u64 + 1)) as u32;
+    let first = subseconds();
+    CycleCounter::enable();
+    let begin = CycleCounter::now();
+    let window = timeout::cycles_for_us(WINDOW_US);
+    while CycleCounter::now().wrapping_sub(begin) < window {
+        check_deadline()?;
+    }
+    let ticks = (first + prediv_s + 1 - subseconds()) % (prediv_s + 1);
+    result.value(ticks);
+    result.value(expected);
+    if ticks.abs_diff(expected) > expected / 20 + 2 {
+        result.message(format_args!(
+            "RTC on {}: {} ticks, expected {}",
+            source.name(),
+            ticks,
+            expected
+        ));
+        return Err(codes::CLOCK_INACCURATE);
+    }
+
+    let wakeup_us = wakeup()?;
+    result.value(wakeup_us);
+    result.message(format_args!(
+        "RTC on {}: {} ticks, wakeup in {} us",
+        source.name(),
+        ticks,
+        wakeup_us
+    ));
+    Ok(())
+}
+
+/// The current SSR value; reading DR afterwards releases the calendar shadow registers that
+/// reading SSR locked.
+fn subseconds() -> u32 {
+    let ssr = RTC_SSR.read() & 0xFFFF;
+    let _ = RTC_DR.read();
+    ssr
+}
+
+/// Fires the wakeup timer once and returns how long its interrupt took to become pending.
+fn wakeup() -> Result<u32, ErrorCode> {
+    let saved_cr = RTC_CR.read();
+    let saved_wutr = RTC_WUTR.read();
+    let mask_was_set = EXTI_C1IMR1.read() & EXTI_LINE_RTC_WAKEUP != 0;
+    let irq = 1 << RTC_WKUP_IRQ;
+
+    let outcome = reload(WAKEUP_RELOAD, CR_WUCKSEL_DIV16).and_then(|()| {
+        EXTI_C1IMR1.set_bits(EXTI_LINE_RTC_WAKEUP);
+        RTC_SCR.write(SR_WUTF);
+        NVIC_ICPR0.write(irq);
+        CycleCounter::enable();
+        let begin = CycleCounter::now();
+        RTC_CR.set_bits(CR_WUTE | CR_WUTIE);
+        wait(|| RTC_SR.read() & SR_WUTF != 0, 100_000)?;
+        let us = timeout::us_for_cycles(CycleCounter::now().wrapping_sub(begin));
+        if NVIC_ISPR0.read() & irq == 0 {
+            return Err(codes::CLOCK_TIMEOUT);
+        }
+        Ok(us)
+    });
+
+    RTC_CR.clear_bits(CR_WUTE | CR_WUTIE);
+    RTC_SCR.write(SR_WUTF);
+    NVIC_ICPR0.write(irq);
+    if !mask_was_set {
+        EXTI_C1IMR1.clear_bits(EXTI_LINE_RTC_WAKEUP);
+    }
+    reload(saved_wutr, saved_cr & CR_WUCKSEL_MASK)?;
+    RTC_CR.write(saved_cr);
+    outcome
+}
+
+/// Stops the wakeup timer and loads a new reload value and clock, which WUTWF must allow first.
+fn reload(wutr: u32, wucksel: u32) -> Result<(), ErrorCode> {
+    RTC_CR.clear_bits(CR_WUTE | CR_WUTIE);
+    wait(|| RTC_ICSR.read() & ICSR_WUTWF != 0, 10_000)?;
+    RTC_WUTR.write(wutr);
+    RTC_CR.modify(|v| (v & !CR_WUCKSEL_MASK) | wucksel);
+    Ok(())
+}
+
+fn wait(done: impl FnMut() -> bool, timeout_us: u32) -> Result<(), ErrorCode> {
+    if timeout::wait_us(timeout_us, done) {
+        Ok(())
+    } else {
+        Err(codes::CLOCK_TIMEOUT)
+    }
+}
@@ -0,0 +1,51 @@
+//! Register snapshots that put a peripheral back when they go out of scope.
+//!
+//! A test takes one right after switching the peripheral's clock on, so whichever way it
+//! returns, `?` included, the registers are restored before the clock goes off again and the
+//! host's next flash operation finds the chip as `Init` left it. Pins have [`gpio::Saved`] and
+//! clocks [`rcc::enable`] for the same purpose.
+//!
+//! [`gpio::Saved`]: super::gpio::Saved
+//! [`rcc::enable`]: super::rcc::enable
+
+use crate::regs::Reg;
+
+/// Registers and the values they held when the snapshot was taken.
+pub struct Snapshot<const N: usize> {
+    regs: [Reg; N],
+    values: [u32; N],
+    /// Write 0 to the last register before restoring any of them.
+    stop_first: bool,
+}
+
+impl<const N: usize> Snapshot<N> {
+    /// Saves `regs`, written back in the order given when dropped.
+    pub fn registers(regs: [Reg; N]) -> Self {
+        Self {
+            regs,
+            values: regs.map(Reg::read),
+            stop_first: false,
+        }
+    }
+
+    /// Saves a peripheral's `regs`, listed with the one holding its enable bit last: when
+    /// dropped, that one is cleared first so nothing runs on half-restored settings, and put back
+    /// last.
+    pub fn peripheral(regs: [Reg; N]) -> Self {
+        Self {
+            stop_first: true,
+            ..Self::registers(regs)
+        }
+    }
+}
+
+impl<const N: usize> Drop for Snapshot<N> {
+    fn drop(&mut self) {
+        if let (true, Some(enable)) = (self.stop_first, self.regs.last()) {
+            enable.write(0);
+        }
+        for (reg, &value) in self.regs.iter().zip(&self.values) {
+            reg.write(value);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! SPI device probe, part of `self-test-bus`.
+//!
+//! The test masters SPI1 or SPI2 with a software-driven chip select, so any GPIO can select the
+//! device, and sends one command of the kind most SPI peripherals answer with a fixed ID.
+
+use flash_algorithm::ErrorCode;
+
+use super::gpio::{Pin, Pull};
+use super::params::word;
+use super::snapshot::Snapshot;
+use super::table::{flags, group};
+use super::{rcc, SelfTestResult, Test};
+use crate::error::codes;
+use crate::regs::Reg;
+use crate::timeout;
+
+const CR1: usize = 0x00;
+const CR2: usize = 0x04;
+const SR: usize = 0x08;
+const DR: usize = 0x0C;
+
+const CR1_MSTR: u32 = 1 << 2;
+const CR1_BR_SHIFT: u32 = 3;
+const BR_MAX: u32 = 7;
+const CR1_SPE: u32 = 1 << 6;
+const CR1_SSI: u32 = 1 << 8;
+const CR1_SSM: u32 = 1 << 9;
+const CR2_DS_8BIT: u32 = 0b0111 << 8;
+const CR2_FRXTH: u32 = 1 << 12;
+const SR_RXNE: u32 = 1 << 0;
+const SR_TXE: u32 = 1 << 1;
+const SR_BSY: u32 = 1 << 7;
+
+/// AF5 covers all of SPI1's pins and most of SPI2's.
+const AF_SPI: u8 = 5;
+
+struct Instance {
+    base: usize,
+    enable: Reg,
+    enable_mask: u32,
+    /// On PCLK2 rather than PCLK1.
+    apb2: bool,
+}
+
+/// SPI1 and SPI2; SPI3 is the radio's SUBGHZSPI.
+const INSTANCES: [Instance; 2] = [
+    Instance {
+        base: 0x4001_3000,
+        enable: rcc::APB2ENR,
+        enable_mask: 1 << 12,
+        apb2: true,
+    },
+    Instance {
+        base: 0x4000_3800,
+        enable: rcc::APB1ENR1,
+        enable_mask: 1 << 14,
+        apb2: false,
+    },
+];
+
+impl Instance {
+    fn reg(&self, offset: usize) -> Reg {
+        Reg::at(self.base, offset)
+    }
+
+    /// Exchanges one byte; DR is accessed a byte at a time so the FIFO packs no second frame.
+    fn transfer(&self, byte: u8) -> Result<u8, ErrorCode> {
+        let dr = (self.base + DR) as *mut u8;
+        let sr = self.reg(SR);
+        if !timeout::wait_us(BYTE_TIMEOUT_US, || sr.read() & SR_TXE != 0) {
+            return Err(codes::BUS_TIMEOUT);
+        }
+        unsafe { dr.write_volatile(byte) };
+        if !timeout::wait_us(BYTE_TIMEOUT_US, || sr.read() & SR_RXNE != 0) {
+            return Err(codes::BUS_TIMEOUT);
+        }
+        Ok(unsafe { dr.read_volatile() })
+    }
+}
+
+pub const SPI_PROBE: Test = Test {
+    id: 0x0543,
+    flags: flags::REQUIRES_PARAMS,
+    group: group::PERIPHERALS,
+    expected_ms: 2,
+    run: spi_probe,
+};
+
+/// JEDEC Read Identification: opcode 0x9F, no dummy bytes, three ID bytes, mode 0.
+const DEFAULT_COMMAND: u32 = 0x0003_009F;
+const MAX_DUMMY: u32 = 4;
+/// Even at PCLK / 256 a byte takes well under this.
+const BYTE_TIMEOUT_US: u32 = 1_000;
+/// Chip select setup and hold time, generous for any SPI device.
+const CS_DELAY_US: u32 = 1;
+
+/// Selects a device on SPI1 or SPI2, sends it an ID command and compares the answer with the
+/// ID the host expects.
+///
+/// Parameters: `[instance, pins, command, expected, mask, clock_khz]`; instance 0 is SPI1 and 1
+/// SPI2, and `pins` holds the SCK, MISO, MOSI and chip select [`Pin`] IDs in bytes 0 to 3, the
+/// first three on AF5. `command` holds the opcode, the number of dummy bytes (up to 4), the
+/// number of response bytes (1 to 4) and the SPI mode in bytes 0 to 3; it defaults to a JEDEC
+/// ID read. `expected` is required and has the first response byte most significant, as does
+/// `mask`, which defaults to every response bit. `clock_khz` is an upper bound (default 1000).
+/// An answer of all zero or all one bits means no device drove MISO and fails as missing; any
+/// other difference as the wrong device. Values: response, expected, mask, SCK in Hz. The
+/// instance and pins are restored after.
+fn spi_probe(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let instance = word(params, 0)
+        .and_then(|index| INSTANCES.get(index as usize))
+        .ok_or(codes::INVALID_ARGUMENT)?;
+    let ids = word(params, 1)
+        .ok_or(codes::INVALID_ARGUMENT)?
+        .to_le_bytes();
+    if (1..4).any(|i| ids[..i].contains(&ids[i])) {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+    let [Some(sck), Some(miso), Some(mosi), Some(cs)] = ids.map(Pin::from_id) else {
+        return Err(codes::INVALID_ARGUMENT);
+    };
+    let [opcode, dummy, length, mode] = word(params, 2).unwrap_or(DEFAULT_COMMAND).to_le_bytes();
+    let expected = word(params, 3).ok_or(codes::INVALID_ARGUMENT)?;
+    let clock_hz = word(params, 5).unwrap_or(1_000).saturating_mul(1_000);
+    if dummy as u32 > MAX_DUMMY || !(1..=4).contains(&length) || mode > 3 || clock_hz == 0 {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+    let all = u32::MAX >> (32 - 8 * length as u32);
+    let mask = word(params, 4).unwrap_or(all) & all;
+
+    let pclk = if instance.apb2 {
+        rcc::pclk2_hz()
+    } else {
+        rcc::pclk1_hz()
+    };
+    // SCK is PCLK / 2 ^ (BR + 1); the slowest setting is used if none is slow enough.
+    let br = (0..BR_MAX)
+        .find(|&br| pclk >> (br + 1) <= clock_hz)
+        .unwrap_or(BR_MAX);
+    let sck_hz = pclk >> (br + 1);
+
+    let response = rcc::with_clock(instance.enable, instance.enable_mask, || {
+        let saved = [sck, miso, mosi, cs].map(Pin::save);
+        let spi = Snapshot::peripheral([instance.reg(CR2), instance.reg(CR1)]);
+
+        cs.output(true);
+        instance.reg(CR1).write(0);
+        instance.reg(CR2).write(CR2_DS_8BIT | CR2_FRXTH);
+        // CPOL and CPHA are CR1 bits 1 and 0, the same as the mode number's.
+        instance
+            .reg(CR1)
+            .write(CR1_SSM | CR1_SSI | CR1_MSTR | br << CR1_BR_SHIFT | mode as u32 | CR1_SPE);
+        // Only now, with SCK idling at CPOL, hand the pins to the SPI.
+        sck.alternate(AF_SPI, false, Pull::None);
+        mosi.alternate(AF_SPI, false, Pull::None);
+        // Without a device MISO floats; the pull-up makes that read as all ones.
+        miso.alternate(AF_SPI, false, Pull::Up);
+
+        let outcome = transaction(instance, cs, opcode, dummy, length);
+
+        timeout::wait_us(BYTE_TIMEOUT_US, || instance.reg(SR).read() & SR_BSY == 0);
+        drop(spi);
+        // CS goes back first, so the device never sees SCK or MOSI change while selected.
+        for saved in saved.into_iter().rev() {
+            saved.restore();
+        }
+        outcome
+    })?;
+
+    result.value(response);
+    result.value(expected);
+    result.value(mask);
+    result.value(sck_hz);
+    result.message(format_args!(
+        "0x{:02X} answered 0x{:0width$X}, expected 0x{:0width$X}",
+        opcode,
+        response,
+        expected & mask,
+        width = length as usize * 2
+    ));
+    if response == 0 || response == all {
+        return Err(codes::DEVICE_MISSING);
+    }
+    if (response ^ expected) & mask != 0 {
+        return Err(codes::DEVICE_ID_MISMATCH);
+    }
+    Ok(())
+}
+
+/// One chip-select-framed command, returning the response with its first byte most significant.
+fn transaction(
+    instance: &Instance,
+    cs: Pin,
+    opcode: u8,
+    dummy: u8,
+    length: u8,
+) -> Result<u32, ErrorCode> {
+    cs.set(false);
+    timeout::wait_us(CS_DELAY_US, || false);
+    let outcome = exchange(instance, opcode, dummy, length);
+    timeout::wait_us(CS_DELAY_US, || false);
+    cs.set(true);
+    outcome
+}
+
+/// Clocks out the opcode and `dummy` bytes, then shifts in `length` response bytes.
+fn exchange(instance: &Instance, opcode: u8, dummy: u8, length: u8) -> Result<u32, ErrorCode> {
+    instance.transfer(opcode)?;
+    for _ in 0..dummy {
+        instance.transfer(0)?;
+    }
+    let mut response = 0;
+    for _ in 0..length {
+        response = response << 8 | instance.transfer(0)? as u32;
+    }
+    Ok(response)
+}
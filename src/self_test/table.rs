@@ -0,0 +1,42 @@
+//! Per-test metadata the library's `SelfTestItem` has no room for, exported for host tooling in
+//! the protocol crate's [`TestInfo`] layout.
+
+use super::{Test, TESTS};
+
+#[allow(unused_imports)] // Only the `self-test-*` families register flagged tests.
+pub use protocol::flags;
+pub use protocol::{group, ProtocolVersion, TestInfo};
+
+const fn table<const N: usize>() -> [TestInfo; N] {
+    let mut out = [TestInfo::TERMINATOR; N];
+    let mut i = 0;
+    while i < TESTS.len() {
+        let test: &Test = &TESTS[i];
+        assert!(
+            i == 0 || TESTS[i - 1].group <= test.group,
+            "TESTS must be sorted by group"
+        );
+        out[i] = TestInfo {
+            id: test.id,
+            flags: test.flags,
+            group: test.group,
+            expected_ms: test.expected_ms,
+        };
+        i += 1;
+    }
+    out
+}
+
+/// One entry per registered test in execution order, terminated by a zero ID.
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+#[link_section = "SelfTestTable"]
+pub static SelfTestTable: [TestInfo; TESTS.len() + 1] = table();
+
+/// Layout version of this table and the self-test mailboxes; hosts reject an unknown major.
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+#[link_section = "SelfTestVersion"]
+pub static SelfTestVersion: ProtocolVersion = ProtocolVersion::CURRENT;
@@ -0,0 +1,291 @@
+//! General-purpose timer tests, enabled by `self-test-timers`.
+//!
+//! TIM1, TIM2, TIM16 and TIM17 share one register layout as far as the tests use it; BDTR and
+//! slave mode only exist on some of them, as [`Instance`] records.
+
+use flash_algorithm::ErrorCode;
+
+use super::gpio::{Pin, Pull};
+use super::params::word;
+use super::snapshot::Snapshot;
+use super::table::{flags, group};
+use super::{check_deadline, rcc, SelfTestResult, Test};
+use crate::error::codes;
+use crate::regs::Reg;
+use crate::timeout;
+
+const CR1: usize = 0x00;
+const SMCR: usize = 0x08;
+const SR: usize = 0x10;
+const EGR: usize = 0x14;
+const CCMR1: usize = 0x18;
+const CCMR2: usize = 0x1C;
+const CCER: usize = 0x20;
+const PSC: usize = 0x28;
+const ARR: usize = 0x2C;
+const CCR1: usize = 0x34;
+const CCR2: usize = 0x38;
+const CCR3: usize = 0x3C;
+const CCR4: usize = 0x40;
+const BDTR: usize = 0x44;
+/// The registers the tests reconfigure, in the order they are put back: CR1 holds CEN, so last.
+const SAVED_REGS: [usize; 12] = [
+    PSC, ARR, CCR1, CCR2, CCR3, CCR4, CCMR1, CCMR2, CCER, SMCR, BDTR, CR1,
+];
+
+const CR1_CEN: u32 = 1 << 0;
+const CR1_ARPE: u32 = 1 << 7;
+const EGR_UG: u32 = 1 << 0;
+const SR_CC1IF: u32 = 1 << 1;
+const SR_CC2IF: u32 = 1 << 2;
+/// PWM mode 1 with preload, in the low byte of a CCMR for odd channels, the high for even.
+const CCMR_OC_PWM1: u32 = 0b110 << 4 | 1 << 3;
+/// IC1 on TI1, IC2 on TI1 too: PWM input mode.
+const CCMR1_PWM_INPUT: u32 = 0b01 | 0b10 << 8;
+const CCER_CC1E: u32 = 1 << 0;
+const CCER_CC2E: u32 = 1 << 4;
+const CCER_CC2P: u32 = 1 << 5;
+/// Trigger TI1FP1, slave mode reset: every rising edge restarts the count.
+const SMCR_RESET_ON_TI1: u32 = 0b101 << 4 | 0b100;
+const BDTR_MOE: u32 = 1 << 15;
+const COUNTER_MAX: u32 = 0xFFFF;
+
+struct Instance {
+    name: &'static str,
+    base: usize,
+    enable: Reg,
+    enable_mask: u32,
+    /// On PCLK2 rather than PCLK1.
+    apb2: bool,
+    channels: u8,
+    /// Outputs gated by BDTR.MOE.
+    advanced: bool,
+    /// Has the slave mode controller PWM input needs.
+    slave: bool,
+}
+
+/// By the index the tests' parameters name them with.
+const INSTANCES: [Instance; 4] = [
+    Instance {
+        name: "TIM1",
+        base: 0x4001_2C00,
+        enable: rcc::APB2ENR,
+        enable_mask: 1 << 11,
+        apb2: true,
+        channels: 4,
+        advanced: true,
+        slave: true,
+    },
+    Instance {
+        name: "TIM2",
+        base: 0x4000_0000,
+        enable: rcc::APB1ENR1,
+        enable_mask: 1 << 0,
+        apb2: false,
+        channels: 4,
+        advanced: false,
+        slave: true,
+    },
+    Instance {
+        name: "TIM16",
+        base: 0x4001_4400,
+        enable: rcc::APB2ENR,
+        enable_mask: 1 << 17,
+        apb2: true,
+        channels: 1,
+        advanced: true,
+        slave: false,
+    },
+    Instance {
+        name: "TIM17",
+        base: 0x4001_4800,
+        enable: rcc::APB2ENR,
+        enable_mask: 1 << 18,
+        apb2: true,
+        channels: 1,
+        advanced: true,
+        slave: false,
+    },
+];
+
+impl Instance {
+    fn reg(&self, offset: usize) -> Reg {
+        Reg::at(self.base, offset)
+    }
+
+    /// The timer kernel clock: PCLK, doubled whenever the APB prescaler divides at all.
+    fn clock_hz(&self) -> u32 {
+        let pclk = if self.apb2 {
+            rcc::pclk2_hz()
+        } else {
+            rcc::pclk1_hz()
+        };
+        if pclk == timeout::clock_hz() {
+            pclk
+        } else {
+            pclk * 2
+        }
+    }
+
+    fn save(&self) -> Snapshot<{ SAVED_REGS.len() }> {
+        Snapshot::peripheral(SAVED_REGS.map(|offset| self.reg(offset)))
+    }
+}
+
+/// The prescaler and reload that divide `clock_hz` down to `hz` with the most resolution.
+fn divide(clock_hz: u32, hz: u32) -> (u32, u32) {
+    let total = clock_hz / hz;
+    let psc = (total - 1) / (COUNTER_MAX + 1);
+    (psc, total / (psc + 1) - 1)
+}
+
+pub const PWM: Test = Test {
+    id: 0x0551,
+    flags: flags::REQUIRES_FIXTURE | flags::REQUIRES_PARAMS,
+    group: group::PERIPHERALS,
+    expected_ms: 50,
+    run: pwm,
+};
+
+const MIN_HZ: u32 = 100;
+/// Counts per period needed for the duty cycle to resolve to 0.1 %.
+const MIN_COUNTS: u32 = 1_000;
+
+/// Generates PWM on one timer channel and measures its frequency and duty cycle with a second
+/// timer in PWM input mode, over the fixture's jumper between their pins.
+///
+/// Parameters: `[output, input, hz, duty, tolerance]`. `output` holds the generating timer (0
+/// TIM1, 1 TIM2, 2 TIM16, 3 TIM17), its channel (1 to 4, only 1 on TIM16 and TIM17), the pin
+/// and its alternate function in bytes 0 to 3; `input` the same for the measuring timer, which
+/// must be TIM1 or TIM2 and uses channel 1, so byte 1 is ignored. `hz` is at least 100 (default
+/// 1000) and `duty` in tenths of a percent, 1 to 999 (default 500). `tolerance` (default 10)
+/// bounds both the frequency error, in tenths of a percent, and the duty cycle error, in tenths
+/// of a percentage point. Values: frequency generated and measured in Hz, then duty cycle
+/// generated and measured. Both timers and pins are restored after.
+fn pwm(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let [out_timer, channel, out_pin, out_af] = word(params, 0)
+        .ok_or(codes::INVALID_ARGUMENT)?
+        .to_le_bytes();
+    let [in_timer, _, in_pin, in_af] = word(params, 1)
+        .ok_or(codes::INVALID_ARGUMENT)?
+        .to_le_bytes();
+    let hz = word(params, 2).unwrap_or(1_000);
+    let duty = word(params, 3).unwrap_or(500);
+    let tolerance = word(params, 4).unwrap_or(10);
+    let output = INSTANCES
+        .get(out_timer as usize)
+        .filter(|output| (1..=output.channels).contains(&channel))
+        .ok_or(codes::INVALID_ARGUMENT)?;
+    let input = INSTANCES
+        .get(in_timer as usize)
+        .filter(|input| input.slave && in_timer != out_timer)
+        .ok_or(codes::INVALID_ARGUMENT)?;
+    let (Some(out_pin), Some(in_pin)) = (Pin::from_id(out_pin), Pin::from_id(in_pin)) else {
+        return Err(codes::INVALID_ARGUMENT);
+    };
+    if out_pin.id() == in_pin.id() || out_af > 15 || in_af > 15 || !(1..1_000).contains(&duty) {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+    if hz < MIN_HZ || hz > output.clock_hz().min(input.clock_hz()) / MIN_COUNTS {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+
+    let (psc, arr) = divide(output.clock_hz(), hz);
+    let period = arr + 1;
+    let high = period * duty / 1_000;
+    let generated_hz = output.clock_hz() / (psc + 1) / period;
+    let generated_duty = high * 1_000 / period;
+
+    let measured = rcc::with_clock(output.enable, output.enable_mask, || {
+        rcc::with_clock(input.enable, input.enable_mask, || {
+            // Dropped in reverse: both timers are stopped before their pins go back.
+            let _pins = [out_pin.save(), in_pin.save()];
+            let _output = output.save();
+            let _input = input.save();
+
+            generate(output, channel, psc, arr, high);
+            out_pin.alternate(out_af, false, Pull::None);
+            in_pin.alternate(in_af, false, Pull::Down);
+            measure(input, hz)
+        })
+    });
+
+    result.value(generated_hz);
+    let (period_counts, high_counts) = match measured {
+        Ok(counts) => counts,
+        Err(e) => {
+            result.message(format_args!("no PWM edges on {}", input.name));
+            return Err(e);
+        }
+    };
+    let (psc, _) = divide(input.clock_hz(), hz);
+    let measured_hz = input.clock_hz() / (psc + 1) / period_counts.max(1);
+    let measured_duty = high_counts * 1_000 / period_counts.max(1);
+    result.value(measured_hz);
+    result.value(generated_duty);
+    result.value(measured_duty);
+    result.message(format_args!(
+        "{} CH{}: {} Hz {}.{} %, measured {} Hz {}.{} %",
+        output.name,
+        channel,
+        generated_hz,
+        generated_duty / 10,
+        generated_duty % 10,
+        measured_hz,
+        measured_duty / 10,
+        measured_duty % 10
+    ));
+    let frequency_error = measured_hz.abs_diff(generated_hz) as u64 * 1_000 / generated_hz as u64;
+    if frequency_error > tolerance as u64 || measured_duty.abs_diff(generated_duty) > tolerance {
+        return Err(codes::TIMER_FAULT);
+    }
+    Ok(())
+}
+
+/// Starts PWM mode 1 on `channel` (1 to 4) with `high` counts out of `arr + 1`.
+fn generate(timer: &Instance, channel: u8, psc: u32, arr: u32, high: u32) {
+    let index = channel as usize - 1;
+    let ccmr = if index < 2 { CCMR1 } else { CCMR2 };
+    timer.reg(CR1).write(0);
+    timer.reg(PSC).write(psc);
+    timer.reg(ARR).write(arr);
+    timer.reg(CCR1 + index * 4).write(high);
+    timer
+        .reg(ccmr)
+        .modify(|v| v & !(0xFF << (index % 2 * 8)) | CCMR_OC_PWM1 << (index % 2 * 8));
+    timer.reg(CCER).write(CCER_CC1E << (index * 4));
+    if timer.advanced {
+        timer.reg(BDTR).write(BDTR_MOE);
+    }
+    timer.reg(EGR).write(EGR_UG);
+    timer.reg(CR1).write(CR1_ARPE | CR1_CEN);
+}
+
+/// Measures one period of the signal on channel 1 in PWM input mode, returning the period and
+/// high time in counts.
+fn measure(timer: &Instance, hz: u32) -> Result<(u32, u32), ErrorCode> {
+    let (psc, _) = divide(timer.clock_hz(), hz);
+    timer.reg(CR1).write(0);
+    timer.reg(PSC).write(psc);
+    timer.reg(ARR).write(COUNTER_MAX);
+    timer.reg(CCMR1).write(CCMR1_PWM_INPUT);
+    timer.reg(CCER).write(CCER_CC1E | CCER_CC2E | CCER_CC2P);
+    timer.reg(SMCR).write(SMCR_RESET_ON_TI1);
+    timer.reg(EGR).write(EGR_UG);
+    timer.reg(CR1).write(CR1_CEN);
+
+    let sr = timer.reg(SR);
+    let period_us = 1_000_000 / hz;
+    // The first rising edge only restarts the count; a whole period follows the second.
+    for _ in 0..2 {
+        sr.write(0);
+        if !timeout::wait_us(period_us * 3, || sr.read() & SR_CC1IF != 0) {
+            return Err(codes::TIMER_FAULT);
+        }
+        check_deadline()?;
+    }
+    if sr.read() & SR_CC2IF == 0 {
+        return Err(codes::TIMER_FAULT);
+    }
+    Ok((timer.reg(CCR1).read(), timer.reg(CCR2).read()))
+}
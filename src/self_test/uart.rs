@@ -0,0 +1,281 @@
+//! U(S)ART loopback test, part of `self-test-bus`.
+//!
+//! USART1, USART2 and LPUART1 share one register layout; they differ in where their clock
+//! enable and kernel clock selection live, and in how LPUART1 scales its baud rate register.
+
+use flash_algorithm::ErrorCode;
+
+use super::gpio::{Pin, Pull};
+use super::params::word;
+use super::snapshot::Snapshot;
+use super::table::{flags, group};
+use super::{check_deadline, rcc, SelfTestResult, Test};
+use crate::error::codes;
+use crate::regs::Reg;
+use crate::timeout;
+
+const CR1: usize = 0x00;
+const CR2: usize = 0x04;
+const CR3: usize = 0x08;
+const BRR: usize = 0x0C;
+const ISR: usize = 0x1C;
+const ICR: usize = 0x20;
+const RDR: usize = 0x24;
+const TDR: usize = 0x28;
+const PRESC: usize = 0x2C;
+/// The registers the test reconfigures, in the order they are put back: CR1 holds UE, so last.
+const SAVED_REGS: [usize; 5] = [PRESC, BRR, CR2, CR3, CR1];
+
+const CR1_UE: u32 = 1 << 0;
+const CR1_RE: u32 = 1 << 2;
+const CR1_TE: u32 = 1 << 3;
+/// Single-wire half-duplex: the receiver listens to the TX pin.
+const CR3_HDSEL: u32 = 1 << 3;
+const ISR_FE: u32 = 1 << 1;
+const ISR_NE: u32 = 1 << 2;
+const ISR_ORE: u32 = 1 << 3;
+const ISR_RXNE: u32 = 1 << 5;
+const ISR_TXE: u32 = 1 << 7;
+const ISR_TEACK: u32 = 1 << 21;
+const ISR_REACK: u32 = 1 << 22;
+/// PECF, FECF, NECF and ORECF.
+const ICR_ERRORS: u32 = 0b1111;
+
+/// Kernel clock dividers selectable in PRESC, by register value.
+const PRESCALERS: [u32; 12] = [1, 2, 4, 6, 8, 10, 12, 16, 32, 64, 128, 256];
+
+struct Instance {
+    name: &'static str,
+    base: usize,
+    enable: Reg,
+    enable_mask: u32,
+    /// Position of the two-bit kernel clock field in RCC_CCIPR; 0 selects PCLK.
+    ccipr_shift: u32,
+    af: u8,
+    /// On PCLK2 rather than PCLK1.
+    apb2: bool,
+    /// LPUART1's BRR holds 256 × f_ck / baud, and must be at least 0x300.
+    low_power: bool,
+}
+
+/// By the index the test's parameters name them with.
+const INSTANCES: [Instance; 3] = [
+    Instance {
+        name: "USART1",
+        base: 0x4001_3800,
+        enable: rcc::APB2ENR,
+        enable_mask: 1 << 14,
+        ccipr_shift: 0,
+        af: 7,
+        apb2: true,
+        low_power: false,
+    },
+    Instance {
+        name: "USART2",
+        base: 0x4000_4400,
+        enable: rcc::APB1ENR1,
+        enable_mask: 1 << 17,
+        ccipr_shift: 2,
+        af: 7,
+        apb2: false,
+        low_power: false,
+    },
+    Instance {
+        name: "LPUART1",
+        base: 0x4000_8000,
+        enable: rcc::APB1ENR2,
+        enable_mask: 1 << 0,
+        ccipr_shift: 10,
+        af: 8,
+        apb2: false,
+        low_power: true,
+    },
+];
+
+impl Instance {
+    fn reg(&self, offset: usize) -> Reg {
+        Reg::at(self.base, offset)
+    }
+
+    /// The PRESC and BRR values for `baud`, with the smallest prescaler that fits BRR, or `None`
+    /// if the kernel clock can't reach it.
+    fn divisors(&self, kernel_hz: u32, baud: u32) -> Option<(u32, u32)> {
+        let (scale, range) = if self.low_power {
+            (256, 0x300..=0xF_FFFF)
+        } else {
+            (1, 16..=0xFFFF)
+        };
+        PRESCALERS.iter().enumerate().find_map(|(presc, &div)| {
+            let brr = ((kernel_hz / div) as u64 * scale + baud as u64 / 2) / baud as u64;
+            range.contains(&brr).then_some((presc as u32, brr as u32))
+        })
+    }
+
+    /// The baud rate `presc` and `brr` actually give.
+    fn baud(&self, kernel_hz: u32, presc: u32, brr: u32) -> u32 {
+        let scale = if self.low_power { 256 } else { 1 };
+        ((kernel_hz / PRESCALERS[presc as usize]) as u64 * scale / brr as u64) as u32
+    }
+}
+
+pub const UART_LOOPBACK: Test = Test {
+    id: 0x0541,
+    flags: flags::REQUIRES_FIXTURE | flags::REQUIRES_PARAMS,
+    group: group::PERIPHERALS,
+    expected_ms: 50,
+    run: uart_loopback,
+};
+
+const OPTION_INTERNAL: u32 = 1 << 0;
+const MIN_BAUD: u32 = 9_600;
+const PATTERN_LEN: u32 = 32;
+
+/// Byte `index` of the pattern sent: 0x55 first, then every bit changing from byte to byte.
+fn pattern(index: u32) -> u8 {
+    (index as u8).wrapping_mul(0x4B) ^ 0x55
+}
+
+/// Per-byte outcomes of the loopback.
+#[derive(Default)]
+struct Stats {
+    intact: u32,
+    framing: u32,
+    overruns: u32,
+    noise: u32,
+}
+
+/// Sends a pattern through a U(S)ART and checks it comes back unchanged, either over a jumper
+/// from TX to RX or, with the internal option, in single-wire half-duplex mode, where the
+/// receiver listens to the TX pin itself.
+///
+/// Parameters: `[instance, baud, pins, options]`; instance 0 is USART1, 1 USART2 and 2 LPUART1,
+/// `baud` is at least 9600 (default 115200), `pins` holds the TX and RX [`Pin`] IDs in bytes 0
+/// and 1, and option bit 0 selects the internal loopback, which leaves RX alone. The instance
+/// runs from PCLK for the test. Framing errors and overruns fail with their own codes, ahead of
+/// any other corrupted or missing byte. Values: achieved baud rate, bytes sent, bytes received
+/// intact, framing errors, overruns, noise errors. The instance and pins are restored after.
+fn uart_loopback(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let instance = word(params, 0)
+        .and_then(|index| INSTANCES.get(index as usize))
+        .ok_or(codes::INVALID_ARGUMENT)?;
+    let baud = word(params, 1).unwrap_or(115_200);
+    let pins = word(params, 2).ok_or(codes::INVALID_ARGUMENT)?;
+    let internal = word(params, 3).unwrap_or(0) & OPTION_INTERNAL != 0;
+    let tx = Pin::from_id(pins as u8).ok_or(codes::INVALID_ARGUMENT)?;
+    let rx = if internal {
+        None
+    } else {
+        let id = (pins >> 8) as u8;
+        let rx = Pin::from_id(id)
+            .filter(|_| id != tx.id())
+            .ok_or(codes::INVALID_ARGUMENT)?;
+        Some(rx)
+    };
+    let kernel_hz = if instance.apb2 {
+        rcc::pclk2_hz()
+    } else {
+        rcc::pclk1_hz()
+    };
+    if baud < MIN_BAUD {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+    let (presc, brr) = instance
+        .divisors(kernel_hz, baud)
+        .ok_or(codes::INVALID_ARGUMENT)?;
+
+    let mut stats = Stats::default();
+    let outcome = rcc::with_clock(instance.enable, instance.enable_mask, || {
+        // Dropped in reverse: the U(S)ART first, then its pins, then its clock selection.
+        let _ccipr = Snapshot::registers([rcc::CCIPR]);
+        let _tx = tx.save();
+        let _rx = rx.map(Pin::save);
+        let _uart = Snapshot::peripheral(SAVED_REGS.map(|offset| instance.reg(offset)));
+
+        rcc::CCIPR.clear_bits(0b11 << instance.ccipr_shift);
+        // The pull-up holds the line idle whenever nothing drives it, such as between frames in
+        // half-duplex mode.
+        tx.alternate(instance.af, false, Pull::Up);
+        if let Some(rx) = rx {
+            rx.alternate(instance.af, false, Pull::Up);
+        }
+        exchange(instance, presc, brr, internal, baud, &mut stats)
+    });
+
+    let achieved = instance.baud(kernel_hz, presc, brr);
+    result.value(achieved);
+    result.value(PATTERN_LEN);
+    result.value(stats.intact);
+    result.value(stats.framing);
+    result.value(stats.overruns);
+    result.value(stats.noise);
+    result.message(format_args!(
+        "{} at {} baud: {} of {} bytes intact",
+        instance.name, achieved, stats.intact, PATTERN_LEN
+    ));
+    outcome?;
+    if stats.framing != 0 {
+        return Err(codes::UART_FRAMING);
+    }
+    if stats.overruns != 0 {
+        return Err(codes::UART_OVERRUN);
+    }
+    if stats.intact != PATTERN_LEN {
+        return Err(codes::DATA_MISMATCH);
+    }
+    Ok(())
+}
+
+/// Configures the instance and sends the pattern a byte at a time, waiting for each to come back.
+fn exchange(
+    instance: &Instance,
+    presc: u32,
+    brr: u32,
+    internal: bool,
+    baud: u32,
+    stats: &mut Stats,
+) -> Result<(), ErrorCode> {
+    let isr = instance.reg(ISR);
+    instance.reg(CR1).write(0);
+    instance.reg(PRESC).write(presc);
+    instance.reg(BRR).write(brr);
+    instance.reg(CR2).write(0);
+    instance
+        .reg(CR3)
+        .write(if internal { CR3_HDSEL } else { 0 });
+    instance.reg(CR1).write(CR1_UE | CR1_TE | CR1_RE);
+    let acks = ISR_TEACK | ISR_REACK;
+    wait(|| isr.read() & acks == acks, 1_000)?;
+    // Whatever the line picked up while the pins were switched over.
+    let _ = instance.reg(RDR).read();
+    instance.reg(ICR).write(ICR_ERRORS);
+
+    // Two frames' time, so a byte that is merely late still arrives before the timeout.
+    let byte_us = 20 * 1_000_000 / baud + 100;
+    for index in 0..PATTERN_LEN {
+        let sent = pattern(index);
+        wait(|| isr.read() & ISR_TXE != 0, byte_us)?;
+        instance.reg(TDR).write(sent as u32);
+        // A corrupted byte still arrives, with its error flags set; only a lost one times out.
+        wait(|| isr.read() & ISR_RXNE != 0, byte_us)?;
+        let flags = isr.read();
+        let received = instance.reg(RDR).read() as u8;
+        instance.reg(ICR).write(ICR_ERRORS);
+
+        stats.framing += (flags & ISR_FE != 0) as u32;
+        stats.overruns += (flags & ISR_ORE != 0) as u32;
+        stats.noise += (flags & ISR_NE != 0) as u32;
+        if flags & (ISR_FE | ISR_ORE | ISR_NE) == 0 && received == sent {
+            stats.intact += 1;
+        }
+        check_deadline()?;
+    }
+    Ok(())
+}
+
+fn wait(done: impl FnMut() -> bool, timeout_us: u32) -> Result<(), ErrorCode> {
+    if timeout::wait_us(timeout_us, done) {
+        Ok(())
+    } else {
+        Err(codes::BUS_TIMEOUT)
+    }
+}
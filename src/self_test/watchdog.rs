@@ -0,0 +1,238 @@
+//! Watchdog tests, enabled by `self-test-watchdog` (RM0461, chapters 32 and 33).
+//!
+//! Neither watchdog can be stopped once started, short of a reset. So a test leaves its watchdog
+//! at the slowest timeout and freezes it while the core is halted. That is about 32 s for the
+//! IWDG but under a second of running core for the WWDG, which won't outlast a flashing
+//! session. Both are flagged [`DESTRUCTIVE`](super::table::flags::DESTRUCTIVE), so `SelfTestAll`
+//! leaves them out: run them alone and last with `SelfTest`, then reset the board.
+
+use flash_algorithm::ErrorCode;
+
+use super::params::word;
+use super::table::{flags, group};
+use super::{check_deadline, rcc, SelfTestResult, Test};
+use crate::error::codes;
+use crate::regs::Reg;
+use crate::timeout::{self, CycleCounter, Deadline, Timeout};
+
+/// DBGMCU_APB1FZR1: stops the watchdog counters while the debugger holds the core halted.
+const DBGMCU_APB1FZR1: Reg = Reg::at(0xE004_2000, 0x3C);
+const APB1FZR1_DBG_WWDG_STOP: u32 = 1 << 11;
+const APB1FZR1_DBG_IWDG_STOP: u32 = 1 << 12;
+
+const IWDG: usize = 0x4000_3000;
+const IWDG_KR: Reg = Reg::at(IWDG, 0x00);
+const IWDG_PR: Reg = Reg::at(IWDG, 0x04);
+const IWDG_RLR: Reg = Reg::at(IWDG, 0x08);
+const IWDG_SR: Reg = Reg::at(IWDG, 0x0C);
+const KR_START: u32 = 0xCCCC;
+const KR_UNLOCK: u32 = 0x5555;
+const KR_REFRESH: u32 = 0xAAAA;
+/// LSI / 4, 8 counts per millisecond.
+const PR_DIV4: u32 = 0;
+/// LSI / 256, the slowest: 0xFFF counts take about 32 s.
+const PR_DIV256: u32 = 6;
+const RLR_MAX: u32 = 0xFFF;
+
+const CSR_RMVF: u32 = 1 << 23;
+const CSR_IWDGRSTF: u32 = 1 << 29;
+
+/// TAMP_BKP19R, the last backup register, so application data in the low ones is left alone.
+const MARKER: Reg = Reg::at(0x4000_B000, 0x14C);
+/// "IWDG", written just before letting the watchdog expire.
+const MARKER_IWDG: u32 = 0x4957_4447;
+const APB1ENR1_RTCAPBEN: u32 = 1 << 10;
+
+pub const IWDG_TEST: Test = Test {
+    id: 0x0501,
+    flags: flags::DESTRUCTIVE,
+    group: group::PERIPHERALS,
+    expected_ms: 200,
+    run: iwdg,
+};
+
+const OPTION_EXPIRE: u32 = 1 << 0;
+
+/// Starts the IWDG with a short timeout and keeps refreshing it for a while, which must not
+/// reset the board.
+///
+/// Parameters: `[options, timeout_ms, hold_ms]`; `timeout_ms` is 1 to 500 (default 10) and
+/// `hold_ms` how long to keep refreshing it (default 100). With option bit 0 the test then
+/// stops refreshing, after writing a marker to TAMP_BKP19R, so the watchdog resets the board
+/// and the call never returns. Running the test again after that reset checks the marker
+/// against IWDGRSTF and reports the outcome. Values: timeout in ms, hold time in ms.
+fn iwdg(params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    let options = word(params, 0).unwrap_or(0);
+    let timeout_ms = word(params, 1).unwrap_or(10);
+    let hold_ms = word(params, 2).unwrap_or(100);
+    if !(1..=500).contains(&timeout_ms) {
+        return Err(codes::INVALID_ARGUMENT);
+    }
+
+    if let Some(reset) = take_marker() {
+        rcc::CSR.set_bits(CSR_RMVF);
+        result.message(format_args!("IWDG reset after expiry: {}", reset));
+        return if reset {
+            Ok(())
+        } else {
+            Err(codes::WATCHDOG_FAULT)
+        };
+    }
+
+    DBGMCU_APB1FZR1.set_bits(APB1FZR1_DBG_IWDG_STOP);
+    configure(PR_DIV4, timeout_ms * 8 - 1)?;
+    result.value(timeout_ms);
+    result.value(hold_ms);
+
+    // Refreshing at a quarter of the timeout leaves plenty of margin for LSI running fast.
+    let period_us = timeout_ms * 1_000 / 4;
+    let mut hold = Deadline::start_us(hold_ms.saturating_mul(1_000));
+    while !hold.expired() {
+        IWDG_KR.write(KR_REFRESH);
+        let mut gap = Deadline::start_us(period_us);
+        while !gap.expired() {
+            check_deadline()?;
+        }
+    }
+
+    if options & OPTION_EXPIRE != 0 {
+        rcc::with_backup_access(|| {
+            rcc::with_clock(rcc::APB1ENR1, APB1ENR1_RTCAPBEN, || {
+                MARKER.write(MARKER_IWDG)
+            })
+        });
+        // Four timeouts to bite, without polling the test deadline: the marker must not be
+        // left behind by a run that returns.
+        let mut bite = Deadline::start_us(timeout_ms * 4_000);
+        while !bite.expired() {}
+        take_marker();
+        result.message(format_args!("no reset {} ms after expiry", timeout_ms * 4));
+        return Err(codes::WATCHDOG_FAULT);
+    }
+
+    configure(PR_DIV256, RLR_MAX)?;
+    result.message(format_args!(
+        "held {} ms at {} ms timeout, left at 32 s",
+        hold_ms, timeout_ms
+    ));
+    Ok(())
+}
+
+/// Starts the IWDG if it isn't already and loads a new prescaler and reload value.
+fn configure(prescaler: u32, reload: u32) -> Result<(), ErrorCode> {
+    IWDG_KR.write(KR_START);
+    IWDG_KR.write(KR_UNLOCK);
+    IWDG_PR.write(prescaler);
+    IWDG_RLR.write(reload);
+    // PVU/RVU clear once the values have crossed into the LSI domain, a few LSI cycles later.
+    if !timeout::wait_us(1_000, || IWDG_SR.read() & 0b11 == 0) {
+        return Err(codes::WATCHDOG_FAULT);
+    }
+    IWDG_KR.write(KR_REFRESH);
+    Ok(())
+}
+
+/// Clears the marker left by an expiry run, returning whether there was one and, if so, whether
+/// the last reset came from the IWDG.
+fn take_marker() -> Option<bool> {
+    rcc::with_backup_access(|| {
+        rcc::with_clock(rcc::APB1ENR1, APB1ENR1_RTCAPBEN, || {
+            if MARKER.read() != MARKER_IWDG {
+                return None;
+            }
+            MARKER.write(0);
+            Some(rcc::CSR.read() & CSR_IWDGRSTF != 0)
+        })
+    })
+}
+
+const WWDG: usize = 0x4000_2C00;
+const WWDG_CR: Reg = Reg::at(WWDG, 0x00);
+const WWDG_CFR: Reg = Reg::at(WWDG, 0x04);
+const WWDG_SR: Reg = Reg::at(WWDG, 0x08);
+const CR_WDGA: u32 = 1 << 7;
+/// The counter's top value; it resets the board on dropping below 0x40.
+const COUNTER_MAX: u32 = 0x7F;
+/// Counts from [`COUNTER_MAX`] down to 0x40, where the early wakeup interrupt fires.
+const COUNTS_TO_EWI: u32 = COUNTER_MAX - 0x40;
+/// An open window: refreshes are allowed at any counter value.
+const CFR_W_OPEN: u32 = 0x7F;
+const CFR_EWI: u32 = 1 << 9;
+const CFR_WDGTB_SHIFT: u32 = 11;
+const WDGTB_MAX: u32 = 7;
+const SR_EWIF: u32 = 1 << 0;
+const APB1ENR1_WWDGEN: u32 = 1 << 11;
+const WWDG_IRQ: u32 = 0;
+const NVIC_ISPR0: Reg = Reg::at(0xE000_E200, 0);
+const NVIC_ICPR0: Reg = Reg::at(0xE000_E280, 0);
+
+pub const WWDG_TEST: Test = Test {
+    id: 0x0502,
+    flags: flags::DESTRUCTIVE,
+    group: group::PERIPHERALS,
+    expected_ms: 100,
+    run: wwdg,
+};
+
+/// Microseconds the WWDG takes from [`COUNTER_MAX`] to the early wakeup at prescaler `wdgtb`.
+fn ewi_us(pclk1_hz: u32, wdgtb: u32) -> u32 {
+    (COUNTS_TO_EWI as u64 * (4096 << wdgtb) * 1_000_000 / pclk1_hz as u64) as u32
+}
+
+/// Starts the WWDG, waits for its early wakeup interrupt and refreshes it before it can reset
+/// the board; it still does so once the core has run for about 0.7 s more at 48 MHz.
+///
+/// The prescaler is the smallest giving at least 20 ms to the early wakeup, so the timing is
+/// measured to a few percent. The interrupt is only checked pending in the NVIC, never
+/// enabled; EWI itself can't be cleared again before a reset. Values: WDGTB, expected and
+/// measured time to the early wakeup in us.
+fn wwdg(_params: &[u8], result: &mut SelfTestResult) -> Result<(), ErrorCode> {
+    // WWDGEN, like WDGA, is only ever cleared by a reset.
+    rcc::APB1ENR1.set_bits(APB1ENR1_WWDGEN);
+    let _ = rcc::APB1ENR1.read();
+    DBGMCU_APB1FZR1.set_bits(APB1FZR1_DBG_WWDG_STOP);
+
+    // The WWDG counts at PCLK1 after a fixed / 4096.
+    let pclk1 = rcc::pclk1_hz();
+    let wdgtb = (0..WDGTB_MAX)
+        .find(|&wdgtb| ewi_us(pclk1, wdgtb) >= 20_000)
+        .unwrap_or(WDGTB_MAX);
+    let expected_us = ewi_us(pclk1, wdgtb);
+    let irq = 1 << WWDG_IRQ;
+    result.value(wdgtb);
+    result.value(expected_us);
+
+    WWDG_CFR.write(CFR_W_OPEN | CFR_EWI | wdgtb << CFR_WDGTB_SHIFT);
+    WWDG_SR.write(0);
+    NVIC_ICPR0.write(irq);
+    CycleCounter::enable();
+    let begin = CycleCounter::now();
+    WWDG_CR.write(CR_WDGA | COUNTER_MAX);
+    // Watching the counter too means a missing EWIF still gets the refresh in a tick before
+    // the reset.
+    timeout::wait_us(expected_us * 2, || {
+        WWDG_SR.read() & SR_EWIF != 0 || WWDG_CR.read() & COUNTER_MAX <= 0x40
+    });
+    let measured_us = timeout::us_for_cycles(CycleCounter::now().wrapping_sub(begin));
+    // Refresh first, then again at the slowest prescaler, to leave the host as long as the WWDG
+    // allows before it resets the board.
+    WWDG_CR.write(CR_WDGA | COUNTER_MAX);
+    WWDG_CFR.write(CFR_W_OPEN | CFR_EWI | WDGTB_MAX << CFR_WDGTB_SHIFT);
+    WWDG_CR.write(CR_WDGA | COUNTER_MAX);
+    let fired = WWDG_SR.read() & SR_EWIF != 0;
+    let pending = NVIC_ISPR0.read() & irq != 0;
+    WWDG_SR.write(0);
+    NVIC_ICPR0.write(irq);
+
+    result.value(measured_us);
+    result.message(format_args!(
+        "EWI after {} us, expected {} us",
+        measured_us, expected_us
+    ));
+    // One counter tick of slack for the prescaler phase at start, plus 5 %.
+    let tolerance = expected_us / COUNTS_TO_EWI + expected_us / 20;
+    if !fired || !pending || measured_us.abs_diff(expected_us) > tolerance {
+        return Err(codes::WATCHDOG_FAULT);
+    }
+    Ok(())
+}
@@ -0,0 +1,130 @@
+//! Stack painting, canary and high-water-mark reporting, enabled by the `stack-check` feature.
+//!
+//! The host allocates the stack and doesn't tell the algorithm where it ends, so the painted
+//! window is derived from the stack pointer in `Init` and [`STACK_SIZE`], which must not exceed
+//! the stack size the host is configured with. The window is clamped to the RAM window declared
+//! in `algorithm!`, and painting is skipped when the stack pointer is outside it.
+
+#[cfg(feature = "stack-check")]
+pub use painted::*;
+
+#[cfg(feature = "stack-check")]
+mod painted {
+    use core::ops::Range;
+
+    use flash_algorithm::ErrorCode;
+
+    use crate::error::codes;
+    use crate::mailbox::Mailbox;
+
+    /// Stack the host reserves for the algorithm; keep in sync with `cargo xtask ram-budget --stack`.
+    ///
+    /// Assumes the host enters `Init` near the top of a stack at least this large, inside
+    /// [`RAM_WINDOW`] and clear of the image and page buffer. A smaller or differently placed host
+    /// stack gets painted over whatever lies below it in the window.
+    pub const STACK_SIZE: u32 = 0x200;
+    /// `ram_start_addr..ram_end_addr` from `algorithm!`, the most the painted window may cover;
+    /// keep in sync with it.
+    const RAM_WINDOW: Range<u32> = 0x2000_0000..0x2001_0000;
+    /// Upper bound of what `Init` has pushed by the time the stack gets painted.
+    const USED_BEFORE_PAINT: u32 = 0x80;
+    /// Left unpainted below the live stack pointer so `paint` never writes its own frame.
+    const PAINT_MARGIN: u32 = 0x40;
+
+    const PAINT: u32 = 0xCCCC_CCCC;
+    const CANARY: u32 = 0x5AFE_57AC;
+
+    /// Stack window and usage, refreshed on every entry point; all zero until `Init` painted it.
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct StackReport {
+        pub bottom: u32,
+        pub top: u32,
+        /// Deepest usage seen so far, in bytes.
+        pub high_water: u32,
+        /// Non-zero once the canary at `bottom` was found overwritten.
+        pub overflowed: u32,
+    }
+
+    impl StackReport {
+        const NONE: Self = Self {
+            bottom: 0,
+            top: 0,
+            high_water: 0,
+            overflowed: 0,
+        };
+    }
+
+    #[allow(non_upper_case_globals)]
+    #[no_mangle]
+    #[used]
+    pub static StackUsage: Mailbox<StackReport> = Mailbox::new(StackReport::NONE);
+
+    /// Fills the unused part of the stack with a known pattern and plants the canary.
+    ///
+    /// Zeroes `StackUsage`, and so disables [`check`], when the stack pointer is outside
+    /// [`RAM_WINDOW`] or too close to its start to leave anything to paint.
+    pub fn paint() {
+        let sp = cortex_m::register::msp::read();
+        let top = sp.saturating_add(USED_BEFORE_PAINT).min(RAM_WINDOW.end);
+        let bottom = top.saturating_sub(STACK_SIZE).max(RAM_WINDOW.start);
+        let floor = sp.saturating_sub(PAINT_MARGIN);
+        if !RAM_WINDOW.contains(&sp) || bottom + 4 >= floor {
+            StackUsage.write(StackReport::NONE);
+            return;
+        }
+
+        let mut addr = bottom + 4;
+        while addr < floor {
+            unsafe { (addr as *mut u32).write_volatile(PAINT) };
+            addr += 4;
+        }
+        unsafe { (bottom as *mut u32).write_volatile(CANARY) };
+
+        StackUsage.write(StackReport {
+            bottom,
+            top,
+            high_water: 0,
+            overflowed: 0,
+        });
+    }
+
+    /// Updates the high-water mark and fails if the canary was overwritten.
+    pub fn check() -> Result<(), ErrorCode> {
+        let mut report = StackUsage.read();
+        if report.bottom == 0 {
+            return Ok(());
+        }
+
+        let mut lowest = report.bottom + 4;
+        while lowest < report.top && unsafe { (lowest as *const u32).read_volatile() } == PAINT {
+            lowest += 4;
+        }
+        report.high_water = report.high_water.max(report.top - lowest);
+
+        let intact = unsafe { (report.bottom as *const u32).read_volatile() } == CANARY;
+        if !intact {
+            report.overflowed = 1;
+        }
+        StackUsage.write(report);
+
+        if intact {
+            Ok(())
+        } else {
+            Err(codes::STACK_OVERFLOW)
+        }
+    }
+
+    /// Deepest stack usage seen so far, in bytes.
+    pub fn high_water() -> u32 {
+        StackUsage.read().high_water
+    }
+}
+
+#[cfg(not(feature = "stack-check"))]
+pub fn paint() {}
+
+#[cfg(not(feature = "stack-check"))]
+pub fn check() -> Result<(), flash_algorithm::ErrorCode> {
+    Ok(())
+}
@@ -0,0 +1,70 @@
+//! Per-session operation counters, reset by `Init` and summarised on RTT by `UnInit`.
+
+use core::fmt::{self, Write};
+
+use crate::log::{self, Level};
+use crate::mailbox::Mailbox;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct OpCounters {
+    pub pages_programmed: u32,
+    pub sectors_erased: u32,
+    pub chip_erases: u32,
+    /// Operations repeated after a transient failure; the driver doesn't retry yet.
+    pub retries: u32,
+    /// Failures recorded in `ErrorMailbox`.
+    pub errors: u32,
+    /// Longest successful page erase, to size the descriptor's `erase_time_out` from.
+    pub slowest_erase_us: u32,
+    /// Longest successful `ProgramPage`-sized program, for `program_time_out`.
+    pub slowest_program_us: u32,
+}
+
+const ZERO: OpCounters = OpCounters {
+    pages_programmed: 0,
+    sectors_erased: 0,
+    chip_erases: 0,
+    retries: 0,
+    errors: 0,
+    slowest_erase_us: 0,
+    slowest_program_us: 0,
+};
+
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[used]
+pub static OpStats: Mailbox<OpCounters> = Mailbox::new(ZERO);
+
+pub fn reset() {
+    OpStats.write(ZERO);
+}
+
+pub fn count(f: impl FnOnce(&mut OpCounters)) {
+    OpStats.update(f);
+}
+
+/// Writes the session's counters and slowest times to `out` as two lines, for `UnInit`'s log and
+/// the console's `stats` command alike.
+pub fn write_summary(out: &mut impl Write) -> fmt::Result {
+    let c = OpStats.read();
+    writeln!(
+        out,
+        "{} pages programmed, {} sectors erased, {} chip erases, {} retries, {} errors",
+        c.pages_programmed, c.sectors_erased, c.chip_erases, c.retries, c.errors
+    )?;
+    writeln!(
+        out,
+        "slowest page erase {} us, slowest program {} us",
+        c.slowest_erase_us, c.slowest_program_us
+    )
+}
+
+pub fn log_summary() {
+    if log::LEVEL >= Level::Info {
+        let mut out = log::Writer;
+        let _ = out
+            .write_str("Stats: ")
+            .and_then(|()| write_summary(&mut out));
+    }
+}
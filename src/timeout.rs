@@ -0,0 +1,132 @@
+//! Time bases for busy-wait timeouts and short delays.
+//!
+//! SysTick may still belong to the previous firmware, so the default source is the DWT cycle
+//! counter. The `timeout-spin` feature swaps in a spin loop calibrated from the `clock` the host
+//! passes to `Init`, for probes that use DWT themselves.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::regs::Reg;
+
+/// Assumed when the host passes 0; the WLE5 maximum, so timeouts only ever err on the long side.
+const DEFAULT_CLOCK_HZ: u32 = 48_000_000;
+
+/// Zero until `Init` gives a clock, so the static lands in `.bss` rather than `.data`, which the
+/// loader may not initialise.
+static CLOCK_HZ: AtomicU32 = AtomicU32::new(0);
+
+/// Records the core clock passed to `Init`, in Hz.
+pub fn set_clock(hz: u32) {
+    CLOCK_HZ.store(hz, Ordering::Relaxed);
+}
+
+pub fn clock_hz() -> u32 {
+    match CLOCK_HZ.load(Ordering::Relaxed) {
+        0 => DEFAULT_CLOCK_HZ,
+        hz => hz,
+    }
+}
+
+/// Core clock cycles in `us` microseconds, saturating at `u32::MAX`.
+pub fn cycles_for_us(us: u32) -> u32 {
+    (us as u64 * clock_hz() as u64 / 1_000_000).min(u32::MAX as u64) as u32
+}
+
+/// Microseconds in `cycles` core clock cycles, for reporting measured durations.
+pub fn us_for_cycles(cycles: u32) -> u32 {
+    (cycles as u64 * 1_000_000 / clock_hz() as u64) as u32
+}
+
+/// A polling budget that runs out after a fixed amount of time.
+pub trait Timeout {
+    /// Starts a budget of `us` microseconds.
+    fn start_us(us: u32) -> Self;
+
+    /// Whether the budget is used up; call once per polling iteration.
+    fn expired(&mut self) -> bool;
+}
+
+const DEMCR: Reg = Reg::at(0xE000_EDFC, 0);
+const DWT_CTRL: Reg = Reg::at(0xE000_1000, 0x00);
+const DWT_CYCCNT: Reg = Reg::at(0xE000_1000, 0x04);
+
+/// Deadline measured with DWT CYCCNT; good for up to `u32::MAX` cycles (~89 s at 48 MHz).
+///
+/// Also the clock for cycle measurements, so it stays compiled in with `timeout-spin`.
+#[cfg_attr(feature = "timeout-spin", allow(dead_code))]
+pub struct CycleCounter {
+    start: u32,
+    cycles: u32,
+}
+
+#[cfg_attr(feature = "timeout-spin", allow(dead_code))]
+impl CycleCounter {
+    pub fn enable() {
+        DEMCR.set_bits(1 << 24);
+        DWT_CTRL.set_bits(1 << 0);
+    }
+
+    pub fn now() -> u32 {
+        DWT_CYCCNT.read()
+    }
+}
+
+impl Timeout for CycleCounter {
+    fn start_us(us: u32) -> Self {
+        Self::enable();
+        Self {
+            start: Self::now(),
+            cycles: cycles_for_us(us),
+        }
+    }
+
+    fn expired(&mut self) -> bool {
+        Self::now().wrapping_sub(self.start) >= self.cycles
+    }
+}
+
+/// Deadline counted in polling iterations, calibrated from the core clock.
+#[cfg(feature = "timeout-spin")]
+pub struct SpinLoop {
+    remaining: u32,
+}
+
+/// Rough cost of one polling iteration including a volatile register read.
+pub const CYCLES_PER_POLL: u32 = 8;
+
+#[cfg(feature = "timeout-spin")]
+impl Timeout for SpinLoop {
+    fn start_us(us: u32) -> Self {
+        Self {
+            remaining: cycles_for_us(us) / CYCLES_PER_POLL,
+        }
+    }
+
+    fn expired(&mut self) -> bool {
+        match self.remaining.checked_sub(1) {
+            Some(remaining) => {
+                self.remaining = remaining;
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+#[cfg(not(feature = "timeout-spin"))]
+pub type Deadline = CycleCounter;
+#[cfg(feature = "timeout-spin")]
+pub type Deadline = SpinLoop;
+
+/// Polls `done` until it returns true or `us` microseconds pass; returns whether it finished.
+pub fn wait_us(us: u32, mut done: impl FnMut() -> bool) -> bool {
+    let mut deadline = Deadline::start_us(us);
+    loop {
+        if done() {
+            return true;
+        }
+        if deadline.expired() {
+            return false;
+        }
+    }
+}
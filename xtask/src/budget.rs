@@ -0,0 +1,131 @@
+//! `cargo xtask ram-budget`: check the algorithm fits the RAM window declared in `algorithm!`.
+
+use std::fs;
+use std::path::Path;
+
+use crate::elf::{Elf, SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE, SHT_NOBITS};
+use crate::{parse_number, USAGE};
+
+/// Stack probe-rs reserves for a flash algorithm unless told otherwise.
+const DEFAULT_STACK_SIZE: u32 = 0x200;
+
+/// Reads `field: value,` out of the `algorithm!` invocation in `src/main.rs`.
+fn declared(source: &str, field: &str) -> Result<u32, String> {
+    let prefix = format!("{field}:");
+    let value = source
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(prefix.as_str()))
+        .ok_or_else(|| format!("no `{field}` in the algorithm! invocation"))?;
+    parse_number(value.trim().trim_end_matches(','))
+}
+
+#[derive(Default)]
+struct Footprint {
+    code: u32,
+    rodata: u32,
+    data: u32,
+    bss: u32,
+    /// Highest address occupied by the image.
+    end: u32,
+}
+
+fn footprint(elf: &Elf) -> Footprint {
+    let mut fp = Footprint::default();
+    for section in elf.sections().iter().filter(|s| s.flags & SHF_ALLOC != 0) {
+        if section.kind == SHT_NOBITS {
+            fp.bss += section.size;
+        } else if section.flags & SHF_EXECINSTR != 0 {
+            fp.code += section.size;
+        } else if section.flags & SHF_WRITE != 0 {
+            fp.data += section.size;
+        } else {
+            fp.rodata += section.size;
+        }
+        fp.end = fp.end.max(section.addr + section.size);
+    }
+    fp
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let path = args.first().ok_or(USAGE)?;
+    let stack = match args.get(1).map(String::as_str) {
+        Some("--stack") => parse_number(args.get(2).ok_or(USAGE)?)?,
+        Some(_) => return Err(USAGE.into()),
+        None => DEFAULT_STACK_SIZE,
+    };
+
+    let main_rs = Path::new(env!("CARGO_MANIFEST_DIR")).join("../src/main.rs");
+    let source = fs::read_to_string(&main_rs)
+        .map_err(|e| format!("failed to read {}: {e}", main_rs.display()))?;
+    let ram_start = declared(&source, "ram_start_addr")?;
+    let ram_end = declared(&source, "ram_end_addr")?;
+    let page_size = declared(&source, "page_size")?;
+
+    let data = fs::read(path).map_err(|e| format!("failed to read {path}: {e}"))?;
+    let fp = footprint(&Elf::parse(&data)?);
+
+    // Images linked at their RAM address already include the header gap before them,
+    // ones linked at zero are loaded relative to the window start.
+    let image_end = if fp.end >= ram_start {
+        fp.end - ram_start
+    } else {
+        fp.end
+    };
+    let window = ram_end.saturating_sub(ram_start);
+    let total = image_end + page_size + stack;
+
+    println!("RAM window  {ram_start:#010x}..{ram_end:#010x} ({window} bytes)");
+    println!("  code      {:>8}", fp.code);
+    println!("  rodata    {:>8}", fp.rodata);
+    println!("  data      {:>8}", fp.data);
+    println!("  bss       {:>8}", fp.bss);
+    println!("  image end {image_end:>8} (including load offset)");
+    println!("  page buf  {page_size:>8}");
+    println!("  stack     {stack:>8}");
+    println!("  total     {total:>8}");
+
+    if total > window {
+        return Err(format!(
+            "algorithm needs {total} bytes but the RAM window is only {window} bytes"
+        ));
+    }
+    println!("fits with {} bytes to spare", window - total);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::tests::{image, Spec};
+
+    #[test]
+    fn declared_reads_algorithm_fields() {
+        let source =
+            "algorithm!(Algorithm, {\n    ram_start_addr: 0x20000000,\n    page_size: 1024,\n});";
+        assert_eq!(declared(source, "ram_start_addr").unwrap(), 0x2000_0000);
+        assert_eq!(declared(source, "page_size").unwrap(), 1024);
+        assert!(declared(source, "ram_end_addr").is_err());
+    }
+
+    #[test]
+    fn footprint_sorts_allocated_sections() {
+        let section = |name, kind, flags, addr, data| Spec {
+            name,
+            kind,
+            flags,
+            addr,
+            data,
+            ..Spec::default()
+        };
+        let file = image(&[
+            section(".text", 1, SHF_ALLOC | SHF_EXECINSTR, 0x20, &[0; 8]),
+            section(".rodata", 1, SHF_ALLOC, 0x28, &[0; 4]),
+            section(".data", 1, SHF_ALLOC | SHF_WRITE, 0x2c, &[0; 4]),
+            section(".bss", SHT_NOBITS, SHF_ALLOC | SHF_WRITE, 0x30, &[0; 16]),
+            section(".comment", 1, 0, 0, &[0; 64]),
+        ]);
+        let fp = footprint(&Elf::parse(&file).unwrap());
+        assert_eq!((fp.code, fp.rodata, fp.data, fp.bss), (8, 4, 4, 16));
+        assert_eq!(fp.end, 0x40);
+    }
+}
@@ -0,0 +1,331 @@
+//! Just enough of an ELF32 little-endian reader to inspect the flash algorithm image.
+
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_RELA: u32 = 4;
+pub const SHT_NOBITS: u32 = 8;
+pub const SHT_REL: u32 = 9;
+
+pub const SHF_WRITE: u32 = 1 << 0;
+pub const SHF_ALLOC: u32 = 1 << 1;
+pub const SHF_EXECINSTR: u32 = 1 << 2;
+
+pub struct Section<'a> {
+    pub name: &'a str,
+    pub kind: u32,
+    pub flags: u32,
+    pub addr: u32,
+    pub offset: u32,
+    pub size: u32,
+    pub link: u32,
+    pub info: u32,
+}
+
+pub struct Symbol<'a> {
+    pub name: &'a str,
+    pub value: u32,
+    pub size: u32,
+    /// Index of the section the symbol is defined in.
+    pub section: u16,
+}
+
+pub struct Relocation {
+    /// Address (or section offset, in relocatable objects) being patched.
+    pub offset: u32,
+    pub kind: u8,
+    pub symbol: u32,
+}
+
+pub struct Elf<'a> {
+    data: &'a [u8],
+    sections: Vec<Section<'a>>,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, String> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| format!("read past end of file at {offset:#x}"))
+}
+
+pub fn u32_at(data: &[u8], offset: usize) -> Result<u32, String> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| format!("read past end of file at {offset:#x}"))
+}
+
+/// Reads the NUL-terminated string starting at `offset`.
+pub fn str_at(data: &[u8], offset: usize) -> Result<&str, String> {
+    let tail = data
+        .get(offset..)
+        .ok_or_else(|| format!("string offset {offset:#x} out of range"))?;
+    let len = tail.iter().position(|&b| b == 0).unwrap_or(tail.len());
+    std::str::from_utf8(&tail[..len]).map_err(|e| format!("invalid string at {offset:#x}: {e}"))
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, String> {
+        if data.len() < 52 || &data[..4] != b"\x7fELF" {
+            return Err("not an ELF file".into());
+        }
+        if data[4] != 1 || data[5] != 1 {
+            return Err("only 32-bit little-endian ELF files are supported".into());
+        }
+
+        let shoff = u32_at(data, 32)? as usize;
+        let shentsize = u16_at(data, 46)? as usize;
+        let shnum = u16_at(data, 48)? as usize;
+        let shstrndx = u16_at(data, 50)? as usize;
+
+        let header = |index: usize, field: usize| u32_at(data, shoff + index * shentsize + field);
+        let names_offset = header(shstrndx, 16)? as usize;
+
+        let mut sections = Vec::with_capacity(shnum);
+        for index in 0..shnum {
+            sections.push(Section {
+                name: str_at(data, names_offset + header(index, 0)? as usize)?,
+                kind: header(index, 4)?,
+                flags: header(index, 8)?,
+                addr: header(index, 12)?,
+                offset: header(index, 16)?,
+                size: header(index, 20)?,
+                link: header(index, 24)?,
+                info: header(index, 28)?,
+            });
+        }
+
+        Ok(Self { data, sections })
+    }
+
+    pub fn sections(&self) -> &[Section<'a>] {
+        &self.sections
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section<'a>> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    /// File contents of `section`; empty for sections that occupy no file space.
+    pub fn section_data(&self, section: &Section) -> Result<&'a [u8], String> {
+        if section.kind == SHT_NOBITS {
+            return Ok(&[]);
+        }
+        let start = section.offset as usize;
+        self.data
+            .get(start..start + section.size as usize)
+            .ok_or_else(|| format!("section {} extends past end of file", section.name))
+    }
+
+    fn symbol_table(&self) -> Option<&Section<'a>> {
+        self.sections.iter().find(|s| s.kind == SHT_SYMTAB)
+    }
+
+    /// Entries of the symbol table, in table order so relocation indices line up.
+    pub fn symbols(&self) -> Result<Vec<Symbol<'a>>, String> {
+        let Some(symtab) = self.symbol_table() else {
+            return Ok(Vec::new());
+        };
+        let strtab = self
+            .sections
+            .get(symtab.link as usize)
+            .ok_or("symbol table has no string table")?;
+        let strings = self.section_data(strtab)?;
+
+        self.section_data(symtab)?
+            .chunks_exact(16)
+            .map(|entry| {
+                Ok(Symbol {
+                    name: str_at(strings, u32_at(entry, 0)? as usize)?,
+                    value: u32_at(entry, 4)?,
+                    size: u32_at(entry, 8)?,
+                    section: u16_at(entry, 14)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Entries of a `SHT_REL`/`SHT_RELA` section.
+    pub fn relocations(&self, section: &Section) -> Result<Vec<Relocation>, String> {
+        let entry_size = if section.kind == SHT_RELA { 12 } else { 8 };
+        self.section_data(section)?
+            .chunks_exact(entry_size)
+            .map(|entry| {
+                let info = u32_at(entry, 4)?;
+                Ok(Relocation {
+                    offset: u32_at(entry, 0)?,
+                    kind: info as u8,
+                    symbol: info >> 8,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// A section for [`image`] to lay out; `data` is its contents, or just its size for
+    /// `SHT_NOBITS`.
+    #[derive(Default)]
+    pub struct Spec<'a> {
+        pub name: &'a str,
+        pub kind: u32,
+        pub flags: u32,
+        pub addr: u32,
+        pub link: u32,
+        pub data: &'a [u8],
+    }
+
+    /// Builds an ELF32 little-endian file holding `sections` after the null section, then
+    /// `.shstrtab`.
+    pub fn image(sections: &[Spec]) -> Vec<u8> {
+        let mut names = vec![0u8];
+        let mut name_offsets = Vec::new();
+        for spec in sections.iter().map(|s| s.name).chain([".shstrtab"]) {
+            name_offsets.push(names.len() as u32);
+            names.extend_from_slice(spec.as_bytes());
+            names.push(0);
+        }
+
+        let mut file = vec![0u8; 52];
+        // name, type, flags, addr, offset, size, link, info, addralign, entsize
+        let mut headers = vec![[0u32; 10]];
+        for (spec, &name) in sections.iter().zip(&name_offsets) {
+            let offset = file.len() as u32;
+            if spec.kind != SHT_NOBITS {
+                file.extend_from_slice(spec.data);
+            }
+            let size = spec.data.len() as u32;
+            headers.push([
+                name, spec.kind, spec.flags, spec.addr, offset, size, spec.link, 0, 4, 0,
+            ]);
+        }
+        let names_offset = file.len() as u32;
+        file.extend_from_slice(&names);
+        let shstrtab_name = *name_offsets.last().unwrap();
+        let names_size = names.len() as u32;
+        headers.push([shstrtab_name, 3, 0, 0, names_offset, names_size, 0, 0, 1, 0]);
+
+        let shoff = file.len() as u32;
+        for header in &headers {
+            file.extend(header.iter().flat_map(|word| word.to_le_bytes()));
+        }
+        file[..6].copy_from_slice(b"\x7fELF\x01\x01");
+        file[32..36].copy_from_slice(&shoff.to_le_bytes());
+        file[46..48].copy_from_slice(&40u16.to_le_bytes());
+        file[48..50].copy_from_slice(&(headers.len() as u16).to_le_bytes());
+        file[50..52].copy_from_slice(&(headers.len() as u16 - 1).to_le_bytes());
+        file
+    }
+
+    #[test]
+    fn parse_reads_section_headers_and_data() {
+        let file = image(&[
+            Spec {
+                name: ".text",
+                kind: 1,
+                flags: SHF_ALLOC | SHF_EXECINSTR,
+                addr: 0x2000_0020,
+                data: &[1, 2, 3, 4],
+                ..Spec::default()
+            },
+            Spec {
+                name: ".bss",
+                kind: SHT_NOBITS,
+                flags: SHF_ALLOC | SHF_WRITE,
+                data: &[0; 16],
+                ..Spec::default()
+            },
+        ]);
+        let elf = Elf::parse(&file).unwrap();
+        let names: Vec<&str> = elf.sections().iter().map(|s| s.name).collect();
+        assert_eq!(names, ["", ".text", ".bss", ".shstrtab"]);
+
+        let text = elf.section(".text").unwrap();
+        assert_eq!(text.addr, 0x2000_0020);
+        assert_eq!(elf.section_data(text).unwrap(), [1, 2, 3, 4]);
+        let bss = elf.section(".bss").unwrap();
+        assert_eq!(bss.size, 16);
+        assert!(elf.section_data(bss).unwrap().is_empty());
+        assert!(elf.section("SelfTestTable").is_none());
+    }
+
+    #[test]
+    fn parse_rejects_other_formats() {
+        assert!(Elf::parse(b"\x7fELF").is_err());
+        let mut file = image(&[]);
+        file[4] = 2;
+        assert!(Elf::parse(&file).is_err());
+        file[..4].copy_from_slice(b"MZ\0\0");
+        assert!(Elf::parse(&file).is_err());
+    }
+
+    #[test]
+    fn section_data_past_end_of_file_fails() {
+        let file = image(&[Spec {
+            name: "ErrorStrings",
+            kind: 1,
+            data: &[0; 8],
+            ..Spec::default()
+        }]);
+        let mut elf = Elf::parse(&file).unwrap();
+        elf.sections[1].size = file.len() as u32;
+        assert!(elf.section_data(&elf.sections[1]).is_err());
+    }
+
+    #[test]
+    fn symbols_resolve_names_through_the_linked_string_table() {
+        let strings = b"\0Init\0";
+        let mut symtab = vec![0u8; 16];
+        for word in [1u32, 0x2000_0041, 0x10] {
+            symtab.extend_from_slice(&word.to_le_bytes());
+        }
+        symtab.extend_from_slice(&[0x12, 0, 1, 0]);
+        let file = image(&[
+            Spec {
+                name: ".text",
+                kind: 1,
+                ..Spec::default()
+            },
+            Spec {
+                name: ".symtab",
+                kind: SHT_SYMTAB,
+                link: 3,
+                data: &symtab,
+                ..Spec::default()
+            },
+            Spec {
+                name: ".strtab",
+                kind: 3,
+                data: strings,
+                ..Spec::default()
+            },
+        ]);
+        let elf = Elf::parse(&file).unwrap();
+        let symbols = elf.symbols().unwrap();
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols[1].name, "Init");
+        assert_eq!(symbols[1].value, 0x2000_0041);
+        assert_eq!(symbols[1].size, 0x10);
+        assert_eq!(symbols[1].section, 1);
+    }
+
+    #[test]
+    fn relocations_split_info_into_kind_and_symbol() {
+        let mut rel = Vec::new();
+        for word in [0x2000_0100u32, 5 << 8 | 2] {
+            rel.extend_from_slice(&word.to_le_bytes());
+        }
+        let file = image(&[Spec {
+            name: ".rel.text",
+            kind: SHT_REL,
+            data: &rel,
+            ..Spec::default()
+        }]);
+        let elf = Elf::parse(&file).unwrap();
+        let relocations = elf.relocations(elf.section(".rel.text").unwrap()).unwrap();
+        assert_eq!(relocations.len(), 1);
+        assert_eq!(relocations[0].offset, 0x2000_0100);
+        assert_eq!(relocations[0].kind, 2);
+        assert_eq!(relocations[0].symbol, 5);
+    }
+}
@@ -0,0 +1,93 @@
+//! `cargo xtask errors`: decode error codes using the image's own `ErrorStrings` table.
+
+use std::fs;
+
+use crate::elf::{self, Elf};
+use crate::{parse_number, USAGE};
+
+/// Size of one `ErrorString` entry in `src/error.rs`.
+const ERROR_STRING_SIZE: usize = 32;
+
+fn error_strings<'a>(elf: &Elf<'a>) -> Result<Vec<(u32, &'a str)>, String> {
+    let section = elf
+        .section("ErrorStrings")
+        .ok_or("no ErrorStrings section in this image")?;
+    let data = elf.section_data(section)?;
+
+    let mut table = Vec::new();
+    for entry in data.chunks_exact(ERROR_STRING_SIZE) {
+        let code = elf::u32_at(entry, 0)?;
+        if code == 0 {
+            break;
+        }
+        table.push((code, elf::str_at(entry, 4)?));
+    }
+    Ok(table)
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let path = args.first().ok_or(USAGE)?;
+    let data = fs::read(path).map_err(|e| format!("failed to read {path}: {e}"))?;
+    let elf = Elf::parse(&data)?;
+    let table = error_strings(&elf)?;
+
+    match args.get(1) {
+        Some(code) => {
+            let code = parse_number(code)?;
+            match table.iter().find(|(c, _)| *c == code) {
+                Some((_, text)) => println!("{code:#x}: {text}"),
+                None => println!("{code:#x}: unknown error code"),
+            }
+        }
+        None => {
+            for (code, text) in table {
+                println!("{code:#010x}  {text}");
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::tests::{image, Spec};
+
+    fn entry(code: u32, text: &str) -> Vec<u8> {
+        let mut entry = code.to_le_bytes().to_vec();
+        entry.extend_from_slice(text.as_bytes());
+        entry.resize(ERROR_STRING_SIZE, 0);
+        entry
+    }
+
+    #[test]
+    fn error_strings_stop_at_the_zero_code() {
+        let data = [
+            entry(0x0001_0001, "flash: busy timeout"),
+            entry(0x5e1f_0004, "self-test skipped"),
+            entry(0, ""),
+            entry(0x0001_0002, "past the terminator"),
+        ]
+        .concat();
+        let file = image(&[Spec {
+            name: "ErrorStrings",
+            kind: 1,
+            data: &data,
+            ..Spec::default()
+        }]);
+        let table = error_strings(&Elf::parse(&file).unwrap()).unwrap();
+        assert_eq!(
+            table,
+            [
+                (0x0001_0001, "flash: busy timeout"),
+                (0x5e1f_0004, "self-test skipped")
+            ]
+        );
+    }
+
+    #[test]
+    fn error_strings_need_the_section() {
+        let file = image(&[]);
+        assert!(error_strings(&Elf::parse(&file).unwrap()).is_err());
+    }
+}
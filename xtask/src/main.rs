@@ -0,0 +1,64 @@
+//! Host-side helpers for working with the built flash algorithm, run with `cargo xtask`.
+
+mod budget;
+mod elf;
+mod errors;
+mod pic;
+mod seal;
+mod self_tests;
+
+use std::env;
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: cargo xtask <command>
+
+commands:
+    errors <elf> [code]          print the ErrorStrings table, or decode a single error code
+    pi-check <elf>               list GOT/PLT sections, absolute relocations and initialised data
+    ram-budget <elf> [--stack n] check the image, page buffer and stack fit the declared RAM window
+    seal <elf> [--verify]        write CRCs of DeviceData and SelfTestInfo into DescriptorCrc, or
+                                 check the ones already there
+    self-tests <elf> [--group g] list registered self tests, or print the SelfTestSkip words
+                                 that run only group g";
+
+pub fn parse_number(text: &str) -> Result<u32, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|e| format!("invalid number {text:?}: {e}"))
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("errors") => errors::run(&args[1..]),
+        Some("pi-check") => pic::run(&args[1..]),
+        Some("ram-budget") => budget::run(&args[1..]),
+        Some("seal") => seal::run(&args[1..]),
+        Some("self-tests") => self_tests::run(&args[1..]),
+        _ => Err(USAGE.into()),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_number_takes_decimal_and_hex() {
+        assert_eq!(parse_number("1024"), Ok(1024));
+        assert_eq!(parse_number("0x5e1f0004"), Ok(0x5e1f_0004));
+        assert_eq!(parse_number("0XFF"), Ok(0xFF));
+        assert!(parse_number("0x").is_err());
+        assert!(parse_number("12k").is_err());
+    }
+}
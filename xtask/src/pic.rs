@@ -0,0 +1,157 @@
+//! `cargo xtask pi-check`: fail if the image isn't position independent.
+//!
+//! The host loads the algorithm wherever its RAM window starts, so anything that bakes in an
+//! absolute address — GOT/PLT entries, absolute relocations, initialised `.data` — breaks at
+//! runtime with no better symptom than a fault. Relocations are only kept in the final ELF when
+//! linking with `-C link-arg=--emit-relocs`; without them only the section checks run.
+
+use std::fs;
+
+use crate::elf::{Elf, Symbol, SHF_ALLOC, SHF_WRITE, SHT_NOBITS, SHT_REL, SHT_RELA};
+use crate::USAGE;
+
+/// ARM relocation types that resolve to an absolute address or go through the GOT.
+const ABSOLUTE_RELOCATIONS: &[(u8, &str)] = &[
+    (2, "R_ARM_ABS32"),
+    (5, "R_ARM_ABS16"),
+    (6, "R_ARM_ABS12"),
+    (7, "R_ARM_THM_ABS5"),
+    (8, "R_ARM_ABS8"),
+    (26, "R_ARM_GOT_BREL"),
+    (38, "R_ARM_TARGET1"),
+    (43, "R_ARM_MOVW_ABS_NC"),
+    (44, "R_ARM_MOVT_ABS"),
+    (47, "R_ARM_THM_MOVW_ABS_NC"),
+    (48, "R_ARM_THM_MOVT_ABS"),
+    (95, "R_ARM_GOT_ABS"),
+    (96, "R_ARM_GOT_PREL"),
+    (97, "R_ARM_GOT_BREL12"),
+];
+
+const FORBIDDEN_SECTIONS: &[&str] = &[".got", ".got.plt", ".plt", ".dynamic", ".dynsym"];
+
+/// Name of the symbol in `section` covering `address`, for pointing at the offending function.
+fn containing<'a>(symbols: &[Symbol<'a>], section: usize, address: u32) -> &'a str {
+    symbols
+        .iter()
+        .filter(|s| s.section as usize == section && s.size > 0 && !s.name.is_empty())
+        // Thumb function symbols carry the mode bit in their value.
+        .find(|s| (s.value & !1..(s.value & !1) + s.size).contains(&address))
+        .map_or("?", |s| s.name)
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let path = args.first().ok_or(USAGE)?;
+    let data = fs::read(path).map_err(|e| format!("failed to read {path}: {e}"))?;
+    let elf = Elf::parse(&data)?;
+    let symbols = elf.symbols()?;
+    let mut problems = Vec::new();
+
+    for section in elf.sections() {
+        if FORBIDDEN_SECTIONS.contains(&section.name) && section.size > 0 {
+            problems.push(format!(
+                "{} section present ({} bytes)",
+                section.name, section.size
+            ));
+        }
+    }
+
+    let initialised_data = elf.sections().iter().enumerate().filter(|(_, s)| {
+        s.flags & (SHF_ALLOC | SHF_WRITE) == SHF_ALLOC | SHF_WRITE
+            && s.kind != SHT_NOBITS
+            && s.size > 0
+    });
+    for (index, section) in initialised_data {
+        let names: Vec<&str> = symbols
+            .iter()
+            .filter(|s| s.section as usize == index && s.size > 0)
+            .map(|s| s.name)
+            .collect();
+        if names.is_empty() {
+            problems.push(format!(
+                "{}: {} bytes of initialised data",
+                section.name, section.size
+            ));
+        }
+        for name in names {
+            problems.push(format!("{}: initialised data {name}", section.name));
+        }
+    }
+
+    let mut relocations_seen = false;
+    for section in elf
+        .sections()
+        .iter()
+        .filter(|s| s.kind == SHT_REL || s.kind == SHT_RELA)
+    {
+        let target_index = section.info as usize;
+        let target = elf.sections().get(target_index);
+        if target.is_none_or(|t| t.flags & SHF_ALLOC == 0) {
+            continue;
+        }
+        relocations_seen = true;
+        for reloc in elf.relocations(section)? {
+            let Some((_, kind)) = ABSOLUTE_RELOCATIONS.iter().find(|(k, _)| *k == reloc.kind)
+            else {
+                continue;
+            };
+            let referenced = symbols.get(reloc.symbol as usize).map_or("?", |s| {
+                if s.name.is_empty() {
+                    "<section>"
+                } else {
+                    s.name
+                }
+            });
+            problems.push(format!(
+                "{kind} at {:#010x} in {} references {referenced}",
+                reloc.offset,
+                containing(&symbols, target_index, reloc.offset)
+            ));
+        }
+    }
+
+    if !relocations_seen {
+        println!(
+            "note: no relocations in {path}; link with -C link-arg=--emit-relocs to scan them"
+        );
+    }
+
+    if problems.is_empty() {
+        println!("{path} is position independent");
+        return Ok(());
+    }
+    for problem in &problems {
+        eprintln!("  {problem}");
+    }
+    Err(format!(
+        "{} position-dependent item(s) found",
+        problems.len()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn containing_ignores_the_thumb_bit() {
+        let symbols = [
+            Symbol {
+                name: "EraseSector",
+                value: 0x2000_0101,
+                size: 0x20,
+                section: 1,
+            },
+            Symbol {
+                name: "",
+                value: 0x2000_0100,
+                size: 0x100,
+                section: 1,
+            },
+        ];
+        assert_eq!(containing(&symbols, 1, 0x2000_0100), "EraseSector");
+        assert_eq!(containing(&symbols, 1, 0x2000_011f), "EraseSector");
+        assert_eq!(containing(&symbols, 1, 0x2000_0120), "?");
+        assert_eq!(containing(&symbols, 2, 0x2000_0100), "?");
+    }
+}
@@ -0,0 +1,103 @@
+//! `cargo xtask seal`: fill in, or check, the `DescriptorCrc` block over the image's descriptors.
+
+use std::fs;
+
+use crate::elf::{self, Elf};
+use crate::USAGE;
+
+/// `DescriptorCrcDescription::magic` of a sealed image.
+const SEALED: u32 = u32::from_le_bytes(*b"DCRC");
+/// The block's fields: the magic, then one CRC per covered section.
+const FIELDS: [&str; 3] = ["magic", "DeviceData", "SelfTestInfo"];
+
+/// CRC-32/ISO-HDLC, the one zlib computes; its check value over "123456789" is 0xCBF43926.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+/// The block the image should carry: magic, then the CRC of each covered section or 0.
+fn expected(elf: &Elf) -> Result<[u32; 3], String> {
+    let mut block = [SEALED, 0, 0];
+    for (slot, name) in block[1..].iter_mut().zip(&FIELDS[1..]) {
+        if let Some(section) = elf.section(name) {
+            *slot = crc32(elf.section_data(section)?);
+        }
+    }
+    Ok(block)
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let path = args.first().ok_or(USAGE)?;
+    let verify = match args.get(1).map(String::as_str) {
+        Some("--verify") => true,
+        Some(_) => return Err(USAGE.into()),
+        None => false,
+    };
+    let mut data = fs::read(path).map_err(|e| format!("failed to read {path}: {e}"))?;
+
+    let (offset, block) = {
+        let elf = Elf::parse(&data)?;
+        let section = elf
+            .section("DescriptorCrc")
+            .ok_or("no DescriptorCrc section in this image")?;
+        let stored = elf.section_data(section)?;
+        if stored.len() != 12 {
+            return Err(format!(
+                "DescriptorCrc is {} bytes, expected 12",
+                stored.len()
+            ));
+        }
+        let block = expected(&elf)?;
+        if verify {
+            for (i, (name, computed)) in FIELDS.iter().zip(block).enumerate() {
+                let stored = elf::u32_at(stored, i * 4)?;
+                if stored != computed {
+                    return Err(format!(
+                        "{name}: stored {stored:#010x}, computed {computed:#010x}"
+                    ));
+                }
+            }
+            println!("descriptors match DescriptorCrc");
+            return Ok(());
+        }
+        (section.offset as usize, block)
+    };
+
+    for (i, word) in block.iter().enumerate() {
+        data[offset + i * 4..][..4].copy_from_slice(&word.to_le_bytes());
+    }
+    fs::write(path, &data).map_err(|e| format!("failed to write {path}: {e}"))?;
+    println!(
+        "sealed: DeviceData {:#010x}, SelfTestInfo {:#010x}",
+        block[1], block[2]
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::tests::{image, Spec};
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn expected_leaves_missing_sections_zero() {
+        let file = image(&[Spec {
+            name: "DeviceData",
+            kind: 1,
+            data: b"123456789",
+            ..Spec::default()
+        }]);
+        let elf = Elf::parse(&file).unwrap();
+        assert_eq!(expected(&elf).unwrap(), [SEALED, 0xCBF4_3926, 0]);
+    }
+}
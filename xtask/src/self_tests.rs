@@ -0,0 +1,211 @@
+//! `cargo xtask self-tests`: list the image's `SelfTestTable`, or derive the `SelfTestSkip` words
+//! that make `SelfTestAll` run only one group.
+
+use std::fs;
+
+use protocol::{flags, group, ProtocolVersion, TestInfo, TestMask};
+
+use crate::elf::Elf;
+use crate::USAGE;
+
+/// The image's `SelfTestVersion`, refusing layouts this build of the tools can't read.
+fn protocol_version(elf: &Elf) -> Result<ProtocolVersion, String> {
+    let version = match elf.section("SelfTestVersion") {
+        Some(section) => ProtocolVersion::from_bytes(elf.section_data(section)?)
+            .ok_or("SelfTestVersion section is truncated")?,
+        None => ProtocolVersion::UNVERSIONED,
+    };
+    if !version.is_supported() {
+        return Err(format!(
+            "image speaks self-test protocol {}.{} (magic {:#010x}), these tools know {}.x",
+            version.major,
+            version.minor,
+            version.magic,
+            protocol::VERSION_MAJOR
+        ));
+    }
+    Ok(version)
+}
+
+fn self_test_table(elf: &Elf) -> Result<Vec<TestInfo>, String> {
+    protocol_version(elf)?;
+    let section = elf
+        .section("SelfTestTable")
+        .ok_or("no SelfTestTable section in this image, is the self-test feature enabled?")?;
+    Ok(protocol::parse_table(elf.section_data(section)?))
+}
+
+fn describe_flags(flags: u32) -> String {
+    let mut names: Vec<String> = flags::NAMES
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, name)| name.to_string())
+        .collect();
+    let unknown = flags::NAMES
+        .iter()
+        .fold(flags, |rest, (bit, _)| rest & !bit);
+    if unknown != 0 {
+        names.push(format!("{unknown:#x}"));
+    }
+    if names.is_empty() {
+        return "-".into();
+    }
+    names.join(",")
+}
+
+/// The `SelfTestSkip` words skipping every test of `table` outside `group`; bits past the table
+/// stay clear.
+fn skip_outside(table: &[TestInfo], group: u32) -> Result<TestMask, String> {
+    let mut skip = [0; protocol::MASK_WORDS];
+    for (bit, _) in table
+        .iter()
+        .enumerate()
+        .filter(|(_, test)| test.group != group)
+    {
+        if !protocol::mask_set(&mut skip, bit) {
+            return Err(format!("table has more than {} tests", protocol::MAX_TESTS));
+        }
+    }
+    Ok(skip)
+}
+
+fn group_name(group: u32) -> String {
+    match group::NAMES.get(group as usize) {
+        Some(name) => name.to_string(),
+        None => format!("group {group}"),
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let path = args.first().ok_or(USAGE)?;
+    let data = fs::read(path).map_err(|e| format!("failed to read {path}: {e}"))?;
+    let elf = Elf::parse(&data)?;
+    let table = self_test_table(&elf)?;
+
+    match args.get(1).map(String::as_str) {
+        Some("--group") => {
+            let name = args.get(2).ok_or(USAGE)?;
+            let names = group::NAMES;
+            let group = names
+                .iter()
+                .position(|g| g == name)
+                .ok_or_else(|| format!("unknown group {name:?}, expected one of {names:?}"))?;
+            let skip = skip_outside(&table, group as u32)?;
+            let words: Vec<String> = skip.iter().map(|word| format!("{word:#010x}")).collect();
+            println!("{}", words.join(" "));
+        }
+        Some(_) => return Err(USAGE.into()),
+        None => {
+            for (bit, test) in table.iter().enumerate() {
+                println!(
+                    "bit {bit:2}  id {:#010x}  {:12}  {:6} ms  {}",
+                    test.id,
+                    group_name(test.group),
+                    test.expected_ms,
+                    describe_flags(test.flags)
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::tests::{image, Spec};
+
+    fn table_bytes(tests: &[TestInfo]) -> Vec<u8> {
+        tests
+            .iter()
+            .chain([&TestInfo::TERMINATOR])
+            .flat_map(|test| [test.id, test.flags, test.group, test.expected_ms])
+            .flat_map(u32::to_le_bytes)
+            .collect()
+    }
+
+    fn test(id: u32, group: u32) -> TestInfo {
+        TestInfo {
+            id,
+            flags: 0,
+            group,
+            expected_ms: 1,
+        }
+    }
+
+    #[test]
+    fn describes_known_and_unknown_flags() {
+        assert_eq!(describe_flags(0), "-");
+        assert_eq!(
+            describe_flags(flags::DESTRUCTIVE | flags::REQUIRES_PARAMS),
+            "destructive,requires-params"
+        );
+        assert_eq!(
+            describe_flags(flags::REQUIRES_FIXTURE | 1 << 8),
+            "requires-fixture,0x100"
+        );
+        assert_eq!(group_name(group::RADIO), "radio");
+        assert_eq!(group_name(42), "group 42");
+    }
+
+    #[test]
+    fn skip_outside_covers_tests_past_the_first_word() {
+        let table: Vec<TestInfo> = (0..40)
+            .map(|bit| {
+                test(
+                    bit + 1,
+                    if bit == 3 || bit == 35 {
+                        group::RADIO
+                    } else {
+                        group::GENERAL
+                    },
+                )
+            })
+            .collect();
+        let skip = skip_outside(&table, group::RADIO).unwrap();
+        assert_eq!(skip[0], !(1 << 3));
+        assert_eq!(skip[1], 0xFF & !(1 << 3));
+
+        let oversized = vec![test(1, group::GENERAL); protocol::MAX_TESTS + 1];
+        assert!(skip_outside(&oversized, group::RADIO).is_err());
+    }
+
+    #[test]
+    fn table_requires_a_supported_version() {
+        let table = table_bytes(&[test(1, group::GENERAL), test(0x0401, group::RADIO)]);
+        let unversioned = image(&[Spec {
+            name: "SelfTestTable",
+            kind: 1,
+            data: &table,
+            ..Spec::default()
+        }]);
+        let ids: Vec<u32> = self_test_table(&Elf::parse(&unversioned).unwrap())
+            .unwrap()
+            .iter()
+            .map(|test| test.id)
+            .collect();
+        assert_eq!(ids, [1, 0x0401]);
+
+        let newer: Vec<u8> = [ProtocolVersion::MAGIC, protocol::VERSION_MAJOR + 1, 0]
+            .into_iter()
+            .flat_map(u32::to_le_bytes)
+            .collect();
+        for version in [&newer[..], &newer[..8]] {
+            let file = image(&[
+                Spec {
+                    name: "SelfTestVersion",
+                    kind: 1,
+                    data: version,
+                    ..Spec::default()
+                },
+                Spec {
+                    name: "SelfTestTable",
+                    kind: 1,
+                    data: &table,
+                    ..Spec::default()
+                },
+            ]);
+            assert!(self_test_table(&Elf::parse(&file).unwrap()).is_err());
+        }
+    }
+}